@@ -0,0 +1,388 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{config, observer::Container, resources_monitor::Levels};
+
+/// How bad a container being low is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Warning => "WARNING",
+            Severity::Critical => "CRITICAL",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// When to alert about the containers, as percentages of their capacity.
+///
+/// A container is raised to a severity as soon as it goes below its threshold, but is only
+/// lowered once it goes back above the threshold plus the hysteresis; so a level moving
+/// around a threshold doesn't alert over and over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlertPolicy {
+    pub warning: u32,
+    pub critical: u32,
+    pub hysteresis: u32,
+}
+
+impl Default for AlertPolicy {
+    fn default() -> AlertPolicy {
+        AlertPolicy {
+            warning: config::X,
+            critical: config::X / 2,
+            hysteresis: config::X / 5,
+        }
+    }
+}
+
+impl FromStr for AlertPolicy {
+    type Err = String;
+
+    /// Parses a policy like `warning=10,critical=5,hysteresis=2`.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<AlertPolicy, String> {
+        let mut policy = AlertPolicy::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("Invalid alert setting: {}", pair))?;
+            let value: u32 = value
+                .parse()
+                .map_err(|_| format!("Invalid percentage: {}", value))?;
+            if value > 100 {
+                return Err(format!("Invalid percentage: {}", value));
+            }
+            match key {
+                "warning" => policy.warning = value,
+                "critical" => policy.critical = value,
+                "hysteresis" => policy.hysteresis = value,
+                _ => return Err(format!("Unknown alert setting: {}", key)),
+            }
+        }
+        if policy.critical > policy.warning {
+            return Err("Critical level is above the warning level".to_string());
+        }
+        if policy.warning + policy.hysteresis > 100 {
+            return Err("Warning level plus hysteresis is above 100".to_string());
+        }
+        Ok(policy)
+    }
+}
+
+impl AlertPolicy {
+    /// Severity of the given level of a container, with its thresholds raised by the given percentage.
+    /// Thresholds never go above the capacity, so a full container is never low.
+    fn severity(&self, container: Container, level: u32, raised: u32) -> Option<Severity> {
+        let threshold = |percentage: u32| {
+            container.capacity() * percentage.saturating_add(raised).min(100) / 100
+        };
+        if level < threshold(self.critical) {
+            Some(Severity::Critical)
+        } else if level < threshold(self.warning) {
+            Some(Severity::Warning)
+        } else {
+            None
+        }
+    }
+}
+
+/// A container went into or out of a severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alert {
+    pub at: SystemTime,
+    pub container: Container,
+    /// Severity now, `None` if the container is no longer low.
+    pub severity: Option<Severity>,
+    pub previous: Option<Severity>,
+    pub level: u32,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Some(severity) => write!(
+                f,
+                "[{}] {} is low: {}",
+                severity, self.container, self.level
+            ),
+            None => write!(f, "[CLEARED] {} is back to {}", self.container, self.level),
+        }
+    }
+}
+
+/// Keeps track of the severity of each container, to alert once per crossing.
+#[derive(Debug)]
+pub struct AlertTracker {
+    policy: AlertPolicy,
+    severities: [Option<Severity>; 4],
+    pending: Vec<Alert>,
+}
+
+impl AlertTracker {
+    /// Creates a new AlertTracker instance, starting from the given levels without alerting.
+    pub fn new(policy: AlertPolicy, levels: Levels) -> AlertTracker {
+        let severities =
+            Container::ALL.map(|container| policy.severity(container, levels.get(container), 0));
+        AlertTracker {
+            policy,
+            severities,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Updates the level of a container, keeping an alert if its severity changed.
+    pub fn update(&mut self, container: Container, level: u32) {
+        let index = Container::ALL
+            .iter()
+            .position(|other| *other == container)
+            .expect("Every container is tracked");
+        let current = self.severities[index];
+        let worse = self.policy.severity(container, level, 0);
+        let better = self
+            .policy
+            .severity(container, level, self.policy.hysteresis);
+        let severity = if worse > current {
+            worse
+        } else if better < current {
+            better
+        } else {
+            current
+        };
+
+        if severity != current {
+            self.severities[index] = severity;
+            self.pending.push(Alert {
+                at: SystemTime::now(),
+                container,
+                severity,
+                previous: current,
+                level,
+            });
+        }
+    }
+
+    /// Returns the policy the alerts follow.
+    pub fn policy(&self) -> AlertPolicy {
+        self.policy
+    }
+
+    /// Returns the severity of a container.
+    pub fn severity(&self, container: Container) -> Option<Severity> {
+        let index = Container::ALL
+            .iter()
+            .position(|other| *other == container)
+            .expect("Every container is tracked");
+        self.severities[index]
+    }
+
+    /// Takes the alerts since the last time they were taken.
+    pub fn take(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Where alerts are delivered.
+/// Sinks are called from the thread that changed the container, so they should be quick.
+pub trait AlertSink: Send + Sync {
+    fn alert(&self, alert: &Alert);
+}
+
+/// Calls a function with each alert.
+impl<F: Fn(&Alert) + Send + Sync> AlertSink for F {
+    fn alert(&self, alert: &Alert) {
+        self(alert)
+    }
+}
+
+/// Appends the alerts to a file, a line each, starting with a timestamp in milliseconds.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    /// Creates a new FileSink instance appending to the file at the given path.
+    pub fn open(path: &str) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AlertSink for FileSink {
+    fn alert(&self, alert: &Alert) {
+        let timestamp = alert
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+        let mut file = self.file.lock().expect("Failed to lock alert file");
+        // losing an alert is better than stopping the coffee maker
+        let _ = writeln!(file, "{} {}", timestamp, alert);
+    }
+}
+
+/// Alert sinks registered somewhere, delivered in the order they were registered.
+#[derive(Default)]
+pub struct AlertSinks {
+    sinks: RwLock<Vec<Arc<dyn AlertSink>>>,
+}
+
+impl AlertSinks {
+    /// Registers a sink.
+    pub fn register(&self, sink: Arc<dyn AlertSink>) {
+        let mut sinks = self.sinks.write().expect("Failed to lock alert sinks");
+        sinks.push(sink);
+    }
+
+    /// Delivers the alerts to every sink.
+    pub fn deliver(&self, alerts: &[Alert]) {
+        let sinks = self.sinks.read().expect("Failed to lock alert sinks");
+        for alert in alerts {
+            for sink in sinks.iter() {
+                sink.alert(alert);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod alerts_tests {
+    use super::*;
+
+    fn tracker() -> AlertTracker {
+        let full = Levels {
+            coffee: config::C,
+            coffee_beans: config::G,
+            foam: config::E,
+            milk: config::L,
+        };
+        let policy = AlertPolicy {
+            warning: 10,
+            critical: 5,
+            hysteresis: 2,
+        };
+        AlertTracker::new(policy, full)
+    }
+
+    fn severities(alerts: Vec<Alert>) -> Vec<Option<Severity>> {
+        alerts.into_iter().map(|alert| alert.severity).collect()
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy: AlertPolicy = "warning=20,critical=8".parse().unwrap();
+        assert_eq!(policy.warning, 20);
+        assert_eq!(policy.critical, 8);
+        assert_eq!(policy.hysteresis, AlertPolicy::default().hysteresis);
+    }
+
+    #[test]
+    fn parse_invalid_policy() {
+        assert!("warning=5,critical=10".parse::<AlertPolicy>().is_err());
+        assert!("warning=101".parse::<AlertPolicy>().is_err());
+        assert!("warning=90,hysteresis=11".parse::<AlertPolicy>().is_err());
+        assert!("warning=90,hysteresis=10".parse::<AlertPolicy>().is_ok());
+        assert!("loud=1".parse::<AlertPolicy>().is_err());
+        assert!("warning".parse::<AlertPolicy>().is_err());
+    }
+
+    #[test]
+    fn full_containers_are_never_low() {
+        let policy = AlertPolicy {
+            warning: 100,
+            critical: 50,
+            hysteresis: 20,
+        };
+        let mut tracker = AlertTracker::new(policy, Levels::default());
+        tracker.update(Container::Milk, config::L);
+        assert_eq!(tracker.severity(Container::Milk), None);
+    }
+
+    #[test]
+    fn alerts_once_per_crossing() {
+        let mut tracker = tracker();
+        tracker.update(Container::Milk, 999);
+        tracker.update(Container::Milk, 900);
+        tracker.update(Container::Milk, 800);
+        assert_eq!(severities(tracker.take()), vec![Some(Severity::Warning)]);
+
+        tracker.update(Container::Milk, 499);
+        tracker.update(Container::Milk, 0);
+        assert_eq!(severities(tracker.take()), vec![Some(Severity::Critical)]);
+        assert_eq!(tracker.severity(Container::Milk), Some(Severity::Critical));
+        assert_eq!(tracker.severity(Container::Coffee), None);
+    }
+
+    #[test]
+    fn hysteresis_avoids_flapping() {
+        let mut tracker = tracker();
+        tracker.update(Container::CoffeeBeans, 999);
+        // back above the warning level, but not by enough
+        tracker.update(Container::CoffeeBeans, 1000);
+        tracker.update(Container::CoffeeBeans, 1199);
+        tracker.update(Container::CoffeeBeans, 999);
+        assert_eq!(severities(tracker.take()), vec![Some(Severity::Warning)]);
+
+        tracker.update(Container::CoffeeBeans, 1200);
+        assert_eq!(severities(tracker.take()), vec![None]);
+    }
+
+    #[test]
+    fn critical_clears_to_warning() {
+        let mut tracker = tracker();
+        tracker.update(Container::Foam, 0);
+        tracker.update(Container::Foam, 60);
+        tracker.update(Container::Foam, 70);
+        let alerts = tracker.take();
+        assert_eq!(
+            severities(alerts.clone()),
+            vec![Some(Severity::Critical), Some(Severity::Warning)]
+        );
+        assert_eq!(alerts[1].previous, Some(Severity::Critical));
+        assert_eq!(alerts[1].level, 70);
+    }
+
+    #[test]
+    fn starts_without_alerting() {
+        let empty = Levels::default();
+        let mut tracker = AlertTracker::new(AlertPolicy::default(), empty);
+        assert!(tracker.take().is_empty());
+        assert_eq!(
+            tracker.severity(Container::Coffee),
+            Some(Severity::Critical)
+        );
+    }
+
+    #[test]
+    fn delivers_to_every_sink() {
+        let sinks = AlertSinks::default();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..2 {
+            let received = received.clone();
+            sinks.register(Arc::new(move |alert: &Alert| {
+                received.lock().unwrap().push(alert.to_string());
+            }));
+        }
+
+        let mut tracker = tracker();
+        tracker.update(Container::Milk, 0);
+        sinks.deliver(&tracker.take());
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["[CRITICAL] milk is low: 0", "[CRITICAL] milk is low: 0"]
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    builder::CoffeeMakerBuilder,
+    observer::{Container, Context, Observer},
+    orders::Order,
+    take_orders::OrderSource,
+    LockGranularity, RefillPolicy,
+};
+
+/// A configuration of the coffee maker to benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub dispensers: usize,
+    pub granularity: LockGranularity,
+    pub refill: RefillPolicy,
+}
+
+impl Default for BenchConfig {
+    fn default() -> BenchConfig {
+        BenchConfig {
+            dispensers: 4,
+            granularity: LockGranularity::default(),
+            refill: RefillPolicy::default(),
+        }
+    }
+}
+
+impl BenchConfig {
+    /// The configurations compared unless set otherwise: the amount of dispensers,
+    /// then each lock granularity and refill policy.
+    pub fn standard() -> Vec<BenchConfig> {
+        let mut configs: Vec<BenchConfig> = [1, 2, 4, 8]
+            .into_iter()
+            .map(|dispensers| BenchConfig {
+                dispensers,
+                ..BenchConfig::default()
+            })
+            .collect();
+        configs.push(BenchConfig {
+            granularity: LockGranularity::Machine,
+            ..BenchConfig::default()
+        });
+        configs.push(BenchConfig {
+            refill: RefillPolicy::Fill,
+            ..BenchConfig::default()
+        });
+        configs
+    }
+}
+
+impl FromStr for BenchConfig {
+    type Err = String;
+
+    /// Parses a configuration like `dispensers=4,locks=machine,refill=fill`.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<BenchConfig, String> {
+        let mut config = BenchConfig::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("Invalid bench setting: {}", pair))?;
+            match key {
+                "dispensers" => {
+                    config.dispensers = value
+                        .parse()
+                        .map_err(|_| format!("Invalid amount of dispensers: {}", value))?
+                }
+                "locks" => config.granularity = value.parse()?,
+                "refill" => config.refill = value.parse()?,
+                _ => return Err(format!("Unknown bench setting: {}", key)),
+            }
+        }
+        if config.dispensers == 0 {
+            return Err("There must be at least one dispenser".to_string());
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for BenchConfig {
+    /// Displays the configuration in the same format it is parsed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "dispensers={},locks={},refill={}",
+            self.dispensers, self.granularity, self.refill
+        )
+    }
+}
+
+/// Adds up the time dispensers spent waiting for the containers.
+#[derive(Default)]
+struct LockWait(Mutex<Duration>);
+
+impl Observer for LockWait {
+    fn lock_acquired(&self, _context: Context, _container: Container, waited: Duration) {
+        *self.0.lock().expect("Failed to lock lock wait") += waited;
+    }
+}
+
+/// Outcome of running the workload with a configuration.
+/// Durations are in the time of the coffee maker, as if the clock weren't accelerated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchResult {
+    pub config: BenchConfig,
+    pub completed: u64,
+    /// From the first order being queued until every order was made.
+    pub makespan: Duration,
+    /// Mean time the completed orders waited in the queue.
+    pub average_wait: Duration,
+    /// Time every dispenser spent waiting for the containers to be unlocked, added up.
+    pub lock_wait: Duration,
+}
+
+impl BenchResult {
+    /// Orders completed per minute.
+    pub fn throughput(&self) -> f64 {
+        match self.makespan.as_secs_f64() {
+            seconds if seconds > 0.0 => self.completed as f64 * 60.0 / seconds,
+            _ => 0.0,
+        }
+    }
+
+    /// Share of the time of the dispensers spent waiting for the containers to be unlocked.
+    pub fn contention(&self) -> f64 {
+        let available = self.makespan.as_secs_f64() * self.config.dispensers as f64;
+        match available {
+            available if available > 0.0 => self.lock_wait.as_secs_f64() / available,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Runs the orders with the given configuration, starting from full containers,
+/// with the clock running the given times faster than real time.
+pub fn run(config: BenchConfig, orders: &[Order], speedup: u32) -> BenchResult {
+    let lock_wait = Arc::new(LockWait::default());
+    let machine = CoffeeMakerBuilder::new()
+        .name(&config.to_string())
+        .dispenser_count(config.dispensers)
+        .lock_granularity(config.granularity)
+        .refill_policy(config.refill)
+        .speedup(speedup)
+        .source(OrderSource::List(orders.to_vec()))
+        .observer(lock_wait.clone())
+        .build()
+        .expect("Full containers are valid");
+
+    let started = Instant::now();
+    let report = machine
+        .run()
+        .expect("Orders from a list can always be taken");
+    let elapsed = started.elapsed();
+
+    let speedup = speedup.max(1);
+    let lock_wait = *lock_wait.0.lock().expect("Failed to lock lock wait");
+    BenchResult {
+        config,
+        completed: report.completed,
+        makespan: elapsed * speedup,
+        average_wait: report.latency.overall.queue_wait.mean * speedup,
+        lock_wait: lock_wait * speedup,
+    }
+}
+
+/// Results of running the same orders with several configurations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    pub results: Vec<BenchResult>,
+}
+
+/// Runs the orders with each configuration, one after the other.
+pub fn compare(configs: &[BenchConfig], orders: &[Order], speedup: u32) -> Comparison {
+    Comparison {
+        results: configs
+            .iter()
+            .map(|config| run(*config, orders, speedup))
+            .collect(),
+    }
+}
+
+impl fmt::Display for Comparison {
+    /// Displays a table with a row per configuration.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<48} {:>6} {:>11} {:>13} {:>14} {:>11}",
+            "Configuration", "orders", "orders/min", "makespan (s)", "avg wait (ms)", "contention"
+        )?;
+        for result in &self.results {
+            writeln!(
+                f,
+                "{:<48} {:>6} {:>11.1} {:>13.2} {:>14.1} {:>10.1}%",
+                result.config.to_string(),
+                result.completed,
+                result.throughput(),
+                result.makespan.as_secs_f64(),
+                result.average_wait.as_secs_f64() * 1000.0,
+                result.contention() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bench_tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config: BenchConfig = "dispensers=2,locks=machine,refill=fill".parse().unwrap();
+        assert_eq!(config.dispensers, 2);
+        assert_eq!(config.granularity, LockGranularity::Machine);
+        assert_eq!(config.refill, RefillPolicy::Fill);
+        assert_eq!(config.to_string(), "dispensers=2,locks=machine,refill=fill");
+        assert_eq!("".parse::<BenchConfig>().unwrap(), BenchConfig::default());
+
+        assert!("dispensers=0".parse::<BenchConfig>().is_err());
+        assert!("locks=none".parse::<BenchConfig>().is_err());
+        assert!("speed=2".parse::<BenchConfig>().is_err());
+    }
+
+    #[test]
+    fn runs_every_config() {
+        let orders = vec![Order::from(10, 10, 10).unwrap(); 5];
+        let configs = BenchConfig::standard();
+        let comparison = compare(&configs, &orders, 10);
+
+        assert_eq!(comparison.results.len(), configs.len());
+        for (result, config) in comparison.results.iter().zip(&configs) {
+            assert_eq!(result.config, *config);
+            assert_eq!(result.completed, 5);
+            assert!(result.contention() <= 1.0);
+        }
+        let table = comparison.to_string();
+        assert_eq!(table.lines().count(), configs.len() + 1);
+        assert!(table.contains("dispensers=4,locks=machine,refill=just-enough"));
+    }
+
+    #[test]
+    fn rates_are_zero_without_time() {
+        let result = BenchResult {
+            config: BenchConfig::default(),
+            completed: 3,
+            makespan: Duration::ZERO,
+            average_wait: Duration::ZERO,
+            lock_wait: Duration::ZERO,
+        };
+        assert_eq!(result.throughput(), 0.0);
+        assert_eq!(result.contention(), 0.0);
+    }
+}
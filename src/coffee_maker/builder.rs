@@ -0,0 +1,396 @@
+use std::{io::Write, sync::Arc, time::Duration};
+
+use super::{
+    alerts::{AlertPolicy, AlertSink},
+    capabilities::Capabilities,
+    config,
+    faults::{FaultConfig, FaultInjector},
+    journal::{Journal, Recovery},
+    machine::{CoffeeMaker, Extras},
+    observer::Observer,
+    orders::Order,
+    pool::ScalingPolicy,
+    resources_monitor::MonitorMode,
+    state::MachineState,
+    take_orders::OrderSource,
+    LockGranularity, RefillPolicy, Resources,
+};
+
+/// Amount of dispensers of a coffee maker, unless set otherwise.
+pub const DEFAULT_DISPENSERS: usize = 3;
+
+/// Builds a `CoffeeMaker`, starting from full containers and `DEFAULT_DISPENSERS` dispensers
+/// able to pour every ingredient.
+pub struct CoffeeMakerBuilder {
+    name: String,
+    coffee: u32,
+    coffee_beans: u32,
+    foam: u32,
+    milk: u32,
+    dispensers: Vec<Capabilities>,
+    faults: Option<FaultConfig>,
+    granularity: LockGranularity,
+    refill: RefillPolicy,
+    speedup: u32,
+    patience: Option<Duration>,
+    alert_policy: Option<AlertPolicy>,
+    alert_sinks: Vec<Arc<dyn AlertSink>>,
+    journal: Option<String>,
+    extras: Extras,
+}
+
+impl Default for CoffeeMakerBuilder {
+    fn default() -> CoffeeMakerBuilder {
+        CoffeeMakerBuilder {
+            name: "coffee maker".to_string(),
+            coffee: config::C,
+            coffee_beans: config::G,
+            foam: config::E,
+            milk: config::L,
+            dispensers: vec![Capabilities::ALL; DEFAULT_DISPENSERS],
+            faults: None,
+            granularity: LockGranularity::default(),
+            refill: RefillPolicy::default(),
+            speedup: 1,
+            patience: None,
+            alert_policy: None,
+            alert_sinks: Vec::new(),
+            journal: None,
+            extras: Extras::default(),
+        }
+    }
+}
+
+impl CoffeeMakerBuilder {
+    /// Creates a new CoffeeMakerBuilder instance with the defaults.
+    pub fn new() -> CoffeeMakerBuilder {
+        CoffeeMakerBuilder::default()
+    }
+
+    /// Sets the name of the coffee maker.
+    pub fn name(mut self, name: &str) -> CoffeeMakerBuilder {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets the initial amount of each resource.
+    pub fn resources(
+        mut self,
+        coffee: u32,
+        coffee_beans: u32,
+        foam: u32,
+        milk: u32,
+    ) -> CoffeeMakerBuilder {
+        self.coffee = coffee;
+        self.coffee_beans = coffee_beans;
+        self.foam = foam;
+        self.milk = milk;
+        self
+    }
+
+    /// Sets the amount of dispensers, all of them able to pour every ingredient.
+    pub fn dispenser_count(mut self, count: usize) -> CoffeeMakerBuilder {
+        self.dispensers = vec![Capabilities::ALL; count];
+        self
+    }
+
+    /// Sets the capabilities of each dispenser.
+    pub fn dispensers(mut self, dispensers: Vec<Capabilities>) -> CoffeeMakerBuilder {
+        self.dispensers = dispensers;
+        self
+    }
+
+    /// Adds a source to take orders from when started.
+    pub fn source(mut self, source: OrderSource) -> CoffeeMakerBuilder {
+        self.extras.sources.push(source);
+        self
+    }
+
+    /// Adds a file to take orders from when started.
+    pub fn orders_file(self, filename: &str) -> CoffeeMakerBuilder {
+        self.source(OrderSource::File(filename.to_string()))
+    }
+
+    /// Prints the resources at the given interval while running.
+    pub fn monitor(mut self, interval_millis: u64) -> CoffeeMakerBuilder {
+        self.extras.monitor_interval = Some(interval_millis);
+        self
+    }
+
+    /// Sets how the monitor prints the resources, as a dashboard unless set otherwise.
+    pub fn monitor_mode(mut self, mode: MonitorMode) -> CoffeeMakerBuilder {
+        self.extras.monitor_mode = mode;
+        self
+    }
+
+    /// Shows a full screen dashboard, refreshed at the given interval while running.
+    pub fn dashboard(mut self, interval_millis: u64) -> CoffeeMakerBuilder {
+        self.extras.dashboard_interval = Some(interval_millis);
+        self
+    }
+
+    /// Injects faults with the given config.
+    pub fn faults(mut self, config: FaultConfig) -> CoffeeMakerBuilder {
+        self.faults = Some(config);
+        self
+    }
+
+    /// Sets how the containers are locked while they are used.
+    pub fn lock_granularity(mut self, granularity: LockGranularity) -> CoffeeMakerBuilder {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Sets how much coffee beans or milk is transformed when there isn't enough coffee or foam.
+    pub fn refill_policy(mut self, refill: RefillPolicy) -> CoffeeMakerBuilder {
+        self.refill = refill;
+        self
+    }
+
+    /// Makes every action of the machine the given times shorter, to run faster than real time.
+    pub fn speedup(mut self, speedup: u32) -> CoffeeMakerBuilder {
+        self.speedup = speedup;
+        self
+    }
+
+    /// Sets how long customers wait for their orders to be taken before leaving.
+    pub fn patience(mut self, patience: Duration) -> CoffeeMakerBuilder {
+        self.patience = Some(patience);
+        self
+    }
+
+    /// Sets when to alert about the containers.
+    pub fn alerts(mut self, policy: AlertPolicy) -> CoffeeMakerBuilder {
+        self.alert_policy = Some(policy);
+        self
+    }
+
+    /// Adds a sink where the alerts about the containers will be delivered.
+    pub fn alert_sink(mut self, sink: Arc<dyn AlertSink>) -> CoffeeMakerBuilder {
+        self.alert_sinks.push(sink);
+        self
+    }
+
+    /// Scales the dispensers automatically with the given policy while running.
+    pub fn autoscale(mut self, policy: ScalingPolicy) -> CoffeeMakerBuilder {
+        self.extras.scaling = Some(policy);
+        self
+    }
+
+    /// Estimates when the orders will be ready, as they are taken; and reports how close
+    /// those estimates were.
+    pub fn estimates(mut self) -> CoffeeMakerBuilder {
+        self.extras.estimates = true;
+        self
+    }
+
+    /// Adds an observer of everything that happens in the coffee maker.
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> CoffeeMakerBuilder {
+        self.extras.observers.push(observer);
+        self
+    }
+
+    /// Adds a sink where the final report will be written.
+    pub fn sink(mut self, sink: Box<dyn Write + Send>) -> CoffeeMakerBuilder {
+        self.extras.sinks.push(sink);
+        self
+    }
+
+    /// Carries on from the state saved in the file, if there is one, instead of the initial
+    /// resources; and saves the state to it when shut down.
+    pub fn state_file(mut self, path: &str) -> CoffeeMakerBuilder {
+        self.extras.state_file = Some(path.to_string());
+        self
+    }
+
+    /// Records what happens to every order in a journal at the given path.
+    /// If it exists, recovers from the run that wrote it: the orders it accepted but didn't
+    /// finish are put back in the queue, instead of those of the state file; and the orders
+    /// it took are skipped from the first orders file.
+    pub fn journal(mut self, path: &str) -> CoffeeMakerBuilder {
+        self.journal = Some(path.to_string());
+        self
+    }
+
+    /// Builds the coffee maker, starting its dispensers.
+    /// Fails if the resources exceed the capacity of the containers,
+    /// or the state file or the journal can't be read or aren't valid.
+    pub fn build(mut self) -> Result<CoffeeMaker, String> {
+        let journal =
+            match &self.journal {
+                Some(path) => Some(Journal::recover(path).map_err(|error| {
+                    format!("Failed to recover the journal {}: {}", path, error)
+                })?),
+                None => None,
+            };
+        if let Some((journal, recovery)) = &journal {
+            self.extras.observers.push(journal.clone());
+            for (index, source) in self.extras.sources.iter_mut().enumerate() {
+                if let OrderSource::File(path) = source {
+                    let taken = recovery.taken.get(&index).copied().unwrap_or(0);
+                    *source = OrderSource::Resumed(path.clone(), taken);
+                }
+            }
+        }
+        let state = match &self.extras.state_file {
+            Some(path) => MachineState::load(path)?,
+            None => None,
+        };
+        let resources = match &state {
+            Some(state) => {
+                let levels = state.levels;
+                let resources =
+                    Resources::new(levels.coffee, levels.coffee_beans, levels.foam, levels.milk)?;
+                let stats = resources.stats();
+                stats.restore(state.completed, state.failed, state.retried, &state.faults);
+                resources
+            }
+            None => Resources::new(self.coffee, self.coffee_beans, self.foam, self.milk)?,
+        };
+        if let Some(config) = self.faults {
+            resources.inject_faults(Arc::new(FaultInjector::new(config)));
+        }
+        resources.set_lock_granularity(self.granularity);
+        resources.set_refill_policy(self.refill);
+        resources.set_speedup(self.speedup);
+        if let Some(policy) = self.alert_policy {
+            resources.set_alert_policy(policy);
+        }
+        for sink in self.alert_sinks {
+            resources.add_alert_sink(sink);
+        }
+        let machine =
+            CoffeeMaker::with_extras(&self.name, resources, &self.dispensers, self.extras);
+        machine.orders().set_patience(self.patience);
+        if let Some(state) = &state {
+            machine.orders().restore(state.rejected, state.abandoned);
+        }
+        match journal {
+            // a new journal has nothing to recover, the orders come from the state if any
+            Some((_, recovery)) if recovery != Recovery::default() => {
+                machine.orders().resume_after(recovery.last_id);
+                for (id, ingredients) in recovery.unfinished {
+                    machine.orders().requeue(id, ingredients);
+                }
+            }
+            _ => {
+                for (ingredients, patience) in state.into_iter().flat_map(|state| state.orders) {
+                    machine
+                        .orders()
+                        .push_with_patience(Order::Order(ingredients), patience);
+                }
+            }
+        }
+        Ok(machine)
+    }
+}
+
+#[cfg(test)]
+mod coffee_maker_builder_tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::coffee_maker::{
+        observer::Context,
+        orders::{Order, Ticket},
+        test_utils::SharedWriter,
+    };
+
+    #[test]
+    fn defaults() {
+        let machine = CoffeeMakerBuilder::new().build().unwrap();
+        assert_eq!(machine.name(), "coffee maker");
+        assert_eq!(machine.pool().running(), DEFAULT_DISPENSERS);
+        machine.shutdown();
+    }
+
+    #[test]
+    fn too_many_resources() {
+        let result = CoffeeMakerBuilder::new()
+            .resources(config::C + 1, 0, 0, 0)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn runs_and_reports_to_sinks() {
+        let sink = SharedWriter::default();
+        let machine = CoffeeMakerBuilder::new()
+            .name("builder")
+            .resources(100, 0, 100, 0)
+            .dispensers(vec!["coffee+water".parse().unwrap()])
+            .source(OrderSource::List(vec![
+                Order::from(10, 10, 0).unwrap(),
+                Order::from(10, 10, 10).unwrap(),
+            ]))
+            .sink(Box::new(sink.clone()))
+            .build()
+            .unwrap();
+
+        let report = machine.run().unwrap();
+        assert_eq!(report.name, "builder");
+        assert_eq!(report.completed, 1);
+        assert_eq!(report.rejected, 1);
+
+        let written = sink.written();
+        assert_eq!(written, report.to_string());
+    }
+
+    /// Records the lifecycle of the orders.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn order_queued(&self, ticket: &Ticket) {
+            self.0.lock().unwrap().push(format!("queued {}", ticket.id));
+        }
+
+        fn order_started(&self, _dispenser: usize, ticket: &Ticket) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("started {}", ticket.id));
+        }
+
+        fn grinder_finished(&self, context: Context, amount: u32) {
+            let event = format!("ground {} for {}", amount, context.order.unwrap());
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn order_completed(&self, _dispenser: usize, ticket: &Ticket) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("completed {}", ticket.id));
+        }
+    }
+
+    #[test]
+    fn observers_see_the_orders() {
+        let recorder = Arc::new(Recorder::default());
+        let machine = CoffeeMakerBuilder::new()
+            .resources(0, 100, 0, 0)
+            .dispenser_count(1)
+            .source(OrderSource::List(vec![Order::from(10, 0, 0).unwrap()]))
+            .observer(recorder.clone())
+            .build()
+            .unwrap();
+        machine.run().unwrap();
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec!["queued 1", "started 1", "ground 10 for 1", "completed 1"]
+        );
+    }
+
+    #[test]
+    fn missing_orders_file() {
+        let machine = CoffeeMakerBuilder::new()
+            .orders_file("assets/_temp__builder__missing.csv")
+            .build()
+            .unwrap();
+        assert!(machine.run().is_err());
+        machine.shutdown();
+    }
+}
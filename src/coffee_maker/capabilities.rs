@@ -0,0 +1,113 @@
+use std::{fmt, str::FromStr};
+
+use super::orders::Ingredients;
+
+/// Ingredients a dispenser is able to pour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    pub coffee: bool,
+    pub water: bool,
+    pub foam: bool,
+}
+
+impl Capabilities {
+    /// A dispenser able to pour every ingredient.
+    pub const ALL: Capabilities = Capabilities {
+        coffee: true,
+        water: true,
+        foam: true,
+    };
+
+    /// Capabilities needed to make a drink with the given ingredients.
+    pub fn needed_for(ingredients: &Ingredients) -> Capabilities {
+        Capabilities {
+            coffee: ingredients.coffee > 0,
+            water: ingredients.water > 0,
+            foam: ingredients.foam > 0,
+        }
+    }
+
+    /// Returns whether a dispenser with these capabilities can make a drink with the given ingredients.
+    /// Ingredients that are not needed don't require the capability.
+    pub fn can_make(&self, ingredients: &Ingredients) -> bool {
+        (self.coffee || ingredients.coffee == 0)
+            && (self.water || ingredients.water == 0)
+            && (self.foam || ingredients.foam == 0)
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        Capabilities::ALL
+    }
+}
+
+impl FromStr for Capabilities {
+    type Err = String;
+
+    /// Parses capabilities in the format `coffee+water+foam` (in any order), or `all`.
+    fn from_str(s: &str) -> Result<Capabilities, String> {
+        if s == "all" {
+            return Ok(Capabilities::ALL);
+        }
+
+        let mut capabilities = Capabilities {
+            coffee: false,
+            water: false,
+            foam: false,
+        };
+        for capability in s.split('+') {
+            match capability {
+                "coffee" => capabilities.coffee = true,
+                "water" => capabilities.water = true,
+                "foam" => capabilities.foam = true,
+                _ => return Err(format!("Unknown capability: {}", capability)),
+            }
+        }
+        Ok(capabilities)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Displays the capabilities in the same format they are parsed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = [
+            (self.coffee, "coffee"),
+            (self.water, "water"),
+            (self.foam, "foam"),
+        ]
+        .into_iter()
+        .filter(|(has, _)| *has)
+        .map(|(_, name)| name)
+        .collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+#[cfg(test)]
+mod capabilities_tests {
+    use super::*;
+
+    #[test]
+    fn all_can_make_anything() {
+        assert!(Capabilities::ALL.can_make(&Ingredients::new(10, 10, 10)));
+        assert!(Capabilities::ALL.can_make(&Ingredients::new(0, 0, 0)));
+    }
+
+    #[test]
+    fn missing_capability_only_matters_if_needed() {
+        let no_milk: Capabilities = "coffee+water".parse().unwrap();
+        assert!(no_milk.can_make(&Ingredients::new(10, 10, 0)));
+        assert!(!no_milk.can_make(&Ingredients::new(10, 10, 10)));
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for input in ["coffee", "water", "coffee+foam", "coffee+water+foam"] {
+            let capabilities: Capabilities = input.parse().unwrap();
+            assert_eq!(capabilities.to_string(), input);
+        }
+        assert_eq!("all".parse::<Capabilities>().unwrap(), Capabilities::ALL);
+        assert!("coffee+tea".parse::<Capabilities>().is_err());
+    }
+}
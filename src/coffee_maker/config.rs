@@ -8,3 +8,5 @@ pub const L: u32 = 10000;
 pub const E: u32 = 1000;
 /// Percentage of resources below which the coffee maker will issue a warning
 pub const X: u32 = 10;
+/// Amount of times an order is attempted before it is discarded because of faults
+pub const MAX_ATTEMPTS: u32 = 3;
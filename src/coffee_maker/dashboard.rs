@@ -0,0 +1,395 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use super::{
+    alerts::Severity,
+    observer::{Container, Context, Ingredient, Observer},
+    orders::{Orders, Ticket},
+    pool::DispenserPool,
+    resources_monitor::{spawn_monitor, MonitorHandle},
+    snapshot::ContainerSnapshot,
+    Error, Resources,
+};
+
+/// Width of the level bars, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Amount of alerts shown.
+const RECENT_ALERTS: usize = 5;
+
+/// What a dispenser is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Idle,
+    WaitingForCoffee,
+    Grinding,
+    PouringCoffee,
+    PouringWater,
+    WaitingForFoam,
+    Whipping,
+    PouringFoam,
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Activity::Idle => "idle",
+            Activity::WaitingForCoffee => "waiting for coffee lock",
+            Activity::Grinding => "grinding",
+            Activity::PouringCoffee => "pouring coffee",
+            Activity::PouringWater => "pouring water",
+            Activity::WaitingForFoam => "waiting for foam lock",
+            Activity::Whipping => "whipping",
+            Activity::PouringFoam => "pouring foam",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A full screen terminal dashboard of a coffee maker.
+/// Keeps track of what each dispenser is doing and of the recent alerts as an observer,
+/// and shows them along with the levels of the containers and the queue.
+pub struct Dashboard {
+    started: Instant,
+    dispensers: Mutex<HashMap<usize, (Activity, u64)>>,
+    alerts: Mutex<VecDeque<String>>,
+}
+
+impl Dashboard {
+    /// Creates a new Dashboard instance, with every dispenser idle.
+    pub fn new() -> Arc<Dashboard> {
+        Arc::new(Dashboard {
+            started: Instant::now(),
+            dispensers: Mutex::new(HashMap::new()),
+            alerts: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Returns what the given dispenser is doing, and for which order.
+    pub fn activity(&self, dispenser: usize) -> (Activity, Option<u64>) {
+        let dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        match dispensers.get(&dispenser) {
+            Some((activity, order)) => (*activity, Some(*order)),
+            None => (Activity::Idle, None),
+        }
+    }
+
+    /// Returns the most recent alerts, oldest first.
+    pub fn alerts(&self) -> Vec<String> {
+        let alerts = self.alerts.lock().expect("Failed to lock alerts");
+        alerts.iter().cloned().collect()
+    }
+
+    /// Sets what the dispenser of the context is doing.
+    fn set(&self, context: Context, activity: Activity) {
+        if let (Some(dispenser), Some(order)) = (context.dispenser, context.order) {
+            let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+            dispensers.insert(dispenser, (activity, order));
+        }
+    }
+
+    /// Sets the dispenser as idle.
+    fn idle(&self, dispenser: usize) {
+        let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        dispensers.remove(&dispenser);
+    }
+
+    /// Renders the dashboard, to be printed over the previous one.
+    pub fn render(&self, resources: &Resources, orders: &Orders, pool: &DispenserPool) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let snapshot = resources.snapshot();
+        let warning = resources.alert_policy().warning;
+        let stats = resources.stats();
+        let per_minute = match elapsed {
+            elapsed if elapsed > 0.0 => stats.completed() as f64 * 60.0 / elapsed,
+            _ => 0.0,
+        };
+
+        let mut lines = vec![format!("Coffee maker - up {:.1}s", elapsed), String::new()];
+        lines.extend(
+            snapshot
+                .containers
+                .iter()
+                .map(|container| level_line(container, warning)),
+        );
+        lines.extend([
+            String::new(),
+            format!(
+                "Queue: {}   Completed: {} ({:.1}/min)   Retried: {}   Failed: {}   Abandoned: {}",
+                orders.len(),
+                stats.completed(),
+                per_minute,
+                stats.retried(),
+                stats.failed(),
+                orders.abandoned()
+            ),
+            String::new(),
+            "Dispensers".to_string(),
+        ]);
+        for (id, capabilities, state) in pool.list() {
+            let (activity, order) = self.activity(id);
+            let order = order.map_or(String::new(), |order| format!("order {}", order));
+            lines.push(format!(
+                "  #{:<3} {:<18} {:<9} {:<24} {}",
+                id,
+                capabilities.to_string(),
+                state.to_string(),
+                activity.to_string(),
+                order
+            ));
+        }
+        lines.push(String::new());
+        lines.push("Alerts".to_string());
+        lines.extend(
+            self.alerts()
+                .into_iter()
+                .map(|alert| format!("  {}", alert)),
+        );
+
+        // moves to the top, clearing each line and whatever is below the dashboard
+        let mut screen = format!("{}[H", 27 as char);
+        for line in lines {
+            screen.push_str(&format!("{}{}[K\n", line, 27 as char));
+        }
+        screen.push_str(&format!("{}[J", 27 as char));
+        screen
+    }
+
+    /// Starts refreshing the dashboard at an interval in a new thread, writing it to the
+    /// given output; and once more when stopped.
+    /// Returns a handle to stop it.
+    pub fn start(
+        self: &Arc<Self>,
+        resources: Arc<Resources>,
+        orders: Arc<Orders>,
+        pool: Arc<DispenserPool>,
+        interval_millis: u64,
+        output: Box<dyn Write + Send>,
+    ) -> MonitorHandle {
+        let dashboard = self.clone();
+        let mut cleared = false;
+
+        spawn_monitor(interval_millis, output, move |_last| {
+            let screen = dashboard.render(&resources, &orders, &pool);
+            match std::mem::replace(&mut cleared, true) {
+                // clears the screen once, then refreshes in place
+                false => format!("{}[2J{}", 27 as char, screen),
+                true => screen,
+            }
+        })
+    }
+}
+
+/// Renders a bar filled according to the level, with a `|` marking the threshold if any.
+fn bar(level: u32, capacity: u32, threshold: Option<u32>) -> String {
+    let position = |amount: u32| match capacity {
+        0 => 0,
+        _ => (amount.min(capacity) as usize * BAR_WIDTH) / capacity as usize,
+    };
+    let filled = position(level);
+    let mut bar: Vec<char> = (0..BAR_WIDTH)
+        .map(|i| if i < filled { '#' } else { '.' })
+        .collect();
+    if let Some(threshold) = threshold {
+        bar[position(threshold).min(BAR_WIDTH - 1)] = '|';
+    }
+    format!("[{}]", bar.into_iter().collect::<String>())
+}
+
+/// Renders the line of a container, marking the warning level of the alert policy,
+/// as a percentage of its capacity, and whether an alert is raised for it.
+fn level_line(snapshot: &ContainerSnapshot, warning: u32) -> String {
+    let (name, unit) = match snapshot.container {
+        Container::Coffee => ("Coffee", "mg"),
+        Container::CoffeeBeans => ("Coffee beans", "mg"),
+        Container::Foam => ("Foam", "ml"),
+        Container::Milk => ("Milk", "ml"),
+    };
+    let threshold = snapshot.capacity * warning.min(100) / 100;
+    let alert = match snapshot.alert {
+        Some(Severity::Warning) => " LOW",
+        Some(Severity::Critical) => " CRITICAL",
+        None => "",
+    };
+    format!(
+        "{:<13} {} {:>5}/{} {}{}",
+        name,
+        bar(snapshot.level, snapshot.capacity, Some(threshold)),
+        snapshot.level,
+        snapshot.capacity,
+        unit,
+        alert
+    )
+}
+
+impl Observer for Dashboard {
+    fn lock_waiting(&self, context: Context, container: Container) {
+        match container {
+            Container::Coffee => self.set(context, Activity::WaitingForCoffee),
+            _ => self.set(context, Activity::WaitingForFoam),
+        }
+    }
+
+    fn lock_acquired(&self, context: Context, container: Container, _waited: std::time::Duration) {
+        match container {
+            Container::Coffee => self.set(context, Activity::PouringCoffee),
+            _ => self.set(context, Activity::PouringFoam),
+        }
+    }
+
+    fn ingredient_dispensed(&self, context: Context, ingredient: Ingredient, _amount: u32) {
+        if ingredient == Ingredient::Coffee {
+            self.set(context, Activity::PouringWater);
+        }
+    }
+
+    fn grinder_started(&self, context: Context, _amount: u32) {
+        self.set(context, Activity::Grinding);
+    }
+
+    fn grinder_finished(&self, context: Context, _amount: u32) {
+        self.set(context, Activity::PouringCoffee);
+    }
+
+    fn whipper_started(&self, context: Context, _amount: u32) {
+        self.set(context, Activity::Whipping);
+    }
+
+    fn whipper_finished(&self, context: Context, _amount: u32) {
+        self.set(context, Activity::PouringFoam);
+    }
+
+    fn threshold_crossed(&self, container: Container, level: u32, low: bool) {
+        let state = match low {
+            true => "below",
+            false => "back above",
+        };
+        let alert = format!(
+            "+{:.1}s {} {} threshold ({})",
+            self.started.elapsed().as_secs_f64(),
+            container,
+            state,
+            level
+        );
+        let mut alerts = self.alerts.lock().expect("Failed to lock alerts");
+        alerts.push_back(alert);
+        if alerts.len() > RECENT_ALERTS {
+            alerts.pop_front();
+        }
+    }
+
+    fn order_completed(&self, dispenser: usize, _ticket: &Ticket) {
+        self.idle(dispenser);
+    }
+
+    fn order_retried(&self, dispenser: usize, _ticket: &Ticket, _error: &Error) {
+        self.idle(dispenser);
+    }
+
+    fn order_failed(&self, dispenser: usize, _ticket: &Ticket, _error: &Error) {
+        self.idle(dispenser);
+    }
+}
+
+#[cfg(test)]
+mod dashboard_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        alerts::AlertPolicy, capabilities::Capabilities, test_utils::SharedWriter,
+    };
+
+    #[test]
+    fn bars_show_level_and_threshold() {
+        let bar = bar(50, 100, Some(10));
+        assert_eq!(bar.len(), BAR_WIDTH + 2);
+        assert_eq!(bar.matches('#').count(), BAR_WIDTH / 2 - 1);
+        assert_eq!(bar.chars().nth(1 + BAR_WIDTH / 10), Some('|'));
+        assert_eq!(
+            super::bar(0, 0, None),
+            format!("[{}]", ".".repeat(BAR_WIDTH))
+        );
+    }
+
+    #[test]
+    fn follows_what_dispensers_do() {
+        let dashboard = Dashboard::new();
+        let context = Context::new(1, 7);
+
+        dashboard.lock_waiting(context, Container::Coffee);
+        assert_eq!(dashboard.activity(1), (Activity::WaitingForCoffee, Some(7)));
+        dashboard.grinder_started(context, 10);
+        assert_eq!(dashboard.activity(1).0, Activity::Grinding);
+        dashboard.ingredient_dispensed(context, Ingredient::Coffee, 10);
+        assert_eq!(dashboard.activity(1).0, Activity::PouringWater);
+        dashboard.whipper_started(context, 10);
+        assert_eq!(dashboard.activity(1).0, Activity::Whipping);
+        // actions without a dispenser don't count
+        dashboard.grinder_started(Context::default(), 10);
+        assert_eq!(dashboard.activity(0), (Activity::Idle, None));
+    }
+
+    #[test]
+    fn keeps_recent_alerts() {
+        let dashboard = Dashboard::new();
+        for level in 0..RECENT_ALERTS as u32 + 2 {
+            dashboard.threshold_crossed(Container::Milk, level, true);
+        }
+        let alerts = dashboard.alerts();
+        assert_eq!(alerts.len(), RECENT_ALERTS);
+        assert!(alerts[0].ends_with("milk below threshold (2)"));
+    }
+
+    #[test]
+    fn renders_the_machine() {
+        let dashboard = Dashboard::new();
+        let resources = Resources::new(0, 100, 0, 0).unwrap();
+        resources.set_alert_policy(AlertPolicy {
+            warning: 50,
+            critical: 0,
+            hysteresis: 0,
+        });
+        let orders = Orders::new();
+        let pool = DispenserPool::new(orders.clone(), resources.clone());
+        pool.add(Capabilities::ALL);
+        resources.register_observer(dashboard.clone());
+        dashboard.lock_waiting(Context::new(0, 3), Container::Foam);
+
+        let screen = dashboard.render(&resources, &orders, &pool);
+        assert!(screen.starts_with("\x1b[H"));
+        assert!(screen.contains("Coffee beans  ["));
+        assert!(screen.contains("100/10000 mg LOW"));
+        // every container is marked at the warning level of the policy
+        let marked = format!("[{}|", ".".repeat(BAR_WIDTH / 2));
+        assert_eq!(screen.matches(&marked).count(), Container::ALL.len());
+        assert!(screen.contains("0/1000 ml LOW"));
+        assert!(screen.contains("Queue: 0"));
+        assert!(screen.contains("waiting for foam lock"));
+        assert!(screen.contains("order 3"));
+
+        orders.push(crate::coffee_maker::orders::Order::NoMoreOrders);
+        pool.join();
+    }
+
+    #[test]
+    fn stops_right_away_into_its_output() {
+        let dashboard = Dashboard::new();
+        let resources = Resources::new(0, 0, 0, 0).unwrap();
+        let orders = Orders::new();
+        let pool = DispenserPool::new(orders.clone(), resources.clone());
+        let output = SharedWriter::default();
+
+        let started = Instant::now();
+        let handle = dashboard.start(resources, orders, pool, 60_000, Box::new(output.clone()));
+        handle.stop();
+
+        assert!(started.elapsed().as_millis() < 60_000);
+        let written = output.written();
+        assert!(written.starts_with("\x1b[2J\x1b[H"));
+        assert!(written.contains("Coffee maker - up"));
+    }
+}
@@ -1,46 +1,140 @@
-use std::{sync::Arc, thread};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use super::{
-    orders::{Ingredients, Order, Orders},
+    capabilities::Capabilities,
+    config,
+    faults::{Actuator, Fault},
+    observer::{Action, Context},
+    orders::{Orders, Ticket},
+    resources::Error,
     ResourceResult, Resources,
 };
 
+/// Extra time a slow dispenser takes to make an order.
+const SLOW_DISPENSER_DELAY: u32 = 100;
+
+/// Identifies a dispenser and what it is able to pour.
+/// Can be shared with other threads to ask the dispenser to drain.
+#[derive(Debug)]
+pub struct Dispenser {
+    pub id: usize,
+    pub capabilities: Capabilities,
+    draining: AtomicBool,
+}
+
+impl Dispenser {
+    /// Creates a new Dispenser instance.
+    pub fn new(id: usize, capabilities: Capabilities) -> Arc<Dispenser> {
+        Arc::new(Dispenser {
+            id,
+            capabilities,
+            draining: AtomicBool::new(false),
+        })
+    }
+
+    /// Asks the dispenser to stop taking orders, once it finishes the current one.
+    /// The dispenser will only notice when it next looks at the queue, see `Orders::wake`.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the dispenser was asked to drain.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+}
+
 /// Handles a single order.
 /// Taking the necessary ingredients from the resources.
-fn handle_order(ingredients: Ingredients, resources: &Arc<Resources>) -> ResourceResult {
-    resources.use_coffee(ingredients.coffee)?;
-    resources.use_water(ingredients.water)?;
-    resources.use_foam(ingredients.foam)?;
-    Ok(())
+fn handle_order(context: Context, ticket: &Ticket, resources: &Arc<Resources>) -> ResourceResult {
+    let ingredients = ticket.ingredients;
+    let fault = resources.roll_fault(Actuator::Dispenser);
+    match fault {
+        Some(Fault::Jam) => return Err(Error::Fault(Fault::Jam)),
+        Some(Fault::SlowActuator) => {
+            let started = Instant::now();
+            resources.sleep(Duration::from_millis(SLOW_DISPENSER_DELAY.into()));
+            let timing = (started, SLOW_DISPENSER_DELAY);
+            resources.notify_action(context, Action::Stall, 0, timing);
+        }
+        _ => {}
+    }
+
+    resources.use_coffee_for(context, ingredients.coffee)?;
+    // stops after the coffee, which is wasted
+    if fault == Some(Fault::PartialPour) {
+        return Err(Error::Fault(Fault::PartialPour));
+    }
+    resources.use_water_for(context, ingredients.water)?;
+    resources.use_foam_for(context, ingredients.foam)
 }
 
-/// Handles orders from the queue until there are no more orders.
-fn dispenser(orders: Arc<Orders>, resources: Arc<Resources>) {
-    while let Order::Order(ingredients) = orders.pop() {
-        if let Err(_err) = handle_order(ingredients, &resources) {
-            // unable to handle order
+/// Handles orders from the queue, that it is able to make, until there are no more orders.
+/// Orders that fail because of a fault are sent back to be retried by another dispenser.
+fn dispenser(dispenser: Arc<Dispenser>, orders: Arc<Orders>, resources: Arc<Resources>) {
+    let id = dispenser.id;
+    let observers = resources.observers();
+    while let Some(mut ticket) = orders.pop_for(&dispenser) {
+        ticket.started_at = Some(Instant::now());
+        observers.notify(|observer| observer.order_started(id, &ticket));
+        let result = handle_order(Context::new(id, ticket.id), &ticket, &resources);
+        ticket.finished_at = Some(Instant::now());
+        match result {
+            Ok(()) => {
+                resources.stats().order_completed();
+                observers.notify(|observer| observer.order_completed(id, &ticket));
+            }
+            Err(err @ Error::Fault(_)) if ticket.attempts + 1 < config::MAX_ATTEMPTS => {
+                resources.stats().order_retried();
+                observers.notify(|observer| observer.order_retried(id, &ticket, &err));
+                orders.retry(ticket, id);
+            }
+            Err(err) => {
+                resources.stats().order_failed();
+                observers.notify(|observer| observer.order_failed(id, &ticket, &err));
+            }
         }
     }
-    orders.push(Order::NoMoreOrders);
 }
 
 /// Spawns a new dispenser thread and returns its handle.
-/// The dispenser will handle orders from the given queue.
-/// The dispenser will stop when it receives a `NoMoreOrders` order, leaving it in the queue.
-pub fn spawn_dispenser(orders: Arc<Orders>, resources: Arc<Resources>) -> thread::JoinHandle<()> {
-    thread::spawn(move || dispenser(orders, resources))
+/// The dispenser is registered in the given queue, and will handle the orders it can make.
+/// The dispenser will stop when it receives a `NoMoreOrders` order, leaving it in the queue,
+/// or when it is drained.
+pub fn spawn_dispenser(
+    dispenser: Arc<Dispenser>,
+    orders: Arc<Orders>,
+    resources: Arc<Resources>,
+) -> thread::JoinHandle<()> {
+    orders.register_dispenser(dispenser.capabilities);
+    thread::spawn(move || self::dispenser(dispenser, orders, resources))
 }
 
 #[cfg(test)]
 mod dispenser_tests {
 
     use super::*;
+    use crate::coffee_maker::{
+        faults::{FaultConfig, FaultInjector},
+        orders::Order,
+    };
 
     #[test]
     fn dispenser_consumes_resources() {
         let orders = Orders::new();
         let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
-        let dispenser = spawn_dispenser(orders.clone(), resources.clone());
+        let dispenser = spawn_dispenser(
+            Dispenser::new(0, Capabilities::ALL),
+            orders.clone(),
+            resources.clone(),
+        );
 
         for _ in 0..9 {
             orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
@@ -55,4 +149,119 @@ mod dispenser_tests {
         resources.use_coffee(10).expect_err("Should be empty");
         resources.use_foam(10).expect_err("Should be empty");
     }
+
+    #[test]
+    fn faulty_orders_are_retried_until_discarded() {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
+        let mut config = FaultConfig::new(0);
+        config.jam = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+
+        let dispensers: Vec<_> = (0..2)
+            .map(|id| {
+                spawn_dispenser(
+                    Dispenser::new(id, Capabilities::ALL),
+                    orders.clone(),
+                    resources.clone(),
+                )
+            })
+            .collect();
+
+        orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
+        orders.push(Order::NoMoreOrders);
+
+        for dispenser in dispensers {
+            dispenser.join().expect("Failed to join dispenser thread");
+        }
+
+        let stats = resources.stats();
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.failed(), 1);
+        assert_eq!(stats.retried(), (config::MAX_ATTEMPTS - 1) as u64);
+        assert_eq!(stats.faults(Fault::Jam), config::MAX_ATTEMPTS as u64);
+    }
+
+    #[test]
+    fn partial_pours_waste_ingredients() {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
+        let mut config = FaultConfig::new(0);
+        config.partial_pour = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+        let dispenser = spawn_dispenser(
+            Dispenser::new(0, Capabilities::ALL),
+            orders.clone(),
+            resources.clone(),
+        );
+
+        orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
+        orders.push(Order::NoMoreOrders);
+        dispenser.join().expect("Failed to join dispenser thread");
+
+        let used = 10 * config::MAX_ATTEMPTS;
+        resources
+            .use_coffee(100 - used)
+            .expect("Should have coffee left");
+        resources.use_coffee(1).expect_err("Should be empty");
+    }
+
+    #[test]
+    fn partial_pours_stop_after_the_coffee() {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
+        let mut config = FaultConfig::new(0);
+        config.partial_pour = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+        let dispenser = spawn_dispenser(
+            Dispenser::new(0, Capabilities::ALL),
+            orders.clone(),
+            resources.clone(),
+        );
+
+        orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
+        orders.push(Order::NoMoreOrders);
+        dispenser.join().expect("Failed to join dispenser thread");
+
+        let levels = resources.levels();
+        assert_eq!(levels.coffee, 100 - 10 * config::MAX_ATTEMPTS);
+        assert_eq!(levels.foam, 100);
+        assert_eq!(resources.stats().failed(), 1);
+    }
+
+    #[test]
+    fn dispensers_only_make_what_they_can() {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
+        let no_foam: Capabilities = "coffee+water".parse().unwrap();
+        let dispenser = spawn_dispenser(
+            Dispenser::new(0, no_foam),
+            orders.clone(),
+            resources.clone(),
+        );
+
+        orders.push(Order::from(10, 10, 0).expect("Failed to create order"));
+        orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
+        orders.push(Order::NoMoreOrders);
+        dispenser.join().expect("Failed to join dispenser thread");
+
+        assert_eq!(resources.stats().completed(), 1);
+        // the foam order was left in the queue
+        resources.use_foam(100).expect("Foam should be untouched");
+    }
+
+    #[test]
+    fn drained_dispenser_stops() {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).expect("Failed to create resources");
+        let dispenser = Dispenser::new(0, Capabilities::ALL);
+        let handle = spawn_dispenser(dispenser.clone(), orders.clone(), resources.clone());
+
+        dispenser.drain();
+        orders.wake();
+        handle.join().expect("Failed to join dispenser thread");
+
+        orders.push(Order::from(10, 10, 10).expect("Failed to create order"));
+        assert_eq!(orders.len(), 1);
+    }
 }
@@ -0,0 +1,531 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use super::{
+    capabilities::Capabilities,
+    config,
+    latency::Summary,
+    observer::{ActionEvent, Observer},
+    orders::{Ingredients, Orders, Ticket},
+    pool::{DispenserPool, DispenserState},
+    resources::{coffee_time, foam_time, grind_time, water_time, whip_time},
+    resources_monitor::Levels,
+    Error, RefillPolicy, Resources,
+};
+
+/// Expected time to make the ingredients with the given levels of the containers,
+/// grinding or whipping first if there isn't enough coffee or foam.
+/// The levels are left as they would be after making it.
+pub fn expected_time(
+    ingredients: &Ingredients,
+    levels: &mut Levels,
+    refill: RefillPolicy,
+) -> Duration {
+    let mut millis = coffee_time(ingredients.coffee)
+        + water_time(ingredients.water)
+        + foam_time(ingredients.foam);
+    let (coffee, beans) = (&mut levels.coffee, &mut levels.coffee_beans);
+    let ground = transform(ingredients.coffee, coffee, beans, config::C, refill);
+    if ground > 0 {
+        millis += grind_time(ground);
+    }
+    let whipped = transform(
+        ingredients.foam,
+        &mut levels.foam,
+        &mut levels.milk,
+        config::E,
+        refill,
+    );
+    if whipped > 0 {
+        millis += whip_time(whipped);
+    }
+    Duration::from_millis(millis.into())
+}
+
+/// Takes `amount` from `stored`, transforming from `source` first if there isn't enough.
+/// Returns the amount transformed. If there isn't enough to transform, nothing changes,
+/// as the order is expected to fail.
+fn transform(
+    amount: u32,
+    stored: &mut u32,
+    source: &mut u32,
+    capacity: u32,
+    refill: RefillPolicy,
+) -> u32 {
+    let needed = amount.saturating_sub(*stored);
+    if needed > *source {
+        return 0;
+    }
+    let transformed = match needed {
+        0 => 0,
+        needed => refill.amount(needed, capacity.saturating_sub(*stored), *source),
+    };
+    *source -= transformed;
+    *stored = (*stored + transformed).saturating_sub(amount);
+    transformed
+}
+
+/// Estimates when each order in progress or in the queue will be ready.
+///
+/// Each running dispenser takes the first queued order it can make as soon as it is free,
+/// as it does when making them; each order taking its `expected_time` from the levels left by
+/// those before it. Waits for the locks are not accounted for.
+/// Orders no running dispenser can make have no estimate.
+fn estimate(
+    now: Instant,
+    in_progress: &HashMap<usize, Ticket>,
+    queue: &[Ticket],
+    dispensers: &[(usize, Capabilities, DispenserState)],
+    mut levels: Levels,
+    refill: RefillPolicy,
+) -> Vec<(u64, Instant)> {
+    let mut estimates = Vec::new();
+    let mut free: Vec<(Instant, usize, Capabilities)> = Vec::new();
+    for (id, capabilities, state) in dispensers {
+        let mut free_at = now;
+        if let Some(ticket) = in_progress.get(id) {
+            let started = ticket.started_at.unwrap_or(now);
+            // its ingredients may already be taken from the levels
+            let expected = expected_time(&ticket.ingredients, &mut levels.clone(), refill);
+            free_at = (started + expected).max(now);
+            estimates.push((ticket.id, free_at));
+        }
+        if *state == DispenserState::Running {
+            free.push((free_at, *id, *capabilities));
+        }
+    }
+
+    let mut queue: Vec<&Ticket> = queue.iter().collect();
+    while !queue.is_empty() {
+        let Some(next) = (0..free.len()).min_by_key(|i| (free[*i].0, free[*i].1)) else {
+            break;
+        };
+        let (free_at, _, capabilities) = free[next];
+        match queue
+            .iter()
+            .position(|ticket| capabilities.can_make(&ticket.ingredients))
+        {
+            Some(i) => {
+                let ticket = queue.remove(i);
+                let ready = free_at + expected_time(&ticket.ingredients, &mut levels, refill);
+                estimates.push((ticket.id, ready));
+                free[next].0 = ready;
+            }
+            // it won't take any of the orders left
+            None => {
+                free.remove(next);
+            }
+        }
+    }
+    estimates
+}
+
+/// How close the estimates given when orders were queued were to when they were made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EtaAccuracy {
+    /// Difference between the estimate and when the order was made, either way.
+    pub error: Summary,
+    /// Orders made after their estimate.
+    pub late: usize,
+}
+
+impl fmt::Display for EtaAccuracy {
+    /// Displays the accuracy in a single line, in milliseconds.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "ETA error: {} orders, mean {:.2}ms, p95 {:.2}ms, {} late",
+            self.error.count,
+            millis(self.error.mean),
+            millis(self.error.p95),
+            self.late
+        )
+    }
+}
+
+/// What the orders given an estimate so far are expected to leave behind, kept up to date
+/// as orders are queued and dispensers come and go, so that each estimate takes the same time
+/// however long the queue is.
+struct Projection {
+    /// When each running dispenser is expected to be free, and what it can make.
+    free: HashMap<usize, (Capabilities, Instant)>,
+    /// Levels of the containers once those orders are made.
+    levels: Levels,
+    /// Levels after the latest action of the coffee maker, and its sequence number.
+    latest: (u64, Levels),
+}
+
+/// Estimates when orders will be ready, from the queue, the dispensers and the levels of the
+/// containers of a coffee maker.
+///
+/// As an observer, it keeps the orders in progress, and gives every order an estimate when it
+/// is queued, to compare with when it was made. Those estimates are projected from the ones
+/// given before, without looking at the coffee maker. It only keeps weak references to the
+/// coffee maker, which owns it through its observers.
+pub struct Eta {
+    orders: Weak<Orders>,
+    pool: Weak<DispenserPool>,
+    resources: Weak<Resources>,
+    refill: RefillPolicy,
+    projection: Mutex<Projection>,
+    /// Order each dispenser is making.
+    in_progress: Mutex<HashMap<usize, Ticket>>,
+    /// Estimate given to each order when it was queued, until it is made.
+    promised: Mutex<HashMap<u64, Instant>>,
+    /// How wrong each estimate was, and whether the order was late.
+    errors: Mutex<Vec<(Duration, bool)>>,
+}
+
+impl Eta {
+    /// Creates a new Eta instance for the coffee maker with the given queue, dispensers and resources.
+    /// It has to be registered as an observer of both the queue and the resources.
+    pub fn new(
+        orders: &Arc<Orders>,
+        pool: &Arc<DispenserPool>,
+        resources: &Arc<Resources>,
+    ) -> Arc<Eta> {
+        let now = Instant::now();
+        let free = pool
+            .list()
+            .into_iter()
+            .filter(|(_, _, state)| *state == DispenserState::Running)
+            .map(|(id, capabilities, _)| (id, (capabilities, now)))
+            .collect();
+        let levels = resources.levels();
+        Arc::new(Eta {
+            orders: Arc::downgrade(orders),
+            pool: Arc::downgrade(pool),
+            resources: Arc::downgrade(resources),
+            refill: resources.refill_policy(),
+            projection: Mutex::new(Projection {
+                free,
+                levels,
+                latest: (0, levels),
+            }),
+            in_progress: Mutex::new(HashMap::new()),
+            promised: Mutex::new(HashMap::new()),
+            errors: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Estimates every order in progress or queued.
+    fn estimate_all(&self) -> Vec<(u64, Instant)> {
+        let (Some(orders), Some(pool), Some(resources)) = (
+            self.orders.upgrade(),
+            self.pool.upgrade(),
+            self.resources.upgrade(),
+        ) else {
+            return Vec::new();
+        };
+        let in_progress = self
+            .in_progress
+            .lock()
+            .expect("Failed to lock orders in progress")
+            .clone();
+        estimate(
+            Instant::now(),
+            &in_progress,
+            &orders.tickets(),
+            &pool.list(),
+            resources.levels(),
+            self.refill,
+        )
+    }
+
+    /// Estimates when the order with the given id will be ready, looking at the coffee maker
+    /// as it is now. Returns `None` if it isn't queued nor being made, or no dispenser can make it.
+    pub fn ready_time(&self, order_id: u64) -> Option<Instant> {
+        self.estimate_all()
+            .into_iter()
+            .find(|(id, _)| *id == order_id)
+            .map(|(_, ready)| ready)
+    }
+
+    /// Estimate given to the order with the given id when it was queued, if it isn't made yet.
+    pub fn promised(&self, order_id: u64) -> Option<Instant> {
+        let promised = self.promised.lock().expect("Failed to lock estimates");
+        promised.get(&order_id).copied()
+    }
+
+    /// Returns how accurate the estimates of the orders made so far were.
+    pub fn accuracy(&self) -> EtaAccuracy {
+        let errors = self.errors.lock().expect("Failed to lock estimate errors");
+        EtaAccuracy {
+            error: Summary::of(errors.iter().map(|(error, _)| *error).collect()),
+            late: errors.iter().filter(|(_, late)| *late).count(),
+        }
+    }
+
+    /// Projects when the given ingredients will be ready if queued now: the running dispenser
+    /// that can make them and is free the soonest takes them, after the orders projected before.
+    fn project(&self, ingredients: &Ingredients) -> Option<Instant> {
+        let now = Instant::now();
+        let mut projection = self.projection.lock().expect("Failed to lock projection");
+        let Projection { free, levels, .. } = &mut *projection;
+        let (_, free_at) = free
+            .iter_mut()
+            .filter(|(_, (capabilities, _))| capabilities.can_make(ingredients))
+            .map(|(id, (_, free_at))| (*id, free_at))
+            .min_by_key(|(id, free_at)| ((**free_at).max(now), *id))?;
+        let ready = (*free_at).max(now) + expected_time(ingredients, levels, self.refill);
+        *free_at = ready;
+        Some(ready)
+    }
+
+    /// Forgets the order the dispenser was making.
+    fn finished(&self, dispenser: usize) {
+        let mut in_progress = self
+            .in_progress
+            .lock()
+            .expect("Failed to lock orders in progress");
+        in_progress.remove(&dispenser);
+    }
+
+    /// Forgets the estimate of an order, returning it.
+    /// Once no order is waiting for its estimate, the projection starts again from the levels
+    /// the coffee maker was left with, so that the orders which weren't made don't count.
+    fn forget(&self, order_id: u64) -> Option<Instant> {
+        let mut promised = self.promised.lock().expect("Failed to lock estimates");
+        let estimate = promised.remove(&order_id);
+        let caught_up = promised.is_empty();
+        drop(promised);
+        if caught_up {
+            let mut projection = self.projection.lock().expect("Failed to lock projection");
+            projection.levels = projection.latest.1;
+        }
+        estimate
+    }
+}
+
+impl Observer for Eta {
+    fn order_queued(&self, ticket: &Ticket) {
+        // orders queued again keep their first estimate
+        if self.promised(ticket.id).is_some() {
+            return;
+        }
+        if let Some(ready) = self.project(&ticket.ingredients) {
+            let mut promised = self.promised.lock().expect("Failed to lock estimates");
+            promised.insert(ticket.id, ready);
+        }
+    }
+
+    fn order_started(&self, dispenser: usize, ticket: &Ticket) {
+        let mut in_progress = self
+            .in_progress
+            .lock()
+            .expect("Failed to lock orders in progress");
+        in_progress.insert(dispenser, *ticket);
+    }
+
+    fn action_performed(&self, event: &ActionEvent) {
+        let mut projection = self.projection.lock().expect("Failed to lock projection");
+        if event.sequence > projection.latest.0 {
+            projection.latest = (event.sequence, event.levels);
+        }
+    }
+
+    fn order_completed(&self, dispenser: usize, ticket: &Ticket) {
+        self.finished(dispenser);
+        if let Some(promised) = self.forget(ticket.id) {
+            let made = ticket.finished_at.unwrap_or_else(Instant::now);
+            let error = match made > promised {
+                true => (made - promised, true),
+                false => (promised - made, false),
+            };
+            let mut errors = self.errors.lock().expect("Failed to lock estimate errors");
+            errors.push(error);
+        }
+    }
+
+    fn order_retried(&self, dispenser: usize, _ticket: &Ticket, _error: &Error) {
+        self.finished(dispenser);
+    }
+
+    fn order_failed(&self, dispenser: usize, ticket: &Ticket, _error: &Error) {
+        self.finished(dispenser);
+        self.forget(ticket.id);
+    }
+
+    fn order_abandoned(&self, ticket: &Ticket) {
+        self.forget(ticket.id);
+    }
+
+    fn dispenser_started(&self, dispenser: usize, capabilities: Capabilities) {
+        let mut projection = self.projection.lock().expect("Failed to lock projection");
+        projection
+            .free
+            .insert(dispenser, (capabilities, Instant::now()));
+    }
+
+    fn dispenser_draining(&self, dispenser: usize) {
+        let mut projection = self.projection.lock().expect("Failed to lock projection");
+        projection.free.remove(&dispenser);
+    }
+}
+
+#[cfg(test)]
+mod eta_tests {
+    use super::*;
+    use crate::coffee_maker::{builder::CoffeeMakerBuilder, machine::CoffeeMaker, orders::Order};
+
+    fn millis(amount: u32) -> Duration {
+        Duration::from_millis(amount.into())
+    }
+
+    #[test]
+    fn expects_grinding_and_whipping() {
+        let mut levels = Levels {
+            coffee: 10,
+            coffee_beans: 100,
+            foam: 50,
+            milk: 0,
+        };
+        let drink = Ingredients::new(30, 10, 20);
+        let expected = expected_time(&drink, &mut levels, RefillPolicy::JustEnough);
+        assert_eq!(
+            expected,
+            millis(grind_time(20) + coffee_time(30) + water_time(10) + foam_time(20))
+        );
+        assert_eq!(levels.coffee, 0);
+        assert_eq!(levels.coffee_beans, 80);
+        assert_eq!(levels.foam, 30);
+
+        // not enough milk: it fails without whipping
+        let drink = Ingredients::new(0, 0, 40);
+        let expected = expected_time(&drink, &mut levels, RefillPolicy::JustEnough);
+        assert_eq!(
+            expected,
+            millis(coffee_time(0) + water_time(0) + foam_time(40))
+        );
+        assert_eq!(levels.foam, 30);
+    }
+
+    #[test]
+    fn dispensers_take_the_orders_as_they_are_free() {
+        let now = Instant::now();
+        let levels = Levels {
+            coffee: config::C,
+            coffee_beans: 0,
+            foam: config::E,
+            milk: 0,
+        };
+        let espresso = Ingredients::new(10, 0, 0);
+        let each = expected_time(&espresso, &mut levels.clone(), RefillPolicy::JustEnough);
+        let mut busy = Ticket::new(1, espresso, None);
+        busy.started_at = Some(now);
+        let in_progress = HashMap::from([(0, busy)]);
+        let queue = [
+            Ticket::new(2, espresso, None),
+            Ticket::new(3, espresso, None),
+            Ticket::new(4, espresso, None),
+        ];
+        let dispensers = [
+            (0, Capabilities::ALL, DispenserState::Running),
+            (1, Capabilities::ALL, DispenserState::Running),
+            (2, Capabilities::ALL, DispenserState::Draining),
+        ];
+
+        let estimates = estimate(
+            now,
+            &in_progress,
+            &queue,
+            &dispensers,
+            levels,
+            RefillPolicy::JustEnough,
+        );
+        assert_eq!(
+            estimates,
+            vec![
+                (1, now + each),
+                (2, now + each),
+                (3, now + each * 2),
+                (4, now + each * 2),
+            ]
+        );
+
+        // nobody running can make foam
+        let dispensers = [(0, "coffee+water".parse().unwrap(), DispenserState::Running)];
+        let queue = [
+            Ticket::new(5, Ingredients::new(0, 0, 10), None),
+            Ticket::new(6, espresso, None),
+        ];
+        let estimates = estimate(
+            now,
+            &HashMap::new(),
+            &queue,
+            &dispensers,
+            levels,
+            RefillPolicy::JustEnough,
+        );
+        assert_eq!(estimates, vec![(6, now + each)]);
+    }
+
+    #[test]
+    fn queued_orders_are_projected_after_the_ones_before() {
+        let orders = Orders::new();
+        let resources = Resources::new(config::C, 0, config::E, 0).unwrap();
+        let pool = DispenserPool::new(orders.clone(), resources.clone());
+        let eta = Eta::new(&orders, &pool, &resources);
+        eta.dispenser_started(0, Capabilities::ALL);
+        eta.dispenser_started(1, "coffee+water".parse().unwrap());
+        let espresso = Ingredients::new(10, 0, 0);
+        let each = expected_time(&espresso, &mut Levels::default(), RefillPolicy::JustEnough);
+
+        let before = Instant::now();
+        for id in 1..=4 {
+            eta.order_queued(&Ticket::new(id, espresso, None));
+        }
+        eta.order_queued(&Ticket::new(5, Ingredients::new(0, 0, 10), None));
+        let after = Instant::now();
+        let promised = |id| eta.promised(id).unwrap();
+        assert!(promised(1) >= before + each && promised(1) <= after + each);
+        assert!(promised(3) >= before + each * 2 && promised(3) <= after + each * 2);
+        // only the first dispenser makes foam, after its two espressos
+        assert!(promised(5) > promised(4));
+
+        // orders queued again keep their estimate, and drained dispensers take no more orders
+        eta.order_queued(&Ticket::new(1, espresso, None));
+        assert_eq!(eta.promised(1), Some(promised(1)));
+        eta.dispenser_draining(0);
+        eta.order_queued(&Ticket::new(6, Ingredients::new(0, 0, 10), None));
+        assert_eq!(eta.promised(6), None);
+    }
+
+    #[test]
+    fn estimates_are_given_when_queued_and_checked() {
+        let machine = CoffeeMakerBuilder::new()
+            .resources(100, 0, 100, 0)
+            .dispensers(vec![Capabilities::ALL])
+            .estimates()
+            .build()
+            .unwrap();
+        let (id, ready_at) = machine.take_order(Ingredients::new(10, 10, 10)).unwrap();
+        assert!(ready_at.unwrap() >= Instant::now() - Duration::from_secs(1));
+        machine.orders().push(Order::from(10, 0, 0).unwrap());
+        assert!(machine.take_order(Ingredients::new(0, 0, 2000)).is_ok());
+
+        let report = machine.shutdown();
+        assert_eq!(id, 1);
+        assert_eq!(machine.estimate_ready_time(id), None);
+        // the last one fails, so it isn't checked
+        assert_eq!(report.eta.unwrap().error.count, 2);
+        assert!(report.to_string().contains("ETA error: 2 orders"));
+    }
+
+    #[test]
+    fn estimates_are_opt_in() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &[Capabilities::ALL]);
+        let (_, ready_at) = machine.take_order(Ingredients::new(10, 10, 10)).unwrap();
+        assert_eq!(ready_at, None);
+
+        let report = machine.shutdown();
+        assert_eq!(report.eta, None);
+        assert!(!report.to_string().contains("ETA"));
+    }
+}
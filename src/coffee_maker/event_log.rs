@@ -0,0 +1,124 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use super::observer::{ActionEvent, Observer};
+
+/// Writes every action performed by the actuators as a JSON object per line.
+///
+/// Each line looks like:
+/// `{"seq":12,"timestamp_ms":1700000000000,"dispenser":0,"order":3,"action":"grind","amount":10,"duration_ms":50,"levels":{"coffee":10,"coffee_beans":90,"foam":0,"milk":0}}`
+/// where `dispenser` and `order` are `null` if the action wasn't made for an order.
+pub struct EventLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventLog {
+    /// Creates a new EventLog instance writing to the given writer.
+    pub fn new(writer: Box<dyn Write + Send>) -> Arc<EventLog> {
+        Arc::new(EventLog {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Creates a new EventLog instance writing to the file at the given path,
+    /// which is truncated if it exists.
+    pub fn create(path: &str) -> io::Result<Arc<EventLog>> {
+        let file = File::create(path)?;
+        Ok(EventLog::new(Box::new(BufWriter::new(file))))
+    }
+}
+
+/// Formats an optional number as JSON.
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+/// Formats an event as a single line JSON object.
+pub fn to_json(event: &ActionEvent) -> String {
+    let timestamp = event
+        .at
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    format!(
+        "{{\"seq\":{},\"timestamp_ms\":{},\"dispenser\":{},\"order\":{},\"action\":\"{}\",\"amount\":{},\"duration_ms\":{},\"levels\":{{\"coffee\":{},\"coffee_beans\":{},\"foam\":{},\"milk\":{}}}}}",
+        event.sequence,
+        timestamp,
+        json_option(event.context.dispenser),
+        json_option(event.context.order),
+        event.action,
+        event.amount,
+        event.duration.as_millis(),
+        event.levels.coffee,
+        event.levels.coffee_beans,
+        event.levels.foam,
+        event.levels.milk,
+    )
+}
+
+impl Observer for EventLog {
+    fn action_performed(&self, event: &ActionEvent) {
+        let mut writer = self.writer.lock().expect("Failed to lock event log");
+        // flushed on every event so the log is complete even if the process dies;
+        // losing an event is better than stopping the coffee maker
+        let _ = writeln!(writer, "{}", to_json(event)).and_then(|_| writer.flush());
+    }
+}
+
+#[cfg(test)]
+mod event_log_tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::coffee_maker::{
+        observer::{Action, Context},
+        resources_monitor::Levels,
+        test_utils::SharedWriter,
+        Resources,
+    };
+
+    #[test]
+    fn formats_events() {
+        let event = ActionEvent {
+            sequence: 5,
+            started: std::time::Instant::now(),
+            at: UNIX_EPOCH + Duration::from_millis(1234),
+            context: Context::new(2, 7),
+            action: Action::PourFoam,
+            amount: 10,
+            duration: Duration::from_millis(24),
+            levels: Levels {
+                coffee: 1,
+                coffee_beans: 2,
+                foam: 3,
+                milk: 4,
+            },
+        };
+        assert_eq!(
+            to_json(&event),
+            "{\"seq\":5,\"timestamp_ms\":1234,\"dispenser\":2,\"order\":7,\"action\":\"pour_foam\",\"amount\":10,\"duration_ms\":24,\"levels\":{\"coffee\":1,\"coffee_beans\":2,\"foam\":3,\"milk\":4}}"
+        );
+    }
+
+    #[test]
+    fn logs_a_line_per_action() {
+        let writer = SharedWriter::default();
+        let resources = Resources::new(0, 100, 0, 0).unwrap();
+        resources.register_observer(EventLog::new(Box::new(writer.clone())));
+
+        resources.use_coffee(10).unwrap();
+        resources.use_water(10).unwrap();
+
+        let written = writer.written();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("\"action\":\"grind\""));
+        assert!(lines[0].contains("\"dispenser\":null"));
+        assert!(lines[1].contains("\"action\":\"pour_coffee\""));
+        assert!(lines[1].contains("\"coffee\":0,\"coffee_beans\":90"));
+        assert!(lines[2].contains("\"action\":\"pour_water\""));
+    }
+}
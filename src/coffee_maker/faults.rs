@@ -0,0 +1,219 @@
+use std::{fmt, str::FromStr, sync::Mutex};
+
+use super::rng::Rng;
+
+/// How much slower a faulty actuator works.
+pub const SLOW_ACTUATOR_FACTOR: u32 = 3;
+
+/// Hardware faults that can be injected into the coffee maker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// The actuator gets stuck and the operation is aborted.
+    Jam,
+    /// The actuator works, but takes longer than it should.
+    SlowActuator,
+    /// The dispenser stops pouring after the coffee, wasting it and ruining the drink.
+    PartialPour,
+    /// A container sensor reports a wrong level, aborting the operation.
+    SensorMisread,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 4] = [
+        Fault::Jam,
+        Fault::SlowActuator,
+        Fault::PartialPour,
+        Fault::SensorMisread,
+    ];
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Fault::Jam => "jam",
+            Fault::SlowActuator => "slow actuator",
+            Fault::PartialPour => "partial pour",
+            Fault::SensorMisread => "sensor misread",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parts of the coffee maker where faults can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Actuator {
+    Dispenser,
+    Grinder,
+    Whipper,
+}
+
+impl Actuator {
+    /// Returns whether the given fault can happen on this actuator.
+    fn can_suffer(&self, fault: Fault) -> bool {
+        match self {
+            Actuator::Dispenser => fault != Fault::SensorMisread,
+            Actuator::Grinder | Actuator::Whipper => fault != Fault::PartialPour,
+        }
+    }
+}
+
+/// Probabilities (in `[0, 1]`) of each fault happening on every actuator use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    pub seed: u64,
+    pub jam: f64,
+    pub slow_actuator: f64,
+    pub partial_pour: f64,
+    pub sensor_misread: f64,
+}
+
+impl FaultConfig {
+    /// Creates a config with the given seed and no faults.
+    pub fn new(seed: u64) -> FaultConfig {
+        FaultConfig {
+            seed,
+            jam: 0.0,
+            slow_actuator: 0.0,
+            partial_pour: 0.0,
+            sensor_misread: 0.0,
+        }
+    }
+
+    fn probability(&self, fault: Fault) -> f64 {
+        match fault {
+            Fault::Jam => self.jam,
+            Fault::SlowActuator => self.slow_actuator,
+            Fault::PartialPour => self.partial_pour,
+            Fault::SensorMisread => self.sensor_misread,
+        }
+    }
+}
+
+impl FromStr for FaultConfig {
+    type Err = String;
+
+    /// Parses a config in the format `seed=<u64>,jam=<f64>,slow=<f64>,partial=<f64>,misread=<f64>`.
+    /// Every key is optional.
+    fn from_str(s: &str) -> Result<FaultConfig, String> {
+        let mut config = FaultConfig::new(0);
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("Invalid fault setting: {}", pair))?;
+
+            if key == "seed" {
+                config.seed = value.parse().or(Err(format!("Invalid seed: {}", value)))?;
+                continue;
+            }
+
+            let probability: f64 = value
+                .parse()
+                .or(Err(format!("Invalid probability: {}", value)))?;
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!("Probability out of range: {}", value));
+            }
+            match key {
+                "jam" => config.jam = probability,
+                "slow" => config.slow_actuator = probability,
+                "partial" => config.partial_pour = probability,
+                "misread" => config.sensor_misread = probability,
+                _ => return Err(format!("Unknown fault: {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Decides, in a reproducible way, when faults happen.
+/// Can be used thread-safely.
+pub struct FaultInjector {
+    config: FaultConfig,
+    rng: Mutex<Rng>,
+}
+
+impl FaultInjector {
+    /// Creates a new FaultInjector instance.
+    pub fn new(config: FaultConfig) -> FaultInjector {
+        FaultInjector {
+            config,
+            rng: Mutex::new(Rng::new(config.seed)),
+        }
+    }
+
+    /// Rolls for a fault on the given actuator.
+    /// At most one fault is returned, checked in the order of `Fault::ALL`.
+    pub fn roll(&self, actuator: Actuator) -> Option<Fault> {
+        let mut rng = self.rng.lock().expect("Failed to lock fault rng");
+        Fault::ALL
+            .into_iter()
+            .filter(|fault| actuator.can_suffer(*fault))
+            .find(|fault| rng.chance(self.config.probability(*fault)))
+    }
+}
+
+#[cfg(test)]
+mod fault_config_tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let config: FaultConfig = "seed=42,jam=0.1,slow=0.2,partial=0.3,misread=0.4"
+            .parse()
+            .unwrap();
+        assert_eq!(config.seed, 42);
+        assert_eq!(config.jam, 0.1);
+        assert_eq!(config.slow_actuator, 0.2);
+        assert_eq!(config.partial_pour, 0.3);
+        assert_eq!(config.sensor_misread, 0.4);
+    }
+
+    #[test]
+    fn parse_partial_config() {
+        let config: FaultConfig = "jam=0.5".parse().unwrap();
+        assert_eq!(config.seed, 0);
+        assert_eq!(config.jam, 0.5);
+        assert_eq!(config.slow_actuator, 0.0);
+    }
+
+    #[test]
+    fn parse_invalid_config() {
+        let invalid = ["jam", "jam=2", "jam=abc", "seed=-1", "fire=0.1"];
+        for input in invalid {
+            assert!(input.parse::<FaultConfig>().is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod fault_injector_tests {
+    use super::*;
+
+    #[test]
+    fn no_faults_by_default() {
+        let injector = FaultInjector::new(FaultConfig::new(1));
+        for _ in 0..100 {
+            assert_eq!(injector.roll(Actuator::Dispenser), None);
+        }
+    }
+
+    #[test]
+    fn certain_faults_always_happen() {
+        let mut config = FaultConfig::new(1);
+        config.partial_pour = 1.0;
+        let injector = FaultInjector::new(config);
+        assert_eq!(injector.roll(Actuator::Dispenser), Some(Fault::PartialPour));
+        // grinders can't pour
+        assert_eq!(injector.roll(Actuator::Grinder), None);
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let mut config = FaultConfig::new(7);
+        config.jam = 0.5;
+        let a = FaultInjector::new(config);
+        let b = FaultInjector::new(config);
+        for _ in 0..100 {
+            assert_eq!(a.roll(Actuator::Grinder), b.roll(Actuator::Grinder));
+        }
+    }
+}
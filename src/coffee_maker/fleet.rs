@@ -0,0 +1,377 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use super::{
+    machine::CoffeeMaker,
+    observer::{Observer, Observers},
+    orders::{Ingredients, Orders, Ticket},
+    resources_monitor::{spawn_monitor, MonitorHandle, MonitorMode},
+};
+
+/// Counters of a single coffee maker in the fleet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineStats {
+    pub name: String,
+    pub dispatched: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub retried: u64,
+}
+
+/// Counters of the whole fleet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FleetStats {
+    pub machines: Vec<MachineStats>,
+    /// Orders that no coffee maker could take.
+    pub unroutable: u64,
+}
+
+impl FleetStats {
+    /// Amount of orders fulfilled by every coffee maker.
+    pub fn completed(&self) -> u64 {
+        self.machines.iter().map(|machine| machine.completed).sum()
+    }
+
+    /// Amount of orders that could not be fulfilled by any coffee maker.
+    pub fn failed(&self) -> u64 {
+        self.machines.iter().map(|machine| machine.failed).sum()
+    }
+}
+
+impl fmt::Display for FleetStats {
+    /// Displays a line per coffee maker, followed by the totals.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for machine in &self.machines {
+            writeln!(
+                f,
+                "{}: dispatched {}, completed {}, failed {}, retried {}",
+                machine.name,
+                machine.dispatched,
+                machine.completed,
+                machine.failed,
+                machine.retried
+            )?;
+        }
+        writeln!(
+            f,
+            "Total: completed {}, failed {}, unroutable {}",
+            self.completed(),
+            self.failed(),
+            self.unroutable
+        )
+    }
+}
+
+/// Several coffee makers sharing a single stream of orders.
+/// Can be used thread-safely.
+pub struct Fleet {
+    machines: Vec<CoffeeMaker>,
+    dispatched: Vec<AtomicU64>,
+    unroutable: AtomicU64,
+    observers: Observers,
+}
+
+impl Fleet {
+    /// Creates a new Fleet instance with the given coffee makers.
+    pub fn new(machines: Vec<CoffeeMaker>) -> Arc<Fleet> {
+        let dispatched = machines.iter().map(|_| AtomicU64::new(0)).collect();
+        Arc::new(Fleet {
+            machines,
+            dispatched,
+            unroutable: AtomicU64::new(0),
+            observers: Observers::new(),
+        })
+    }
+
+    /// Registers an observer of the orders no coffee maker could take.
+    pub fn register_observer(&self, observer: Arc<dyn Observer>) {
+        self.observers.register(observer);
+    }
+
+    /// Coffee makers of the fleet.
+    pub fn machines(&self) -> &[CoffeeMaker] {
+        &self.machines
+    }
+
+    /// Chooses the coffee maker for an order.
+    /// Among those that can take it, the least loaded one is chosen;
+    /// breaking ties by the most remaining resources.
+    /// Returns `None` if no coffee maker can take it.
+    pub fn route(&self, ingredients: &Ingredients) -> Option<usize> {
+        self.machines
+            .iter()
+            .enumerate()
+            .filter(|(_, machine)| machine.can_take(ingredients))
+            .map(|(i, machine)| (i, machine.load(), machine.resources().remaining()))
+            .filter(|(_, load, _)| load.is_finite())
+            .min_by(|(_, load_a, remaining_a), (_, load_b, remaining_b)| {
+                load_a.total_cmp(load_b).then(remaining_b.cmp(remaining_a))
+            })
+            .map(|(i, _, _)| i)
+    }
+
+    /// Sends an order to the chosen coffee maker.
+    /// Returns whether it could be sent; if not, it is rejected as if it were taken by a queue.
+    pub fn dispatch_ticket(&self, ticket: Ticket) -> bool {
+        match self.route(&ticket.ingredients) {
+            Some(i) => {
+                self.machines[i].orders().push_ticket(ticket);
+                self.dispatched[i].fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => {
+                self.unroutable.fetch_add(1, Ordering::Relaxed);
+                self.observers
+                    .notify(|observer| observer.order_rejected(&ticket.ingredients, ticket.origin));
+                false
+            }
+        }
+    }
+
+    /// Starts sending the orders from the source to the coffee makers in a new thread.
+    /// When there are no more orders, waits for every coffee maker to finish.
+    /// Returns a handle to the thread.
+    pub fn dispatch(self: &Arc<Self>, source: Arc<Orders>) -> JoinHandle<()> {
+        let fleet = self.clone();
+        thread::spawn(move || {
+            while let Some(ticket) = source.pop_ticket() {
+                fleet.dispatch_ticket(ticket);
+            }
+            for machine in fleet.machines() {
+                machine.shutdown();
+            }
+        })
+    }
+
+    /// Returns the counters of every coffee maker.
+    pub fn stats(&self) -> FleetStats {
+        let machines = self
+            .machines
+            .iter()
+            .zip(&self.dispatched)
+            .map(|(machine, dispatched)| {
+                let stats = machine.resources().stats();
+                MachineStats {
+                    name: machine.name().to_string(),
+                    dispatched: dispatched.load(Ordering::Relaxed),
+                    completed: stats.completed(),
+                    failed: stats.failed(),
+                    retried: stats.retried(),
+                }
+            })
+            .collect();
+
+        FleetStats {
+            machines,
+            unroutable: self.unroutable.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the status of every coffee maker, as shown by the monitor.
+    pub fn status(&self) -> String {
+        self.machines
+            .iter()
+            .map(|machine| {
+                format!(
+                    "[{}] Queue: {}\n{}",
+                    machine.name(),
+                    machine.orders().len(),
+                    machine.resources().status()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Renders a snapshot of every coffee maker in the given mode, the last one with the totals.
+    pub fn render(&self, mode: MonitorMode, last: bool) -> String {
+        if mode == MonitorMode::Dashboard {
+            let mut status = format!("{}[2J{}", 27 as char, self.status()); // clear screen
+            if last {
+                for machine in &self.machines {
+                    status += &format!("\n[{}] {}", machine.name(), machine.resources().totals());
+                }
+            }
+            return status;
+        }
+        self.machines
+            .iter()
+            .map(|machine| match last {
+                true => machine.resources().render_final(mode, Some(machine.name())),
+                false => machine.resources().render(mode, Some(machine.name())),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Starts the fleet monitor in a new thread.
+    /// This will print the status of every coffee maker at an interval, in the given mode,
+    /// as well as whenever a snapshot is requested; and a final one with the totals when stopped.
+    /// Returns a handle to ask the monitor for snapshots and to stop it.
+    pub fn monitor(self: &Arc<Self>, interval_millis: u64, mode: MonitorMode) -> MonitorHandle {
+        self.monitor_to(interval_millis, mode, Box::new(io::stdout()))
+    }
+
+    /// Starts the fleet monitor like `monitor`, writing to the given output.
+    pub fn monitor_to(
+        self: &Arc<Self>,
+        interval_millis: u64,
+        mode: MonitorMode,
+        output: Box<dyn Write + Send>,
+    ) -> MonitorHandle {
+        let fleet = self.clone();
+        spawn_monitor(interval_millis, output, move |last| {
+            fleet.render(mode, last)
+        })
+    }
+}
+
+#[cfg(test)]
+mod fleet_tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::coffee_maker::{
+        capabilities::Capabilities,
+        orders::{Order, Origin},
+        test_utils::SharedWriter,
+        Resources,
+    };
+
+    /// Keeps the orders rejected.
+    #[derive(Default)]
+    struct Rejections(Mutex<Vec<(Ingredients, Option<Origin>)>>);
+
+    impl Observer for Rejections {
+        fn order_rejected(&self, ingredients: &Ingredients, origin: Option<Origin>) {
+            let mut rejected = self.0.lock().expect("Failed to lock rejections");
+            rejected.push((*ingredients, origin));
+        }
+    }
+
+    fn idle_machine(name: &str, coffee: u32) -> CoffeeMaker {
+        let resources = Resources::new(coffee, 0, 0, 0).unwrap();
+        let machine = CoffeeMaker::new(name, resources, &[]);
+        // a dispenser that can't pour water, so the water orders stay queued
+        machine.pool().add("coffee".parse().unwrap());
+        machine
+    }
+
+    fn water() -> Order {
+        Order::from(0, 10, 0).unwrap()
+    }
+
+    fn coffee(amount: u32) -> Ingredients {
+        Ingredients {
+            coffee: amount,
+            water: 0,
+            foam: 0,
+        }
+    }
+
+    #[test]
+    fn routes_to_least_loaded_machine() {
+        let fleet = Fleet::new(vec![idle_machine("a", 100), idle_machine("b", 100)]);
+        fleet.machines()[0].orders().push(water());
+
+        assert_eq!(fleet.route(&coffee(10)), Some(1));
+        fleet.machines()[1].orders().push(water());
+        fleet.machines()[1].orders().push(water());
+        assert_eq!(fleet.route(&coffee(10)), Some(0));
+
+        for machine in fleet.machines() {
+            machine.pool().remove(0).unwrap();
+        }
+    }
+
+    #[test]
+    fn routes_to_machine_with_more_resources_on_ties() {
+        let fleet = Fleet::new(vec![idle_machine("a", 50), idle_machine("b", 100)]);
+        assert_eq!(fleet.route(&coffee(10)), Some(1));
+        // only b has enough
+        assert_eq!(fleet.route(&coffee(80)), Some(1));
+        assert_eq!(fleet.route(&coffee(101)), None);
+
+        for machine in fleet.machines() {
+            machine.pool().remove(0).unwrap();
+        }
+    }
+
+    #[test]
+    fn dispatches_every_order() {
+        let machines = (0..2)
+            .map(|i| {
+                let resources = Resources::new(100, 0, 100, 0).unwrap();
+                CoffeeMaker::new(&i.to_string(), resources, &[Capabilities::ALL])
+            })
+            .collect();
+        let fleet = Fleet::new(machines);
+        let source = Orders::new();
+        let dispatcher = fleet.dispatch(source.clone());
+
+        for _ in 0..10 {
+            source.push(Order::from(10, 10, 10).unwrap());
+        }
+        // nobody has this much coffee
+        source.push(Order::from(1000, 0, 0).unwrap());
+        source.push(Order::NoMoreOrders);
+        dispatcher.join().unwrap();
+
+        let stats = fleet.stats();
+        assert_eq!(stats.completed(), 10);
+        assert_eq!(stats.failed(), 0);
+        assert_eq!(stats.unroutable, 1);
+        let dispatched: u64 = stats.machines.iter().map(|m| m.dispatched).sum();
+        assert_eq!(dispatched, 10);
+    }
+
+    #[test]
+    fn unroutable_orders_are_rejected_with_their_origin() {
+        let fleet = Fleet::new(vec![idle_machine("a", 50)]);
+        let rejections = Arc::new(Rejections::default());
+        fleet.register_observer(rejections.clone());
+        let source = Orders::new();
+        let origin = Origin {
+            source: 1,
+            position: 4,
+        };
+        source.take_from(coffee(80), None, Some(origin)).unwrap();
+
+        let ticket = source.pop_ticket().unwrap();
+        assert!(!fleet.dispatch_ticket(ticket));
+        assert_eq!(fleet.stats().unroutable, 1);
+        let rejected = rejections.0.lock().unwrap();
+        assert_eq!(*rejected, vec![(coffee(80), Some(origin))]);
+        drop(rejected);
+
+        fleet.machines()[0].pool().remove(0).unwrap();
+    }
+
+    #[test]
+    fn monitor_stops_right_away_with_the_totals() {
+        let fleet = Fleet::new(vec![idle_machine("a", 100), idle_machine("b", 100)]);
+        fleet.machines()[0].resources().use_coffee(10).unwrap();
+        let output = SharedWriter::default();
+        let monitor = fleet.monitor_to(60_000, MonitorMode::Log, Box::new(output.clone()));
+
+        let started = std::time::Instant::now();
+        monitor.stop();
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("poured_coffee=10mg"), "{}", lines[0]);
+        assert!(lines[1].contains("poured_coffee=0mg"), "{}", lines[1]);
+        assert!(lines.iter().all(|line| line.contains("[final]")));
+
+        for machine in fleet.machines() {
+            machine.pool().remove(0).unwrap();
+        }
+    }
+}
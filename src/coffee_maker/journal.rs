@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    observer::Observer,
+    orders::{Ingredients, Order, Origin, Ticket},
+    resources::Error,
+};
+
+/// What the journal records about an order, one per line.
+/// Orders read from a file are followed by where they were read from, `<source>:<position>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    /// The order with the given id was put in the queue, written `accepted <id> <coffee>,<water>,<foam>`.
+    Accepted(u64, Ingredients, Option<Origin>),
+    /// An order was turned away when taken, written `rejected <coffee>,<water>,<foam>`.
+    Rejected(Ingredients, Option<Origin>),
+    Started(u64),
+    Completed(u64),
+    Failed(u64),
+    Abandoned(u64),
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let amounts = |i: &Ingredients| format!("{},{},{}", i.coffee, i.water, i.foam);
+        let origin = |origin: &Option<Origin>| {
+            origin.map_or(String::new(), |o| format!(" {}:{}", o.source, o.position))
+        };
+        match self {
+            Entry::Accepted(id, ingredients, from) => {
+                write!(
+                    f,
+                    "accepted {} {}{}",
+                    id,
+                    amounts(ingredients),
+                    origin(from)
+                )
+            }
+            Entry::Rejected(ingredients, from) => {
+                write!(f, "rejected {}{}", amounts(ingredients), origin(from))
+            }
+            Entry::Started(id) => write!(f, "started {}", id),
+            Entry::Completed(id) => write!(f, "completed {}", id),
+            Entry::Failed(id) => write!(f, "failed {}", id),
+            Entry::Abandoned(id) => write!(f, "abandoned {}", id),
+        }
+    }
+}
+
+impl FromStr for Entry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Entry, String> {
+        let invalid = || format!("Invalid journal entry: {}", s);
+        let id = |id: &str| id.parse::<u64>().map_err(|_| invalid());
+        let ingredients = |amounts: &str| {
+            let amounts = amounts
+                .split(',')
+                .map(|amount| amount.parse().map_err(|_| invalid()))
+                .collect::<Result<Vec<u32>, String>>()?;
+            match amounts[..] {
+                [coffee, water, foam] => match Order::from(coffee, water, foam)? {
+                    Order::Order(ingredients) => Ok(ingredients),
+                    Order::NoMoreOrders => Err(invalid()),
+                },
+                _ => Err(invalid()),
+            }
+        };
+        let origin = |origin: &str| -> Result<Option<Origin>, String> {
+            let (source, position) = origin.split_once(':').ok_or_else(invalid)?;
+            Ok(Some(Origin {
+                source: source.parse().map_err(|_| invalid())?,
+                position: position.parse().map_err(|_| invalid())?,
+            }))
+        };
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        match fields[..] {
+            ["accepted", order, amounts] => {
+                Ok(Entry::Accepted(id(order)?, ingredients(amounts)?, None))
+            }
+            ["accepted", order, amounts, from] => Ok(Entry::Accepted(
+                id(order)?,
+                ingredients(amounts)?,
+                origin(from)?,
+            )),
+            ["rejected", amounts] => Ok(Entry::Rejected(ingredients(amounts)?, None)),
+            ["rejected", amounts, from] => {
+                Ok(Entry::Rejected(ingredients(amounts)?, origin(from)?))
+            }
+            ["started", order] => Ok(Entry::Started(id(order)?)),
+            ["completed", order] => Ok(Entry::Completed(id(order)?)),
+            ["failed", order] => Ok(Entry::Failed(id(order)?)),
+            ["abandoned", order] => Ok(Entry::Abandoned(id(order)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// What a previous run left in the journal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Orders taken from each orders file, accepted or rejected, by the index of its source;
+    /// which were the first ones of the file.
+    pub taken: BTreeMap<usize, usize>,
+    /// Orders accepted but never finished, in the order they were accepted.
+    pub unfinished: Vec<(u64, Ingredients)>,
+    /// Orders completed, failed or abandoned.
+    pub finished: usize,
+    /// Highest id given to an order, zero if none.
+    pub last_id: u64,
+}
+
+impl Recovery {
+    /// Goes through the entries of a journal, in the order they were written.
+    pub fn from_entries(entries: &[Entry]) -> Recovery {
+        let mut recovery = Recovery::default();
+        let mut accepted = Vec::new();
+        let mut finished = HashSet::new();
+        let mut take = |origin: &Option<Origin>| {
+            if let Some(origin) = origin {
+                let taken = recovery.taken.entry(origin.source).or_default();
+                *taken = (*taken).max(origin.position + 1);
+            }
+        };
+        for entry in entries {
+            match entry {
+                Entry::Accepted(id, ingredients, origin) => {
+                    take(origin);
+                    recovery.last_id = recovery.last_id.max(*id);
+                    accepted.push((*id, *ingredients));
+                }
+                Entry::Rejected(_, origin) => take(origin),
+                Entry::Started(_) => {}
+                Entry::Completed(id) | Entry::Failed(id) | Entry::Abandoned(id) => {
+                    finished.insert(*id);
+                }
+            }
+        }
+        recovery.finished = finished.len();
+        recovery.unfinished = accepted
+            .into_iter()
+            .filter(|(id, _)| !finished.contains(id))
+            .collect();
+        recovery
+    }
+}
+
+/// Path of the temporary file written next to the given one, to replace it at once.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    PathBuf::from(temporary)
+}
+
+/// Waits for the entries of the directory holding the given file to reach the disk,
+/// so that the file being created or renamed isn't lost if the system crashes.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // directories can only be opened as files, and synced, on unix
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Reads the entries of the journal at the given path; none if it doesn't exist.
+/// A last line that can't be read is left out, as the process may have died while writing it.
+pub fn read_journal(path: impl AsRef<Path>) -> io::Result<Vec<Entry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+    let mut entries = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        match line.parse() {
+            Ok(entry) => entries.push(entry),
+            Err(_) if i == lines.len() - 1 => {}
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Appends what happens to every order to a file, synced to the disk before going on,
+/// so that a run that dies midway can be recovered.
+///
+/// Orders put back in the queue after a recovery keep their ids and aren't accepted again.
+pub struct Journal {
+    file: Mutex<File>,
+    /// Highest id recovered; orders up to it were accepted in a previous run.
+    recovered: u64,
+}
+
+impl Journal {
+    /// Recovers what the journal at the given path recorded, if it exists,
+    /// and opens it to carry on appending to it.
+    pub fn recover(path: &str) -> io::Result<(Arc<Journal>, Recovery)> {
+        let recovery = Recovery::from_entries(&read_journal(path)?);
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        sync_dir(Path::new(path))?;
+        // the last line may have been left half written
+        if fs::read(path)?.last().is_some_and(|last| *last != b'\n') {
+            writeln!(file)?;
+        }
+        let journal = Journal {
+            file: Mutex::new(file),
+            recovered: recovery.last_id,
+        };
+        Ok((Arc::new(journal), recovery))
+    }
+
+    /// Appends an entry and waits for it to reach the disk.
+    fn record(&self, entry: Entry) {
+        let mut file = self.file.lock().expect("Failed to lock journal");
+        // losing an entry is better than stopping the coffee maker
+        let _ = writeln!(file, "{}", entry).and_then(|_| file.sync_data());
+    }
+}
+
+impl Observer for Journal {
+    fn order_queued(&self, ticket: &Ticket) {
+        if ticket.id > self.recovered {
+            self.record(Entry::Accepted(
+                ticket.id,
+                ticket.ingredients,
+                ticket.origin,
+            ));
+        }
+    }
+
+    fn order_rejected(&self, ingredients: &Ingredients, origin: Option<Origin>) {
+        self.record(Entry::Rejected(*ingredients, origin));
+    }
+
+    fn order_started(&self, _dispenser: usize, ticket: &Ticket) {
+        self.record(Entry::Started(ticket.id));
+    }
+
+    fn order_completed(&self, _dispenser: usize, ticket: &Ticket) {
+        self.record(Entry::Completed(ticket.id));
+    }
+
+    fn order_failed(&self, _dispenser: usize, ticket: &Ticket, _error: &Error) {
+        self.record(Entry::Failed(ticket.id));
+    }
+
+    fn order_abandoned(&self, ticket: &Ticket) {
+        self.record(Entry::Abandoned(ticket.id));
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        builder::CoffeeMakerBuilder, capabilities::Capabilities, state::MachineState,
+        test_utils::temp_file,
+    };
+
+    #[test]
+    fn parses_what_it_writes() {
+        let origin = Some(Origin {
+            source: 1,
+            position: 4,
+        });
+        let entries = [
+            Entry::Accepted(3, Ingredients::new(10, 20, 30), None),
+            Entry::Accepted(3, Ingredients::new(10, 20, 30), origin),
+            Entry::Rejected(Ingredients::new(0, 0, 5), None),
+            Entry::Rejected(Ingredients::new(0, 0, 5), origin),
+            Entry::Started(3),
+            Entry::Completed(3),
+            Entry::Failed(4),
+            Entry::Abandoned(5),
+        ];
+        for entry in entries {
+            assert_eq!(entry.to_string().parse(), Ok(entry));
+        }
+        assert_eq!(entries[0].to_string(), "accepted 3 10,20,30");
+        assert_eq!(entries[1].to_string(), "accepted 3 10,20,30 1:4");
+        assert!("accepted 3".parse::<Entry>().is_err());
+        assert!("accepted 3 10,20,30 1".parse::<Entry>().is_err());
+        assert!("served 3".parse::<Entry>().is_err());
+        assert!("rejected 5000,0,0".parse::<Entry>().is_err());
+    }
+
+    #[test]
+    fn recovers_unfinished_orders() {
+        let path = temp_file("journal_recovery");
+        // the second order wasn't read from a file
+        let journal = "accepted 1 10,0,0 0:0\nrejected 0,0,5 0:1\naccepted 2 20,0,0\nstarted 1\n\
+                       accepted 3 30,0,0 1:0\nstarted 2\ncompleted 1\nfailed 3\naccepted 4 4";
+        fs::write(&path, journal).unwrap();
+
+        let entries = read_journal(&path).unwrap();
+        // the half written last line is left out
+        assert_eq!(entries.len(), 8);
+        let recovery = Recovery::from_entries(&entries);
+        assert_eq!(recovery.taken, BTreeMap::from([(0, 2), (1, 1)]));
+        assert_eq!(recovery.unfinished, vec![(2, Ingredients::new(20, 0, 0))]);
+        assert_eq!(recovery.finished, 2);
+        assert_eq!(recovery.last_id, 3);
+
+        fs::write(&path, "accepted 1 10,0,0\nserved 1\ncompleted 1\n").unwrap();
+        assert!(read_journal(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_journal(&path).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn resumes_where_it_died() {
+        let (journal, orders) = (temp_file("journal_resume"), temp_file("journal_orders"));
+        fs::write(&orders, "10,0,0\n0,0,10\n20,0,0\n30,0,0\n").unwrap();
+        // the first order was made, the second rejected and the third was being made
+        fs::write(
+            &journal,
+            "accepted 1 10,0,0 0:0\nstarted 1\ncompleted 1\nrejected 0,0,10 0:1\naccepted 2 20,0,0 0:2\nstarted 2\n",
+        )
+        .unwrap();
+
+        let machine = CoffeeMakerBuilder::new()
+            .dispensers(vec!["coffee+water".parse::<Capabilities>().unwrap()])
+            .orders_file(&orders)
+            .journal(&journal)
+            .build()
+            .unwrap();
+        let report = machine.run().unwrap();
+        assert_eq!(report.completed, 2);
+        assert_eq!(report.rejected, 0);
+
+        let recovery = Recovery::from_entries(&read_journal(&journal).unwrap());
+        assert_eq!(recovery.taken, BTreeMap::from([(0, 4)]));
+        assert_eq!(recovery.finished, 3);
+        assert!(recovery.unfinished.is_empty());
+        assert_eq!(recovery.last_id, 3);
+        fs::remove_file(&journal).unwrap();
+        fs::remove_file(&orders).unwrap();
+    }
+
+    #[test]
+    fn resumes_along_with_the_state() {
+        let (journal, orders, state) = (
+            temp_file("journal_state_resume"),
+            temp_file("journal_state_orders"),
+            temp_file("journal_state"),
+        );
+        fs::write(&orders, "10,0,0\n20,0,0\n").unwrap();
+        // a foam order was left in the state, and restored along with the journal;
+        // the first order of the file was made, then the process died
+        let empty = CoffeeMakerBuilder::new()
+            .dispensers(vec![])
+            .build()
+            .unwrap();
+        let left = MachineState {
+            orders: vec![(Ingredients::new(0, 0, 10), None)],
+            ..MachineState::of(&empty)
+        };
+        empty.shutdown();
+        left.save(&state).unwrap();
+        fs::write(
+            &journal,
+            "accepted 1 0,0,10\naccepted 2 10,0,0 0:0\nstarted 2\ncompleted 2\n",
+        )
+        .unwrap();
+
+        let machine = CoffeeMakerBuilder::new()
+            .dispensers(vec![Capabilities::ALL])
+            .orders_file(&orders)
+            .state_file(&state)
+            .journal(&journal)
+            .build()
+            .unwrap();
+        let report = machine.run().unwrap();
+        // the foam order once, and the second order of the file
+        assert_eq!(report.completed, 2);
+
+        let recovery = Recovery::from_entries(&read_journal(&journal).unwrap());
+        assert_eq!(recovery.taken, BTreeMap::from([(0, 2)]));
+        assert!(recovery.unfinished.is_empty());
+        for path in [journal, orders, state] {
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
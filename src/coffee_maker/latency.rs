@@ -0,0 +1,258 @@
+use std::{collections::BTreeMap, fmt, sync::Mutex, time::Duration};
+
+use super::{
+    observer::Observer,
+    orders::{Ingredients, Ticket},
+};
+
+/// Statistics of a set of durations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl Summary {
+    /// Summarizes the given durations, every statistic being zero if there are none.
+    pub fn of(mut durations: Vec<Duration>) -> Summary {
+        if durations.is_empty() {
+            return Summary::default();
+        }
+        durations.sort();
+        let total: Duration = durations.iter().sum();
+        Summary {
+            count: durations.len(),
+            mean: total / durations.len() as u32,
+            median: percentile(&durations, 50),
+            p95: percentile(&durations, 95),
+            p99: percentile(&durations, 99),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted durations, which must not be empty.
+fn percentile(sorted: &[Duration], percentage: usize) -> Duration {
+    let rank = (percentage * sorted.len()).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+/// How long customers waited, for a group of completed orders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latencies {
+    /// From being queued until a dispenser first took the order.
+    pub queue_wait: Summary,
+    /// From the dispenser starting the order until it was made.
+    pub service_time: Summary,
+    /// From being queued until the order was made.
+    pub end_to_end: Summary,
+}
+
+/// Latencies of a run, overall and broken down by drink and by dispenser.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub overall: Latencies,
+    /// By kind of drink, see `Ingredients::drink`.
+    pub by_drink: Vec<(String, Latencies)>,
+    pub by_dispenser: Vec<(usize, Latencies)>,
+}
+
+impl fmt::Display for LatencyReport {
+    /// Displays a row per group and measure, in milliseconds.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<34} {:>6} {:>9} {:>9} {:>9} {:>9}",
+            "Latency (ms)", "count", "mean", "median", "p95", "p99"
+        )?;
+        let groups = std::iter::once(("all".to_string(), &self.overall))
+            .chain(self.by_drink.iter().map(|(drink, l)| (drink.clone(), l)))
+            .chain(
+                self.by_dispenser
+                    .iter()
+                    .map(|(id, l)| (format!("dispenser {}", id), l)),
+            );
+        for (group, latencies) in groups {
+            for (measure, summary) in [
+                ("queue wait", latencies.queue_wait),
+                ("service time", latencies.service_time),
+                ("end to end", latencies.end_to_end),
+            ] {
+                let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+                writeln!(
+                    f,
+                    "{:<20} {:<13} {:>6} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+                    group,
+                    measure,
+                    summary.count,
+                    millis(summary.mean),
+                    millis(summary.median),
+                    millis(summary.p95),
+                    millis(summary.p99)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Timings of a completed order.
+#[derive(Debug, Clone)]
+struct Sample {
+    drink: String,
+    dispenser: usize,
+    queue_wait: Duration,
+    service_time: Duration,
+    end_to_end: Duration,
+}
+
+/// Keeps the timings of the completed orders as an observer, to report how long customers waited.
+/// Orders that could not be made are left out.
+#[derive(Debug, Default)]
+pub struct Latency {
+    samples: Mutex<Vec<Sample>>,
+}
+
+impl Latency {
+    /// Creates a new Latency instance without samples.
+    pub fn new() -> Latency {
+        Latency::default()
+    }
+
+    /// Records a completed order, with its queue wait, service time and end-to-end latency.
+    pub fn record(
+        &self,
+        ingredients: &Ingredients,
+        dispenser: usize,
+        (queue_wait, service_time, end_to_end): (Duration, Duration, Duration),
+    ) {
+        let mut samples = self.samples.lock().expect("Failed to lock samples");
+        samples.push(Sample {
+            drink: ingredients.drink(),
+            dispenser,
+            queue_wait,
+            service_time,
+            end_to_end,
+        });
+    }
+
+    /// Returns the latencies of the orders completed so far.
+    pub fn report(&self) -> LatencyReport {
+        let samples = self.samples.lock().expect("Failed to lock samples");
+        let mut by_drink: BTreeMap<&str, Vec<&Sample>> = BTreeMap::new();
+        let mut by_dispenser: BTreeMap<usize, Vec<&Sample>> = BTreeMap::new();
+        for sample in samples.iter() {
+            by_drink.entry(&sample.drink).or_default().push(sample);
+            by_dispenser
+                .entry(sample.dispenser)
+                .or_default()
+                .push(sample);
+        }
+
+        LatencyReport {
+            overall: latencies(samples.iter()),
+            by_drink: by_drink
+                .into_iter()
+                .map(|(drink, samples)| (drink.to_string(), latencies(samples.into_iter())))
+                .collect(),
+            by_dispenser: by_dispenser
+                .into_iter()
+                .map(|(id, samples)| (id, latencies(samples.into_iter())))
+                .collect(),
+        }
+    }
+}
+
+/// Summarizes the latencies of the given samples.
+fn latencies<'a>(samples: impl Iterator<Item = &'a Sample> + Clone) -> Latencies {
+    Latencies {
+        queue_wait: Summary::of(samples.clone().map(|sample| sample.queue_wait).collect()),
+        service_time: Summary::of(samples.clone().map(|sample| sample.service_time).collect()),
+        end_to_end: Summary::of(samples.map(|sample| sample.end_to_end).collect()),
+    }
+}
+
+impl Observer for Latency {
+    fn order_completed(&self, dispenser: usize, ticket: &Ticket) {
+        if let (Some(queue_wait), Some(service_time), Some(end_to_end)) =
+            (ticket.queue_wait(), ticket.service_time(), ticket.latency())
+        {
+            let timings = (queue_wait, service_time, end_to_end);
+            self.record(&ticket.ingredients, dispenser, timings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod latency_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        capabilities::Capabilities, machine::CoffeeMaker, orders::Order, Resources,
+    };
+
+    fn millis(durations: &[u64]) -> Vec<Duration> {
+        durations
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect()
+    }
+
+    #[test]
+    fn summarizes_durations() {
+        let summary = Summary::of(millis(&(1..=100).rev().collect::<Vec<u64>>()));
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.mean, Duration::from_micros(50_500));
+        assert_eq!(summary.median, Duration::from_millis(50));
+        assert_eq!(summary.p95, Duration::from_millis(95));
+        assert_eq!(summary.p99, Duration::from_millis(99));
+
+        let single = Summary::of(millis(&[7]));
+        assert_eq!(single.median, Duration::from_millis(7));
+        assert_eq!(single.p99, Duration::from_millis(7));
+        assert_eq!(Summary::of(Vec::new()), Summary::default());
+    }
+
+    #[test]
+    fn reports_by_drink_and_dispenser() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &[Capabilities::ALL; 2]);
+        machine.orders().push(Order::from(10, 10, 0).unwrap());
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        // more than there is, so it isn't counted
+        machine.orders().push(Order::from(1000, 0, 0).unwrap());
+        let report = machine.shutdown().latency;
+
+        assert_eq!(report.overall.end_to_end.count, 3);
+        let drinks: Vec<(&str, usize)> = report
+            .by_drink
+            .iter()
+            .map(|(drink, latencies)| (drink.as_str(), latencies.queue_wait.count))
+            .collect();
+        assert_eq!(drinks, vec![("coffee+water", 1), ("coffee+water+foam", 2)]);
+        let by_dispenser: usize = report
+            .by_dispenser
+            .iter()
+            .map(|(_, latencies)| latencies.service_time.count)
+            .sum();
+        assert_eq!(by_dispenser, 3);
+        assert!(report.overall.end_to_end.mean >= report.overall.service_time.mean);
+    }
+
+    #[test]
+    fn displays_a_row_per_group_and_measure() {
+        let report = LatencyReport {
+            by_dispenser: vec![(0, Latencies::default())],
+            ..LatencyReport::default()
+        };
+        let displayed = report.to_string();
+        let lines: Vec<&str> = displayed.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("Latency (ms)"));
+        assert!(lines[1].starts_with("all                  queue wait"));
+        assert!(lines[6].starts_with("dispenser 0          end to end"));
+        assert!(lines[6].ends_with("0.00"));
+    }
+}
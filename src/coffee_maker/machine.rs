@@ -0,0 +1,467 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use super::{
+    capabilities::Capabilities,
+    dashboard::Dashboard,
+    eta::Eta,
+    faults::Fault,
+    latency::Latency,
+    observer::Observer,
+    orders::{Ingredients, Order, Orders},
+    pool::{AutoscalerHandle, DispenserPool, ScalingPolicy},
+    report::Report,
+    resources_monitor::{MonitorHandle, MonitorMode},
+    snapshot::{DispenserSnapshot, MachineSnapshot},
+    state::MachineState,
+    take_orders::{take_orders_from, OrderSource},
+    Resources,
+};
+
+/// Optional behaviour of a coffee maker, usually set through `CoffeeMakerBuilder`.
+#[derive(Default)]
+pub struct Extras {
+    /// Where orders are taken from when started.
+    pub sources: Vec<OrderSource>,
+    /// Where the report is written when shut down.
+    pub sinks: Vec<Box<dyn Write + Send>>,
+    /// Interval of the resources monitor, if any.
+    pub monitor_interval: Option<u64>,
+    /// How the resources monitor prints the resources.
+    pub monitor_mode: MonitorMode,
+    /// Refresh interval of the full screen dashboard, if any.
+    pub dashboard_interval: Option<u64>,
+    /// Policy to scale the dispensers automatically, if any.
+    pub scaling: Option<ScalingPolicy>,
+    /// Whether to estimate when the orders will be ready.
+    pub estimates: bool,
+    /// Observers registered before the dispensers start.
+    pub observers: Vec<Arc<dyn Observer>>,
+    /// Where the state is saved when shut down, if anywhere.
+    pub state_file: Option<String>,
+}
+
+/// Threads started by `CoffeeMaker::start`.
+#[derive(Default)]
+struct Running {
+    order_takers: Vec<JoinHandle<()>>,
+    monitor: Option<MonitorHandle>,
+    dashboard: Option<MonitorHandle>,
+    autoscaler: Option<AutoscalerHandle>,
+}
+
+/// A single coffee maker: its own resources, order queue and dispensers.
+pub struct CoffeeMaker {
+    name: String,
+    orders: Arc<Orders>,
+    resources: Arc<Resources>,
+    pool: Arc<DispenserPool>,
+    latency: Arc<Latency>,
+    eta: Option<Arc<Eta>>,
+    extras: Mutex<Extras>,
+    running: Mutex<Running>,
+}
+
+impl CoffeeMaker {
+    /// Creates a new CoffeeMaker instance, starting a dispenser for each of the given capabilities.
+    pub fn new(name: &str, resources: Arc<Resources>, dispensers: &[Capabilities]) -> CoffeeMaker {
+        CoffeeMaker::with_extras(name, resources, dispensers, Extras::default())
+    }
+
+    /// Creates a new CoffeeMaker instance with optional behaviour,
+    /// starting a dispenser for each of the given capabilities.
+    pub fn with_extras(
+        name: &str,
+        resources: Arc<Resources>,
+        dispensers: &[Capabilities],
+        extras: Extras,
+    ) -> CoffeeMaker {
+        let orders = Orders::new();
+        let latency = Arc::new(Latency::new());
+        resources.register_observer(latency.clone());
+        for observer in &extras.observers {
+            resources.register_observer(observer.clone());
+            orders.register_observer(observer.clone());
+        }
+        let pool = DispenserPool::new(orders.clone(), resources.clone());
+        let eta = extras.estimates.then(|| {
+            let eta = Eta::new(&orders, &pool, &resources);
+            resources.register_observer(eta.clone());
+            orders.register_observer(eta.clone());
+            eta
+        });
+        for capabilities in dispensers {
+            pool.add(*capabilities);
+        }
+
+        CoffeeMaker {
+            name: name.to_string(),
+            orders,
+            resources,
+            pool,
+            latency,
+            eta,
+            extras: Mutex::new(extras),
+            running: Mutex::new(Running::default()),
+        }
+    }
+
+    /// Name of the coffee maker.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Queue of orders the coffee maker will make.
+    pub fn orders(&self) -> &Arc<Orders> {
+        &self.orders
+    }
+
+    /// Resources of the coffee maker.
+    pub fn resources(&self) -> &Arc<Resources> {
+        &self.resources
+    }
+
+    /// Dispensers of the coffee maker.
+    pub fn pool(&self) -> &Arc<DispenserPool> {
+        &self.pool
+    }
+
+    /// Registers an observer of everything that happens in the coffee maker.
+    pub fn register_observer(&self, observer: Arc<dyn Observer>) {
+        self.resources.register_observer(observer.clone());
+        self.orders.register_observer(observer);
+    }
+
+    /// Amount of orders waiting per running dispenser.
+    /// Coffee makers without running dispensers have no capacity.
+    pub fn load(&self) -> f64 {
+        match self.pool.running() {
+            0 => f64::INFINITY,
+            running => self.orders.len() as f64 / running as f64,
+        }
+    }
+
+    /// Returns whether the coffee maker can take an order with the given ingredients.
+    /// Its dispensers must be able to make it, and its resources must be enough for it
+    /// as well as for every order already waiting. Water never runs out, so it isn't checked.
+    pub fn can_take(&self, ingredients: &Ingredients) -> bool {
+        let needed = self.orders.pending_ingredients() + *ingredients;
+        self.orders.can_make(ingredients) && self.resources.can_make(&needed)
+    }
+
+    /// Takes an order if a dispenser can make it.
+    /// Returns the id of its ticket and, if the coffee maker estimates them,
+    /// when it is expected to be ready.
+    pub fn take_order(&self, ingredients: Ingredients) -> Result<(u64, Option<Instant>), String> {
+        let id = self.orders.take(ingredients, None)?;
+        let ready_at = self.eta.as_ref().map(|eta| {
+            // it may be made already
+            eta.promised(id).unwrap_or_else(Instant::now)
+        });
+        Ok((id, ready_at))
+    }
+
+    /// Estimates when the order with the given id will be ready.
+    /// Returns `None` if it isn't waiting nor being made, no dispenser can make it,
+    /// or the coffee maker doesn't estimate them.
+    pub fn estimate_ready_time(&self, order_id: u64) -> Option<Instant> {
+        self.eta.as_ref()?.ready_time(order_id)
+    }
+
+    /// Starts the monitor, the dashboard, the autoscaler and taking orders from the sources, if they were set.
+    /// Sources are only taken once, even if started again.
+    pub fn start(&self) -> io::Result<()> {
+        let mut extras = self.extras.lock().expect("Failed to lock extras");
+        let mut running = self.running.lock().expect("Failed to lock running");
+
+        if running.monitor.is_none() {
+            running.monitor = extras
+                .monitor_interval
+                .map(|interval| self.resources.monitor(interval, extras.monitor_mode));
+        }
+        if running.dashboard.is_none() {
+            running.dashboard = extras.dashboard_interval.map(|interval| {
+                let dashboard = Dashboard::new();
+                self.register_observer(dashboard.clone());
+                dashboard.start(
+                    self.resources.clone(),
+                    self.orders.clone(),
+                    self.pool.clone(),
+                    interval,
+                    Box::new(io::stdout()),
+                )
+            });
+        }
+        if running.autoscaler.is_none() {
+            running.autoscaler = extras.scaling.map(|policy| self.pool.autoscale(policy));
+        }
+        for (index, source) in extras.sources.drain(..).enumerate() {
+            let speedup = self.resources.speedup();
+            let handle = take_orders_from(source, index, self.orders.clone(), speedup)?;
+            running.order_takers.push(handle);
+        }
+        Ok(())
+    }
+
+    /// Waits for the order sources to be exhausted and for every order to be made, then stops.
+    /// Returns the final report, which is also written to the sinks;
+    /// and saves the state to the state file, if set, telling the observers if it can't.
+    pub fn shutdown(&self) -> Report {
+        let running = {
+            let mut running = self.running.lock().expect("Failed to lock running");
+            std::mem::take(&mut *running)
+        };
+
+        for handle in running.order_takers {
+            handle.join().expect("Order Taker Panicked");
+        }
+
+        // stopped first, so that it doesn't replace the dispensers as they stop
+        if let Some(autoscaler) = running.autoscaler {
+            autoscaler.stop();
+        }
+        self.orders.push(Order::NoMoreOrders);
+        self.pool.join();
+        if let Some(monitor) = running.monitor {
+            monitor.stop();
+        }
+        if let Some(dashboard) = running.dashboard {
+            dashboard.stop();
+        }
+
+        let report = self.report();
+        let mut extras = self.extras.lock().expect("Failed to lock extras");
+        if let Some(path) = &extras.state_file {
+            if let Err(error) = MachineState::of(self).save(path) {
+                self.resources
+                    .observers()
+                    .notify(|observer| observer.state_not_saved(path, &error));
+            }
+        }
+        for sink in extras.sinks.iter_mut() {
+            // a failing sink shouldn't prevent the others from getting the report
+            let _ = write!(sink, "{}", report).and_then(|_| sink.flush());
+        }
+        report
+    }
+
+    /// Asks the resources monitor to print a snapshot right away, if it is running.
+    /// Returns whether it was running.
+    pub fn request_snapshot(&self) -> bool {
+        let running = self.running.lock().expect("Failed to lock running");
+        running
+            .monitor
+            .as_ref()
+            .map(|monitor| monitor.request_snapshot())
+            .is_some()
+    }
+
+    /// Starts the coffee maker and shuts it down once every order is made.
+    /// Returns the final report.
+    pub fn run(&self) -> io::Result<Report> {
+        self.start()?;
+        Ok(self.shutdown())
+    }
+
+    /// Returns a snapshot of the coffee maker, with its queue and dispensers.
+    pub fn snapshot(&self) -> MachineSnapshot {
+        let mut snapshot = self.resources.snapshot();
+        snapshot.machine = Some(self.name.clone());
+        snapshot.queue_length = self.orders.len();
+        snapshot.dispensers = self
+            .pool
+            .list()
+            .into_iter()
+            .map(|(id, capabilities, state)| DispenserSnapshot {
+                id,
+                capabilities,
+                state,
+            })
+            .collect();
+        snapshot
+    }
+
+    /// Returns the current report of the coffee maker.
+    pub fn report(&self) -> Report {
+        let stats = self.resources.stats();
+        Report {
+            name: self.name.clone(),
+            completed: stats.completed(),
+            failed: stats.failed(),
+            retried: stats.retried(),
+            faults: Fault::ALL
+                .into_iter()
+                .map(|fault| (fault, stats.faults(fault)))
+                .collect(),
+            rejected: self.orders.rejected(),
+            abandoned: self.orders.abandoned(),
+            unserved: self.orders.len(),
+            resources: self.resources.status(),
+            latency: self.latency.report(),
+            eta: self.eta.as_ref().map(|eta| eta.accuracy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod coffee_maker_tests {
+    use super::*;
+    use crate::coffee_maker::{builder::CoffeeMakerBuilder, orders::Ticket};
+    use std::time::Duration;
+
+    #[test]
+    fn makes_its_orders() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &[Capabilities::ALL]);
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        let report = machine.shutdown();
+
+        assert_eq!(report.completed, 1);
+        assert_eq!(report.unserved, 0);
+        assert!(machine.pool().list().is_empty());
+    }
+
+    #[test]
+    fn runs_its_sources() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let extras = Extras {
+            sources: vec![
+                OrderSource::List(vec![Order::from(10, 10, 10).unwrap()]),
+                OrderSource::List(vec![Order::from(10, 10, 10).unwrap()]),
+            ],
+            ..Extras::default()
+        };
+        let machine = CoffeeMaker::with_extras("test", resources, &[Capabilities::ALL], extras);
+
+        let report = machine.run().unwrap();
+        assert_eq!(report.completed, 2);
+    }
+
+    #[test]
+    fn unservable_orders_are_reported() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &["water".parse().unwrap()]);
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        machine.orders().push(Order::from(0, 10, 0).unwrap());
+
+        let report = machine.shutdown();
+        assert_eq!(report.completed, 1);
+        assert_eq!(report.unserved, 1);
+    }
+
+    #[test]
+    fn can_take_accounts_for_waiting_orders() {
+        let resources = Resources::new(100, 0, 0, 0).unwrap();
+        // no dispensers, so nothing is consumed
+        let machine = CoffeeMaker::new("test", resources, &[]);
+        let ingredients = Ingredients {
+            coffee: 60,
+            water: 0,
+            foam: 0,
+        };
+
+        assert!(machine.can_take(&ingredients));
+        machine.orders().push(Order::Order(ingredients));
+        assert!(!machine.can_take(&ingredients));
+    }
+
+    #[test]
+    fn can_take_any_amount_of_water() {
+        let resources = Resources::new(100, 0, 0, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &[]);
+        let ingredients = Ingredients {
+            coffee: 10,
+            water: 3_000_000_000,
+            foam: 0,
+        };
+
+        machine.orders().push(Order::Order(ingredients));
+        assert!(machine.can_take(&ingredients));
+    }
+
+    #[test]
+    fn can_take_requires_able_dispensers() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &["water".parse().unwrap()]);
+        let latte = Ingredients {
+            coffee: 10,
+            water: 0,
+            foam: 10,
+        };
+        assert!(!machine.can_take(&latte));
+        machine.shutdown();
+    }
+
+    #[test]
+    fn machines_without_dispensers_are_full() {
+        let resources = Resources::new(0, 0, 0, 0).unwrap();
+        let machine = CoffeeMaker::new("test", resources, &[]);
+        assert_eq!(machine.load(), f64::INFINITY);
+    }
+
+    #[test]
+    fn observers_see_the_state_not_saved() {
+        struct NotSaved(Mutex<Vec<String>>);
+
+        impl Observer for NotSaved {
+            fn state_not_saved(&self, path: &str, _error: &io::Error) {
+                self.0.lock().unwrap().push(path.to_string());
+            }
+        }
+
+        let not_saved = Arc::new(NotSaved(Mutex::new(Vec::new())));
+        let path = "/nonexistent/coffee_state";
+        let machine = CoffeeMakerBuilder::new()
+            .state_file(path)
+            .observer(not_saved.clone())
+            .build()
+            .unwrap();
+        machine.shutdown();
+        assert_eq!(*not_saved.0.lock().unwrap(), vec![path]);
+    }
+
+    #[test]
+    fn autoscaled_dispensers_are_not_replaced_when_stopping() {
+        #[derive(Default)]
+        struct Started(Mutex<Vec<usize>>);
+
+        impl Observer for Started {
+            fn dispenser_started(&self, dispenser: usize, _capabilities: Capabilities) {
+                self.0.lock().unwrap().push(dispenser);
+            }
+
+            fn order_started(&self, _dispenser: usize, _ticket: &Ticket) {
+                // so that the autoscaler checks the pool while it is being joined
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let started = Arc::new(Started::default());
+        let machine = CoffeeMakerBuilder::new()
+            .dispensers(vec![])
+            .autoscale(ScalingPolicy {
+                min: 1,
+                max: 1,
+                interval: Duration::from_millis(1),
+                ..ScalingPolicy::default()
+            })
+            .observer(started.clone())
+            .build()
+            .unwrap();
+        machine.start().unwrap();
+        while machine.pool().running() == 0 {
+            std::thread::yield_now();
+        }
+        for _ in 0..3 {
+            machine.orders().push(Order::from(10, 10, 10).unwrap());
+        }
+        let report = machine.shutdown();
+
+        assert_eq!(report.completed, 3);
+        assert_eq!(*started.0.lock().unwrap(), vec![0]);
+    }
+}
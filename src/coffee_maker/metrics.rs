@@ -0,0 +1,497 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::{
+    observer::{Action, ActionEvent, Context, Ingredient, Observer},
+    orders::Ticket,
+    Error, Resources,
+};
+
+/// Upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Distribution of durations, as cumulative buckets.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Histogram {
+    /// Amount of observations up to each bound of `BUCKETS`.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Adds an observation.
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Collects the metrics of a coffee maker as an observer, to be exposed to Prometheus.
+///
+/// Levels and order counters are read from the resources when scraped; grinder and whipper
+/// runs, the ingredients poured, and how long orders wait and take are counted as they happen.
+pub struct Metrics {
+    machine: String,
+    resources: Arc<Resources>,
+    poured: [AtomicU64; 3],
+    grinder_runs: AtomicU64,
+    whipper_runs: AtomicU64,
+    abandoned: AtomicU64,
+    queue_wait: Mutex<Histogram>,
+    preparation: Mutex<Histogram>,
+    /// When each dispenser started its current order.
+    started: Mutex<HashMap<usize, Instant>>,
+}
+
+impl Metrics {
+    /// Creates a new Metrics instance for the coffee maker with the given name and resources.
+    /// It has to be registered as an observer of the coffee maker to count what happens.
+    pub fn new(machine: &str, resources: Arc<Resources>) -> Arc<Metrics> {
+        Arc::new(Metrics {
+            machine: machine.to_string(),
+            resources,
+            poured: Default::default(),
+            grinder_runs: AtomicU64::new(0),
+            whipper_runs: AtomicU64::new(0),
+            abandoned: AtomicU64::new(0),
+            queue_wait: Mutex::new(Histogram::default()),
+            preparation: Mutex::new(Histogram::default()),
+            started: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Amount of an ingredient poured so far.
+    pub fn poured(&self, ingredient: Ingredient) -> u64 {
+        self.poured[ingredient as usize].load(Ordering::Relaxed)
+    }
+
+    /// Observes how long the order of a dispenser took, if it was started.
+    fn finished(&self, dispenser: usize) {
+        let mut started = self.started.lock().expect("Failed to lock started orders");
+        if let Some(started) = started.remove(&dispenser) {
+            let mut preparation = self.preparation.lock().expect("Failed to lock histogram");
+            preparation.observe(started.elapsed());
+        }
+    }
+}
+
+impl Observer for Metrics {
+    fn order_started(&self, dispenser: usize, ticket: &Ticket) {
+        let mut queue_wait = self.queue_wait.lock().expect("Failed to lock histogram");
+        queue_wait.observe(ticket.queued_at.elapsed());
+        let mut started = self.started.lock().expect("Failed to lock started orders");
+        started.insert(dispenser, Instant::now());
+    }
+
+    fn ingredient_dispensed(&self, _context: Context, ingredient: Ingredient, amount: u32) {
+        self.poured[ingredient as usize].fetch_add(amount as u64, Ordering::Relaxed);
+    }
+
+    fn action_performed(&self, event: &ActionEvent) {
+        match event.action {
+            Action::Grind => self.grinder_runs.fetch_add(1, Ordering::Relaxed),
+            Action::Whip => self.whipper_runs.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    fn order_completed(&self, dispenser: usize, _ticket: &Ticket) {
+        self.finished(dispenser);
+    }
+
+    fn order_retried(&self, dispenser: usize, _ticket: &Ticket, _error: &Error) {
+        self.finished(dispenser);
+    }
+
+    fn order_failed(&self, dispenser: usize, _ticket: &Ticket, _error: &Error) {
+        self.finished(dispenser);
+    }
+
+    fn order_abandoned(&self, _ticket: &Ticket) {
+        self.abandoned.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes the header of a metric family.
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Writes a sample of a metric.
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+    let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+}
+
+/// Writes the samples of a histogram.
+fn histogram(out: &mut String, name: &str, machine: &str, histogram: &Histogram) {
+    for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+        let bound = bound.to_string();
+        let labels = [("machine", machine), ("le", bound.as_str())];
+        sample(out, &format!("{}_bucket", name), &labels, count);
+    }
+    let labels = [("machine", machine), ("le", "+Inf")];
+    sample(out, &format!("{}_bucket", name), &labels, histogram.count);
+    sample(
+        out,
+        &format!("{}_sum", name),
+        &[("machine", machine)],
+        histogram.sum,
+    );
+    sample(
+        out,
+        &format!("{}_count", name),
+        &[("machine", machine)],
+        histogram.count,
+    );
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders the metrics of the given coffee makers in the Prometheus text format.
+pub fn render(metrics: &[Arc<Metrics>]) -> String {
+    let snapshots: Vec<_> = metrics
+        .iter()
+        .map(|metrics| (metrics, metrics.resources.snapshot()))
+        .collect();
+    let mut out = String::new();
+
+    family(
+        &mut out,
+        "coffee_maker_container_level",
+        "gauge",
+        "Current level of a container, in mg or ml.",
+    );
+    for (metrics, snapshot) in &snapshots {
+        for container in &snapshot.containers {
+            let name = container.container.to_string().replace(' ', "_");
+            let labels = [("machine", metrics.machine.as_str()), ("container", &name)];
+            sample(
+                &mut out,
+                "coffee_maker_container_level",
+                &labels,
+                container.level,
+            );
+        }
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_drinks_served_total",
+        "counter",
+        "Drinks made.",
+    );
+    for (metrics, snapshot) in &snapshots {
+        let labels = [("machine", metrics.machine.as_str())];
+        let served = snapshot.counters.completed;
+        sample(
+            &mut out,
+            "coffee_maker_drinks_served_total",
+            &labels,
+            served,
+        );
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_drinks_failed_total",
+        "counter",
+        "Drinks that could not be made.",
+    );
+    for (metrics, snapshot) in &snapshots {
+        let labels = [("machine", metrics.machine.as_str())];
+        let failed = snapshot.counters.failed;
+        sample(
+            &mut out,
+            "coffee_maker_drinks_failed_total",
+            &labels,
+            failed,
+        );
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_drinks_abandoned_total",
+        "counter",
+        "Orders whose customers left before they were taken.",
+    );
+    for metrics in metrics {
+        let labels = [("machine", metrics.machine.as_str())];
+        let abandoned = metrics.abandoned.load(Ordering::Relaxed);
+        sample(
+            &mut out,
+            "coffee_maker_drinks_abandoned_total",
+            &labels,
+            abandoned,
+        );
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_ingredients_consumed_total",
+        "counter",
+        "Ingredients poured, and coffee beans and milk transformed, in mg or ml.",
+    );
+    for (metrics, snapshot) in &snapshots {
+        let machine = metrics.machine.as_str();
+        let consumed = [
+            ("coffee", metrics.poured(Ingredient::Coffee)),
+            ("water", metrics.poured(Ingredient::Water)),
+            ("foam", metrics.poured(Ingredient::Foam)),
+            ("coffee_beans", snapshot.totals.ground_coffee_beans as u64),
+            ("milk", snapshot.totals.whipped_milk as u64),
+        ];
+        for (ingredient, amount) in consumed {
+            let labels = [("machine", machine), ("ingredient", ingredient)];
+            sample(
+                &mut out,
+                "coffee_maker_ingredients_consumed_total",
+                &labels,
+                amount,
+            );
+        }
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_grinder_runs_total",
+        "counter",
+        "Times the grinder ran.",
+    );
+    for metrics in metrics {
+        let labels = [("machine", metrics.machine.as_str())];
+        let runs = metrics.grinder_runs.load(Ordering::Relaxed);
+        sample(&mut out, "coffee_maker_grinder_runs_total", &labels, runs);
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_whipper_runs_total",
+        "counter",
+        "Times the whipper ran.",
+    );
+    for metrics in metrics {
+        let labels = [("machine", metrics.machine.as_str())];
+        let runs = metrics.whipper_runs.load(Ordering::Relaxed);
+        sample(&mut out, "coffee_maker_whipper_runs_total", &labels, runs);
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_queue_wait_seconds",
+        "histogram",
+        "Time from an order being queued until a dispenser starts it.",
+    );
+    for metrics in metrics {
+        let queue_wait = metrics.queue_wait.lock().expect("Failed to lock histogram");
+        histogram(
+            &mut out,
+            "coffee_maker_queue_wait_seconds",
+            &metrics.machine,
+            &queue_wait,
+        );
+    }
+
+    family(
+        &mut out,
+        "coffee_maker_preparation_seconds",
+        "histogram",
+        "Time a dispenser takes to make an order, whether it succeeds or not.",
+    );
+    for metrics in metrics {
+        let preparation = metrics
+            .preparation
+            .lock()
+            .expect("Failed to lock histogram");
+        histogram(
+            &mut out,
+            "coffee_maker_preparation_seconds",
+            &metrics.machine,
+            &preparation,
+        );
+    }
+
+    out
+}
+
+/// An HTTP server on the loopback interface exposing the metrics at `/metrics`.
+pub struct MetricsServer {
+    address: SocketAddr,
+    handle: JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MetricsServer {
+    /// Starts serving the metrics of the given coffee makers on the given port of the loopback
+    /// interface, in a new thread. Port 0 picks any free port.
+    pub fn start(port: u16, metrics: Vec<Arc<Metrics>>) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let address = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_thread = stop.clone();
+
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop_thread.load(Ordering::Relaxed) {
+                    break;
+                }
+                // a broken connection shouldn't stop the server
+                if let Ok(stream) = stream {
+                    let _ = respond(stream, &metrics);
+                }
+            }
+        });
+
+        Ok(MetricsServer {
+            address,
+            handle,
+            stop,
+        })
+    }
+
+    /// Address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops the server, waiting for the current request to be answered.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // wakes the server up, which is blocked waiting for a connection
+        let _ = TcpStream::connect(self.address);
+        self.handle.join().expect("Metrics Server Panicked");
+    }
+}
+
+/// Answers a request, with the metrics if it is a `GET /metrics`.
+fn respond(stream: TcpStream, metrics: &[Arc<Metrics>]) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers are not needed, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(metrics)),
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+    use crate::coffee_maker::{capabilities::Capabilities, machine::CoffeeMaker, orders::Order};
+    use std::io::Read;
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(60));
+        assert_eq!(histogram.buckets[0], 0);
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[BUCKETS.len() - 1], 1);
+        assert_eq!(histogram.count, 2);
+        assert!((histogram.sum - 60.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn counts_what_the_machine_does() {
+        let resources = Resources::new(0, 100, 0, 100).unwrap();
+        let metrics = Metrics::new("front", resources.clone());
+        let machine = CoffeeMaker::new("front", resources, &[Capabilities::ALL]);
+        machine.register_observer(metrics.clone());
+        machine.orders().push(Order::from(10, 20, 30).unwrap());
+        machine.shutdown();
+
+        let rendered = render(&[metrics]);
+        for line in [
+            "# TYPE coffee_maker_container_level gauge",
+            "coffee_maker_container_level{machine=\"front\",container=\"coffee_beans\"} 90",
+            "coffee_maker_drinks_served_total{machine=\"front\"} 1",
+            "coffee_maker_drinks_failed_total{machine=\"front\"} 0",
+            "coffee_maker_drinks_abandoned_total{machine=\"front\"} 0",
+            "coffee_maker_ingredients_consumed_total{machine=\"front\",ingredient=\"water\"} 20",
+            "coffee_maker_ingredients_consumed_total{machine=\"front\",ingredient=\"milk\"} 30",
+            "coffee_maker_grinder_runs_total{machine=\"front\"} 1",
+            "coffee_maker_whipper_runs_total{machine=\"front\"} 1",
+            "# TYPE coffee_maker_queue_wait_seconds histogram",
+            "coffee_maker_queue_wait_seconds_bucket{machine=\"front\",le=\"+Inf\"} 1",
+            "coffee_maker_preparation_seconds_count{machine=\"front\"} 1",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn serves_the_metrics() {
+        let resources = Resources::new(10, 0, 0, 0).unwrap();
+        let metrics = Metrics::new("front", resources);
+        let server = MetricsServer::start(0, vec![metrics]).unwrap();
+        assert!(server.address().ip().is_loopback());
+
+        let response = get(server.address(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response
+            .contains("coffee_maker_container_level{machine=\"front\",container=\"coffee\"} 10\n"));
+
+        let response = get(server.address(), "/");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        server.stop();
+    }
+}
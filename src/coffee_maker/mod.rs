@@ -1,14 +1,69 @@
 pub mod config;
 
+pub mod capabilities;
+
 pub mod orders;
 
 mod take_orders;
-pub use take_orders::take_orders;
+pub use take_orders::{
+    read_orders_file, read_timed_orders_file, take_orders, take_orders_from, Arrival, OrderSource,
+};
 
 mod dispenser;
-pub use dispenser::spawn_dispenser;
+pub use dispenser::{spawn_dispenser, Dispenser};
 
 mod resources;
-pub use resources::{ResourceResult, Resources};
+pub use resources::{Error, LockGranularity, RefillPolicy, ResourceResult, Resources};
 
 pub mod resources_monitor;
+
+pub mod faults;
+
+pub mod observer;
+
+pub mod event_log;
+
+pub mod replay;
+
+pub mod trace;
+
+pub mod dashboard;
+
+pub mod alerts;
+
+pub mod snapshot;
+
+pub mod metrics;
+
+pub mod latency;
+
+pub mod eta;
+
+pub mod bench;
+
+pub mod simulation;
+
+pub mod workload;
+
+pub mod planning;
+
+pub mod stats;
+
+pub mod pool;
+
+pub mod machine;
+
+pub mod fleet;
+
+pub mod builder;
+
+pub mod report;
+
+pub mod state;
+
+pub mod journal;
+
+mod rng;
+
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,269 @@
+use std::{
+    fmt, io,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{
+    capabilities::Capabilities,
+    config,
+    orders::{Ingredients, Origin, Ticket},
+    resources::Error,
+    resources_monitor::Levels,
+};
+
+/// Who an event happened for, when it is known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    pub dispenser: Option<usize>,
+    pub order: Option<u64>,
+}
+
+impl Context {
+    /// Creates the context of an order being made by a dispenser.
+    pub fn new(dispenser: usize, order: u64) -> Context {
+        Context {
+            dispenser: Some(dispenser),
+            order: Some(order),
+        }
+    }
+}
+
+/// Ingredients poured into a drink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ingredient {
+    Coffee,
+    Water,
+    Foam,
+}
+
+impl fmt::Display for Ingredient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Ingredient::Coffee => "coffee",
+            Ingredient::Water => "water",
+            Ingredient::Foam => "foam",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Containers of the coffee maker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Container {
+    Coffee,
+    CoffeeBeans,
+    Foam,
+    Milk,
+}
+
+impl Container {
+    pub const ALL: [Container; 4] = [
+        Container::Coffee,
+        Container::CoffeeBeans,
+        Container::Foam,
+        Container::Milk,
+    ];
+
+    /// Capacity of the container.
+    pub fn capacity(&self) -> u32 {
+        match self {
+            Container::Coffee => config::C,
+            Container::CoffeeBeans => config::G,
+            Container::Foam => config::E,
+            Container::Milk => config::L,
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Container::Coffee => "coffee",
+            Container::CoffeeBeans => "coffee beans",
+            Container::Foam => "foam",
+            Container::Milk => "milk",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Actions performed by the actuators of the coffee maker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Grind,
+    PourCoffee,
+    PourWater,
+    Whip,
+    PourFoam,
+    /// A slow dispenser held up before pouring.
+    Stall,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Action::Grind => "grind",
+            Action::PourCoffee => "pour_coffee",
+            Action::PourWater => "pour_water",
+            Action::Whip => "whip",
+            Action::PourFoam => "pour_foam",
+            Action::Stall => "stall",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        match s {
+            "grind" => Ok(Action::Grind),
+            "pour_coffee" => Ok(Action::PourCoffee),
+            "pour_water" => Ok(Action::PourWater),
+            "whip" => Ok(Action::Whip),
+            "pour_foam" => Ok(Action::PourFoam),
+            "stall" => Ok(Action::Stall),
+            _ => Err(format!("Unknown action: {}", s)),
+        }
+    }
+}
+
+/// An action that was performed by an actuator.
+#[derive(Debug, Clone, Copy)]
+pub struct ActionEvent {
+    /// Position of the action among every action of the coffee maker, starting from 1.
+    /// Events may be notified out of order, but their sequence numbers follow
+    /// the order in which the containers changed.
+    pub sequence: u64,
+    /// When the action started.
+    pub started: Instant,
+    /// When the action finished.
+    pub at: SystemTime,
+    pub context: Context,
+    pub action: Action,
+    /// Amount of the ingredient involved, zero for stalls.
+    pub amount: u32,
+    /// How long the action took.
+    pub duration: Duration,
+    /// Levels of the containers right after the action.
+    pub levels: Levels,
+}
+
+/// Gets notified of what happens in the coffee maker.
+/// Every method does nothing by default, so only the interesting ones need to be implemented.
+///
+/// Observers are notified from the threads where things happen, sometimes while holding
+/// the locks of the resources; so they should be quick and never call back into the coffee maker.
+pub trait Observer: Send + Sync {
+    /// An order was put in the queue.
+    fn order_queued(&self, _ticket: &Ticket) {}
+    /// An order was turned away when taken, as no dispenser could make it.
+    fn order_rejected(&self, _ingredients: &Ingredients, _origin: Option<Origin>) {}
+    /// A dispenser started making an order.
+    fn order_started(&self, _dispenser: usize, _ticket: &Ticket) {}
+    /// A container is about to be locked to be used, which may take a while.
+    fn lock_waiting(&self, _context: Context, _container: Container) {}
+    /// A container was locked to be used, after waiting for the given time.
+    /// The coffee and foam are locked together with the coffee beans and milk.
+    fn lock_acquired(&self, _context: Context, _container: Container, _waited: Duration) {}
+    /// An ingredient was poured.
+    fn ingredient_dispensed(&self, _context: Context, _ingredient: Ingredient, _amount: u32) {}
+    /// The grinder started grinding the given amount of coffee beans.
+    fn grinder_started(&self, _context: Context, _amount: u32) {}
+    /// The grinder finished grinding the given amount of coffee beans.
+    fn grinder_finished(&self, _context: Context, _amount: u32) {}
+    /// The whipper started whipping the given amount of milk.
+    fn whipper_started(&self, _context: Context, _amount: u32) {}
+    /// The whipper finished whipping the given amount of milk.
+    fn whipper_finished(&self, _context: Context, _amount: u32) {}
+    /// An actuator finished an action, after every other event about it.
+    fn action_performed(&self, _event: &ActionEvent) {}
+    /// A container went below (`low`) or back above its warning threshold.
+    fn threshold_crossed(&self, _container: Container, _level: u32, _low: bool) {}
+    /// A dispenser finished making an order.
+    fn order_completed(&self, _dispenser: usize, _ticket: &Ticket) {}
+    /// A dispenser failed to make an order, which was sent back to be retried.
+    fn order_retried(&self, _dispenser: usize, _ticket: &Ticket, _error: &Error) {}
+    /// A dispenser failed to make an order, which was discarded.
+    fn order_failed(&self, _dispenser: usize, _ticket: &Ticket, _error: &Error) {}
+    /// An order was dropped from the queue, its customer having left before it was taken.
+    fn order_abandoned(&self, _ticket: &Ticket) {}
+    /// A dispenser with the given capabilities was added to the pool and started taking orders.
+    fn dispenser_started(&self, _dispenser: usize, _capabilities: Capabilities) {}
+    /// A dispenser was asked to stop once it finishes its current order.
+    fn dispenser_draining(&self, _dispenser: usize) {}
+    /// A dispenser stopped and was removed from the pool.
+    fn dispenser_stopped(&self, _dispenser: usize) {}
+    /// The state couldn't be saved to the given file when shutting down.
+    fn state_not_saved(&self, _path: &str, _error: &io::Error) {}
+}
+
+/// Observers registered somewhere, notified in the order they were registered.
+/// Can be used thread-safely.
+#[derive(Default)]
+pub struct Observers {
+    observers: RwLock<Vec<Arc<dyn Observer>>>,
+}
+
+impl Observers {
+    /// Creates a new Observers instance, without observers.
+    pub fn new() -> Observers {
+        Observers::default()
+    }
+
+    /// Registers an observer.
+    pub fn register(&self, observer: Arc<dyn Observer>) {
+        let mut observers = self.observers.write().expect("Failed to lock observers");
+        observers.push(observer);
+    }
+
+    /// Notifies every observer.
+    pub fn notify(&self, notification: impl Fn(&dyn Observer)) {
+        let observers = self.observers.read().expect("Failed to lock observers");
+        for observer in observers.iter() {
+            notification(observer.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod observers_tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Observer for Recorder {
+        fn grinder_started(&self, context: Context, amount: u32) {
+            let event = format!("grind {} for {:?}", amount, context.order);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn notifies_every_observer_in_order() {
+        let observers = Observers::new();
+        let first = Arc::new(Recorder::default());
+        let second = Arc::new(Recorder::default());
+        observers.register(first.clone());
+        observers.register(second.clone());
+
+        observers.notify(|observer| observer.grinder_started(Context::new(0, 7), 10));
+        // not implemented, so ignored
+        observers.notify(|observer| observer.whipper_started(Context::default(), 10));
+
+        for recorder in [first, second] {
+            assert_eq!(
+                *recorder.events.lock().unwrap(),
+                vec!["grind 10 for Some(7)"]
+            );
+        }
+    }
+}
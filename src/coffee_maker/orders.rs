@@ -1,8 +1,15 @@
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
-use std_semaphore::Semaphore;
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-use super::config;
+use super::{
+    capabilities::Capabilities,
+    config,
+    dispenser::Dispenser,
+    observer::{Observer, Observers},
+};
 
 /// Stores the ingredients that are needed to make a drink.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,6 +19,38 @@ pub struct Ingredients {
     pub foam: u32,
 }
 
+impl Ingredients {
+    /// Creates the ingredients of a drink, without checking them like an order does.
+    pub fn new(coffee: u32, water: u32, foam: u32) -> Ingredients {
+        Ingredients {
+            coffee,
+            water,
+            foam,
+        }
+    }
+
+    /// Kind of drink, named after the ingredients it has, like `coffee+foam`.
+    pub fn drink(&self) -> String {
+        match Capabilities::needed_for(self).to_string() {
+            drink if drink.is_empty() => "nothing".to_string(),
+            drink => drink,
+        }
+    }
+}
+
+impl Add for Ingredients {
+    type Output = Ingredients;
+
+    /// Adds up each ingredient, saturating instead of overflowing.
+    fn add(self, other: Ingredients) -> Ingredients {
+        Ingredients {
+            coffee: self.coffee.saturating_add(other.coffee),
+            water: self.water.saturating_add(other.water),
+            foam: self.foam.saturating_add(other.foam),
+        }
+    }
+}
+
 /// Encapsules an Order or the lack thereof.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Order {
@@ -27,20 +66,110 @@ impl Order {
         } else if foam > config::E {
             Err("Foam is too much".to_string())
         } else {
-            Ok(Order::Order(Ingredients {
-                coffee,
-                water,
-                foam,
-            }))
+            Ok(Order::Order(Ingredients::new(coffee, water, foam)))
+        }
+    }
+}
+
+/// Where an order was read from: the index of its orders file among the sources of the
+/// coffee maker, and its position among the valid orders of that file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub source: usize,
+    pub position: usize,
+}
+
+/// An order taken by the coffee maker, along with its handling metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket {
+    pub id: u64,
+    pub ingredients: Ingredients,
+    /// Amount of times the order was attempted and failed.
+    pub attempts: u32,
+    /// Last dispenser that failed to make the order.
+    pub failed_on: Option<usize>,
+    /// When the order was first put in the queue.
+    pub queued_at: Instant,
+    /// When a dispenser first took the order from the queue.
+    pub dequeued_at: Option<Instant>,
+    /// When the dispenser making the order started its last attempt.
+    pub started_at: Option<Instant>,
+    /// When the dispenser finished its last attempt, making the order or not.
+    pub finished_at: Option<Instant>,
+    /// When the customer leaves if the order is still in the queue.
+    pub deadline: Option<Instant>,
+    /// Where the order was read from, if it was read from a file.
+    pub origin: Option<Origin>,
+}
+
+impl Ticket {
+    /// Creates a ticket for an order just put in the queue, due after the given patience, if any.
+    pub(crate) fn new(id: u64, ingredients: Ingredients, patience: Option<Duration>) -> Ticket {
+        let queued_at = Instant::now();
+        Ticket {
+            id,
+            ingredients,
+            attempts: 0,
+            failed_on: None,
+            queued_at,
+            dequeued_at: None,
+            started_at: None,
+            finished_at: None,
+            deadline: patience.map(|patience| queued_at + patience),
+            origin: None,
         }
     }
+
+    /// Time the order waited in the queue until a dispenser first took it.
+    pub fn queue_wait(&self) -> Option<Duration> {
+        Some(self.dequeued_at? - self.queued_at)
+    }
+
+    /// Time the dispenser took to make the order, on its last attempt.
+    pub fn service_time(&self) -> Option<Duration> {
+        Some(self.finished_at? - self.started_at?)
+    }
+
+    /// Time from the order being queued until it was finished.
+    pub fn latency(&self) -> Option<Duration> {
+        Some(self.finished_at? - self.queued_at)
+    }
+
+    /// Returns whether the customer already left, the order being past its deadline.
+    /// Orders a dispenser already took are being made, so they never expire.
+    pub fn expired(&self) -> bool {
+        self.dequeued_at.is_none()
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+    }
+
+    /// Returns whether the ticket would rather not go to the given dispenser.
+    fn avoids(&self, dispenser: usize) -> bool {
+        self.failed_on == Some(dispenser)
+    }
+}
+
+/// Entries of the queue.
+#[derive(Debug)]
+enum Queued {
+    Ticket(Ticket),
+    NoMoreOrders,
 }
 
 /// Encapsules the orders that are to be made.
 /// Functions as a thread-safe queue.
+/// Keeps track of the capabilities of the dispensers, to only hand them orders they can make.
 pub struct Orders {
-    orders: Mutex<VecDeque<Order>>,
-    semaphore: Semaphore,
+    orders: Mutex<VecDeque<Queued>>,
+    available: Condvar,
+    dispensers: Mutex<Vec<Capabilities>>,
+    next_id: AtomicU64,
+    rejected: AtomicU64,
+    abandoned: AtomicU64,
+    /// How long customers wait for their orders unless told otherwise, if they leave at all.
+    patience: Mutex<Option<Duration>>,
+    observers: Observers,
 }
 
 impl Orders {
@@ -48,25 +177,354 @@ impl Orders {
     pub fn new() -> Arc<Orders> {
         Arc::new(Orders {
             orders: Mutex::new(VecDeque::new()),
-            semaphore: Semaphore::new(0),
+            available: Condvar::new(),
+            dispensers: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+            rejected: AtomicU64::new(0),
+            abandoned: AtomicU64::new(0),
+            patience: Mutex::new(None),
+            observers: Observers::new(),
         })
     }
 
+    /// Sets the amount of orders rejected and abandoned, to carry on from a previous run.
+    pub fn restore(&self, rejected: u64, abandoned: u64) {
+        self.rejected.store(rejected, Ordering::Relaxed);
+        self.abandoned.store(abandoned, Ordering::Relaxed);
+    }
+
+    /// Sets how long customers wait for their orders before leaving, unless an order says
+    /// otherwise. By default they never leave.
+    pub fn set_patience(&self, patience: Option<Duration>) {
+        *self.patience.lock().expect("Failed to lock patience") = patience;
+    }
+
     /// Adds an order to the queue.
     pub fn push(&self, order: Order) {
+        self.push_with_patience(order, None);
+    }
+
+    /// Adds an order to the queue, whose customer leaves if it isn't taken within the given
+    /// time, or the patience of the queue if `None`.
+    pub fn push_with_patience(&self, order: Order, patience: Option<Duration>) {
+        match order {
+            Order::Order(ingredients) => self.push_ticket(self.new_ticket(ingredients, patience)),
+            Order::NoMoreOrders => {
+                let mut orders = self.orders.lock().expect("Failed to lock orders");
+                orders.push_back(Queued::NoMoreOrders);
+                self.available.notify_all();
+            }
+        }
+    }
+
+    /// Creates the ticket of a new order, queued now.
+    fn new_ticket(&self, ingredients: Ingredients, patience: Option<Duration>) -> Ticket {
+        let patience = patience.or(*self.patience.lock().expect("Failed to lock patience"));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Ticket::new(id, ingredients, patience)
+    }
+
+    /// Puts an order taken in a previous run back in the queue, with the id it had then.
+    /// Its customer waits for as long as it takes.
+    pub fn requeue(&self, id: u64, ingredients: Ingredients) {
+        self.resume_after(id);
+        self.push_ticket(Ticket::new(id, ingredients, None));
+    }
+
+    /// Makes the ids of the next tickets follow the given one, to carry on from a previous run.
+    pub fn resume_after(&self, id: u64) {
+        self.next_id.fetch_max(id + 1, Ordering::Relaxed);
+    }
+
+    /// Adds a drink to the queue like `try_push_with_patience`, returning the id of its ticket.
+    pub fn take(
+        &self,
+        ingredients: Ingredients,
+        patience: Option<Duration>,
+    ) -> Result<u64, String> {
+        self.take_from(ingredients, patience, None)
+    }
+
+    /// Like `take`, for a drink read from the given place.
+    pub fn take_from(
+        &self,
+        ingredients: Ingredients,
+        patience: Option<Duration>,
+        origin: Option<Origin>,
+    ) -> Result<u64, String> {
+        if !self.can_make(&ingredients) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            self.observers
+                .notify(|observer| observer.order_rejected(&ingredients, origin));
+            return Err("No dispenser can make the order".to_string());
+        }
+        let ticket = Ticket {
+            origin,
+            ..self.new_ticket(ingredients, patience)
+        };
+        let id = ticket.id;
+        self.push_ticket(ticket);
+        Ok(id)
+    }
+
+    /// Adds an order to the queue, only if a registered dispenser can make it.
+    /// If no dispensers were registered, every order is accepted.
+    pub fn try_push(&self, order: Order) -> Result<(), String> {
+        self.try_push_with_patience(order, None)
+    }
+
+    /// Like `try_push`, with the patience of `push_with_patience`.
+    pub fn try_push_with_patience(
+        &self,
+        order: Order,
+        patience: Option<Duration>,
+    ) -> Result<(), String> {
+        match order {
+            Order::Order(ingredients) => self.take(ingredients, patience).map(|_| ()),
+            Order::NoMoreOrders => {
+                self.push(order);
+                Ok(())
+            }
+        }
+    }
+
+    /// Drops the orders past their deadline from the queue, counting them as abandoned.
+    /// The observers are notified with the queue unlocked, which is then locked again.
+    fn abandon_expired<'a>(
+        &'a self,
+        mut orders: MutexGuard<'a, VecDeque<Queued>>,
+    ) -> MutexGuard<'a, VecDeque<Queued>> {
+        loop {
+            let mut abandoned = Vec::new();
+            orders.retain(|queued| match queued {
+                Queued::Ticket(ticket) if ticket.expired() => {
+                    abandoned.push(*ticket);
+                    false
+                }
+                _ => true,
+            });
+            if abandoned.is_empty() {
+                return orders;
+            }
+            drop(orders);
+
+            self.abandoned
+                .fetch_add(abandoned.len() as u64, Ordering::Relaxed);
+            for ticket in &abandoned {
+                self.observers
+                    .notify(|observer| observer.order_abandoned(ticket));
+            }
+            orders = self.orders.lock().expect("Failed to lock orders");
+        }
+    }
+
+    /// Adds an already taken order to the back of the queue, keeping its metadata.
+    pub fn push_ticket(&self, ticket: Ticket) {
+        self.observers
+            .notify(|observer| observer.order_queued(&ticket));
+        let mut orders = self.orders.lock().expect("Failed to lock orders");
+        orders.push_back(Queued::Ticket(ticket));
+        self.available.notify_all();
+    }
+
+    /// Registers an observer of the orders being queued.
+    pub fn register_observer(&self, observer: Arc<dyn Observer>) {
+        self.observers.register(observer);
+    }
+
+    /// Returns whether a registered dispenser can make a drink with the given ingredients.
+    /// If no dispensers were registered, any drink can be made.
+    pub fn can_make(&self, ingredients: &Ingredients) -> bool {
+        let dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        dispensers.is_empty() || dispensers.iter().any(|c| c.can_make(ingredients))
+    }
+
+    /// Puts a failed order back at the front of the queue, to be retried.
+    /// Other dispensers will take it before the one it failed on.
+    pub fn retry(&self, mut ticket: Ticket, failed_on: usize) {
+        ticket.attempts += 1;
+        ticket.failed_on = Some(failed_on);
         let mut orders = self.orders.lock().expect("Failed to lock orders");
-        orders.push_back(order);
-        self.semaphore.release();
+        orders.push_front(Queued::Ticket(ticket));
+        self.available.notify_all();
     }
 
-    /// Returns the next order in the queue.
+    /// Returns the next order in the queue, skipping those past their deadline.
     /// If there are no orders, the thread will be blocked until there is one.
     pub fn pop(&self) -> Order {
-        self.semaphore.acquire();
         let mut orders = self.orders.lock().expect("Failed to lock orders");
-        orders
+        orders = self.abandon_expired(orders);
+        while orders.is_empty() {
+            orders = self.available.wait(orders).expect("Failed to lock orders");
+            orders = self.abandon_expired(orders);
+        }
+        match orders
             .pop_front()
             .expect("No orders in queue (Invalid State)")
+        {
+            Queued::Ticket(ticket) => Order::Order(ticket.ingredients),
+            Queued::NoMoreOrders => Order::NoMoreOrders,
+        }
+    }
+
+    /// Registers a dispenser with the given capabilities.
+    /// Orders that no registered dispenser can make will be rejected by `try_push`.
+    pub fn register_dispenser(&self, capabilities: Capabilities) {
+        let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        dispensers.push(capabilities);
+    }
+
+    /// Returns whether a registered dispenser, other than the one asking, can make the ticket.
+    fn others_can_make(&self, capabilities: Capabilities, ticket: &Ticket) -> bool {
+        let dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        let able = dispensers
+            .iter()
+            .filter(|c| c.can_make(&ticket.ingredients))
+            .count();
+        // the one asking is among them
+        able > 1 || (able == 1 && !dispensers.contains(&capabilities))
+    }
+
+    /// Removes a registered dispenser with the given capabilities.
+    fn unregister_dispenser(&self, capabilities: Capabilities) {
+        let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        if let Some(i) = dispensers.iter().position(|c| *c == capabilities) {
+            dispensers.remove(i);
+        }
+    }
+
+    /// Returns the next ticket the given dispenser can make, skipping those past their deadline.
+    /// Tickets that failed on the dispenser are left for other able dispensers, if there are any.
+    /// If there are no such orders, the thread will be blocked until there is one.
+    /// Returns `None` when there are no more orders for it, leaving `NoMoreOrders` in the queue,
+    /// or when the dispenser is draining; unregistering the dispenser.
+    pub fn pop_for(&self, dispenser: &Dispenser) -> Option<Ticket> {
+        let capabilities = dispenser.capabilities;
+        let mut orders = self.orders.lock().expect("Failed to lock orders");
+        loop {
+            orders = self.abandon_expired(orders);
+            let pending = orders
+                .iter()
+                .take_while(|queued| matches!(queued, Queued::Ticket(_)))
+                .count();
+            let chosen = (0..pending).find(|i| match &orders[*i] {
+                Queued::Ticket(ticket) => {
+                    capabilities.can_make(&ticket.ingredients)
+                        && !(ticket.avoids(dispenser.id)
+                            && self.others_can_make(capabilities, ticket))
+                }
+                Queued::NoMoreOrders => false,
+            });
+
+            if dispenser.is_draining() || (chosen.is_none() && pending < orders.len()) {
+                // drained or reached NoMoreOrders
+                self.unregister_dispenser(capabilities);
+                self.available.notify_all();
+                return None;
+            }
+
+            if let Some(Queued::Ticket(mut ticket)) = chosen.and_then(|i| orders.remove(i)) {
+                ticket.dequeued_at.get_or_insert_with(Instant::now);
+                return Some(ticket);
+            }
+
+            orders = self.available.wait(orders).expect("Failed to lock orders");
+        }
+    }
+
+    /// Returns the next ticket in the queue, regardless of who can make it, skipping those past
+    /// their deadline.
+    /// If there are no orders, the thread will be blocked until there is one.
+    /// Returns `None` when there are no more orders, leaving `NoMoreOrders` in the queue.
+    pub fn pop_ticket(&self) -> Option<Ticket> {
+        let mut orders = self.orders.lock().expect("Failed to lock orders");
+        orders = self.abandon_expired(orders);
+        while orders.is_empty() {
+            orders = self.available.wait(orders).expect("Failed to lock orders");
+            orders = self.abandon_expired(orders);
+        }
+        match orders.front() {
+            Some(Queued::Ticket(_)) => match orders.pop_front() {
+                Some(Queued::Ticket(ticket)) => Some(ticket),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Wakes every dispenser waiting for orders, so that they can notice they are draining.
+    pub fn wake(&self) {
+        let _orders = self.orders.lock().expect("Failed to lock orders");
+        self.available.notify_all();
+    }
+
+    /// Amount of orders waiting in the queue.
+    pub fn len(&self) -> usize {
+        let orders = self.orders.lock().expect("Failed to lock orders");
+        orders
+            .iter()
+            .filter(|queued| matches!(queued, Queued::Ticket(_)))
+            .count()
+    }
+
+    /// Returns the tickets waiting in the queue, in the order they will be taken.
+    pub fn tickets(&self) -> Vec<Ticket> {
+        let orders = self.orders.lock().expect("Failed to lock orders");
+        orders
+            .iter()
+            .filter_map(|queued| match queued {
+                Queued::Ticket(ticket) => Some(*ticket),
+                Queued::NoMoreOrders => None,
+            })
+            .collect()
+    }
+
+    /// Returns whether there are no orders waiting in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total coffee and foam needed by the orders waiting in the queue.
+    /// Water never runs out, so it isn't added up.
+    pub fn pending_ingredients(&self) -> Ingredients {
+        let orders = self.orders.lock().expect("Failed to lock orders");
+        orders.iter().fold(
+            Ingredients {
+                coffee: 0,
+                water: 0,
+                foam: 0,
+            },
+            |total, queued| match queued {
+                Queued::Ticket(ticket) => Ingredients {
+                    water: 0,
+                    ..total + ticket.ingredients
+                },
+                Queued::NoMoreOrders => total,
+            },
+        )
+    }
+
+    /// How long the oldest order in the queue has been waiting.
+    pub fn oldest_wait(&self) -> Duration {
+        let orders = self.orders.lock().expect("Failed to lock orders");
+        orders
+            .iter()
+            .filter_map(|queued| match queued {
+                Queued::Ticket(ticket) => Some(ticket.queued_at.elapsed()),
+                Queued::NoMoreOrders => None,
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Amount of orders rejected because no dispenser could make them.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Amount of orders dropped because their customers left before they were taken.
+    pub fn abandoned(&self) -> u64 {
+        self.abandoned.load(Ordering::Relaxed)
     }
 }
 
@@ -117,6 +575,10 @@ mod orders_tests {
 
     use super::*;
 
+    fn dispenser(id: usize, capabilities: &str) -> Arc<Dispenser> {
+        Dispenser::new(id, capabilities.parse().unwrap())
+    }
+
     #[test]
     fn create_orders() {
         let orders = Orders::new();
@@ -139,6 +601,262 @@ mod orders_tests {
         assert_eq!(orders.orders.lock().unwrap().len(), 0);
     }
 
+    #[test]
+    fn tickets_have_increasing_ids() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        orders.push(Order::from(0, 0, 0).unwrap());
+        orders.push(Order::from(0, 0, 0).unwrap());
+        let first = orders.pop_for(&all_0).unwrap();
+        let second = orders.pop_for(&all_0).unwrap();
+        assert!(first.id < second.id);
+    }
+
+    #[test]
+    fn pop_for_leaves_no_more_orders() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        let all_1 = dispenser(1, "all");
+        orders.push(Order::NoMoreOrders);
+        assert_eq!(orders.pop_for(&all_0), None);
+        assert_eq!(orders.pop_for(&all_1), None);
+        assert_eq!(orders.pop(), Order::NoMoreOrders);
+    }
+
+    #[test]
+    fn retried_ticket_prefers_other_dispensers() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        let all_1 = dispenser(1, "all");
+        orders.register_dispenser(Capabilities::ALL);
+        orders.register_dispenser(Capabilities::ALL);
+        orders.push(Order::from(0, 1, 0).unwrap());
+        orders.push(Order::from(0, 2, 0).unwrap());
+        orders.push(Order::NoMoreOrders);
+
+        let ticket = orders.pop_for(&all_0).unwrap();
+        orders.retry(ticket, 0);
+
+        // dispenser 0 skips its failed ticket
+        let next = orders.pop_for(&all_0).unwrap();
+        assert_eq!(next.ingredients.water, 2);
+
+        // another dispenser takes it
+        let retried = orders.pop_for(&all_1).unwrap();
+        assert_eq!(retried.id, ticket.id);
+        assert_eq!(retried.attempts, 1);
+        assert_eq!(retried.failed_on, Some(0));
+    }
+
+    #[test]
+    fn retried_ticket_falls_back_to_same_dispenser() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        orders.register_dispenser(Capabilities::ALL);
+        orders.push(Order::from(0, 1, 0).unwrap());
+        orders.push(Order::NoMoreOrders);
+
+        let ticket = orders.pop_for(&all_0).unwrap();
+        orders.retry(ticket, 0);
+
+        let retried = orders.pop_for(&all_0).unwrap();
+        assert_eq!(retried.id, ticket.id);
+        assert_eq!(orders.pop_for(&all_0), None);
+    }
+
+    #[test]
+    fn orders_go_to_able_dispensers() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        let water_1 = dispenser(1, "water");
+        orders.register_dispenser(all_0.capabilities);
+        orders.register_dispenser(water_1.capabilities);
+        orders.push(Order::from(10, 10, 0).unwrap());
+        orders.push(Order::from(0, 20, 0).unwrap());
+        orders.push(Order::NoMoreOrders);
+
+        let ticket = orders.pop_for(&water_1).unwrap();
+        assert_eq!(ticket.ingredients.water, 20);
+        assert_eq!(orders.pop_for(&water_1), None);
+
+        let ticket = orders.pop_for(&all_0).unwrap();
+        assert_eq!(ticket.ingredients.coffee, 10);
+    }
+
+    #[test]
+    fn orders_nobody_can_make_are_rejected() {
+        let orders = Orders::new();
+        orders.register_dispenser("coffee+water".parse().unwrap());
+
+        assert!(orders.try_push(Order::from(10, 10, 0).unwrap()).is_ok());
+        assert!(orders.try_push(Order::from(10, 10, 10).unwrap()).is_err());
+        assert!(orders.try_push(Order::NoMoreOrders).is_ok());
+
+        assert_eq!(orders.rejected(), 1);
+        assert_eq!(orders.orders.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn draining_dispenser_gets_no_orders() {
+        let orders = Orders::new();
+        let all_0 = dispenser(0, "all");
+        orders.register_dispenser(all_0.capabilities);
+        orders.push(Order::from(0, 1, 0).unwrap());
+
+        all_0.drain();
+        assert_eq!(orders.pop_for(&all_0), None);
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn pop_ticket_takes_any_order() {
+        let orders = Orders::new();
+        orders.register_dispenser("water".parse().unwrap());
+        orders.push(Order::from(10, 0, 10).unwrap());
+        orders.push(Order::NoMoreOrders);
+
+        assert_eq!(orders.pop_ticket().unwrap().ingredients.foam, 10);
+        assert_eq!(orders.pop_ticket(), None);
+        assert_eq!(orders.pop(), Order::NoMoreOrders);
+    }
+
+    #[test]
+    fn expired_orders_are_abandoned() {
+        let orders = Orders::new();
+        orders.set_patience(Some(Duration::from_millis(1)));
+        orders.push(Order::from(1, 0, 0).unwrap());
+        orders.push_with_patience(Order::from(2, 0, 0).unwrap(), Some(Duration::from_secs(60)));
+        orders.push(Order::from(3, 0, 0).unwrap());
+        orders.set_patience(None);
+        orders.push(Order::from(4, 0, 0).unwrap());
+        orders.push(Order::NoMoreOrders);
+        std::thread::sleep(Duration::from_millis(5));
+
+        let tickets = dispenser(0, "all");
+        let first = orders.pop_for(&tickets).unwrap();
+        assert_eq!(first.ingredients.coffee, 2);
+        assert!(first.deadline.is_some());
+        assert_eq!(orders.abandoned(), 2);
+        let second = orders.pop_ticket().unwrap();
+        assert_eq!(second.ingredients.coffee, 4);
+        assert_eq!(second.deadline, None);
+        assert_eq!(orders.pop_ticket(), None);
+        assert_eq!(orders.abandoned(), 2);
+    }
+
+    #[test]
+    fn abandoned_orders_are_notified() {
+        struct Abandoned(Mutex<Vec<u64>>);
+        impl Observer for Abandoned {
+            fn order_abandoned(&self, ticket: &Ticket) {
+                self.0.lock().unwrap().push(ticket.id);
+            }
+        }
+
+        let orders = Orders::new();
+        let abandoned = Arc::new(Abandoned(Mutex::new(Vec::new())));
+        orders.register_observer(abandoned.clone());
+        orders.push_with_patience(Order::from(1, 0, 0).unwrap(), Some(Duration::ZERO));
+        orders.push(Order::from(2, 0, 0).unwrap());
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(orders.pop(), Order::from(2, 0, 0).unwrap());
+        assert_eq!(*abandoned.0.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn observers_of_abandoned_orders_can_look_at_the_queue() {
+        struct Remaining(Arc<Orders>, Mutex<Vec<usize>>);
+        impl Observer for Remaining {
+            fn order_abandoned(&self, _ticket: &Ticket) {
+                let remaining = self.0.len();
+                self.1.lock().unwrap().push(remaining);
+            }
+        }
+
+        let orders = Orders::new();
+        let remaining = Arc::new(Remaining(orders.clone(), Mutex::new(Vec::new())));
+        orders.register_observer(remaining.clone());
+        orders.push_with_patience(Order::from(1, 0, 0).unwrap(), Some(Duration::ZERO));
+        orders.push(Order::from(2, 0, 0).unwrap());
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(orders.pop(), Order::from(2, 0, 0).unwrap());
+        assert_eq!(*remaining.1.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn retried_orders_are_never_abandoned() {
+        let orders = Orders::new();
+        orders.push_with_patience(
+            Order::from(1, 0, 0).unwrap(),
+            Some(Duration::from_millis(1)),
+        );
+        orders.push(Order::NoMoreOrders);
+        let dispenser = dispenser(0, "all");
+        let ticket = orders.pop_for(&dispenser).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        orders.retry(ticket, 0);
+        assert_eq!(
+            orders.pop_for(&dispenser).map(|ticket| ticket.id),
+            Some(ticket.id)
+        );
+        assert_eq!(orders.abandoned(), 0);
+    }
+
+    #[test]
+    fn pushed_tickets_keep_their_metadata() {
+        let source = Orders::new();
+        let destination = Orders::new();
+        source.push(Order::from(0, 1, 0).unwrap());
+        let ticket = source.pop_ticket().unwrap();
+
+        destination.push_ticket(ticket);
+        assert_eq!(destination.pop_ticket(), Some(ticket));
+    }
+
+    #[test]
+    fn pending_ingredients_are_added_up() {
+        let orders = Orders::new();
+        orders.push(Order::from(1, 2, 3).unwrap());
+        orders.push(Order::from(10, 20, 30).unwrap());
+        orders.push(Order::NoMoreOrders);
+        assert_eq!(
+            orders.pending_ingredients(),
+            Ingredients {
+                coffee: 11,
+                water: 0,
+                foam: 33
+            }
+        );
+    }
+
+    #[test]
+    fn pending_ingredients_ignore_huge_amounts_of_water() {
+        let orders = Orders::new();
+        orders.push(Order::from(1, 3_000_000_000, 0).unwrap());
+        orders.push(Order::from(1, 3_000_000_000, 0).unwrap());
+        assert_eq!(orders.pending_ingredients().coffee, 2);
+        assert_eq!(orders.pending_ingredients().water, 0);
+    }
+
+    #[test]
+    fn oldest_wait_grows() {
+        let orders = Orders::new();
+        assert_eq!(orders.oldest_wait(), Duration::ZERO);
+        orders.push(Order::from(0, 1, 0).unwrap());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(orders.oldest_wait() >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn any_order_accepted_without_dispensers() {
+        let orders = Orders::new();
+        assert!(orders.try_push(Order::from(10, 10, 10).unwrap()).is_ok());
+        assert_eq!(orders.rejected(), 0);
+    }
+
     #[test]
     fn produce_and_consume_from_different_threads() {
         let order_num = 50;
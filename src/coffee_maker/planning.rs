@@ -0,0 +1,480 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use super::{
+    capabilities::Capabilities,
+    config,
+    latency::Summary,
+    orders::Ingredients,
+    resources_monitor::Levels,
+    simulation::{simulate, Outcome, SimulationConfig, SimulationReport},
+};
+
+/// Splits settings like `key=value,key=value`, naming them `what` in the errors.
+fn settings<'a>(
+    s: &'a str,
+    what: &'a str,
+) -> impl Iterator<Item = Result<(&'a str, &'a str), String>> + 'a {
+    s.split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(move |pair| {
+            pair.split_once('=')
+                .ok_or(format!("Invalid {} setting: {}", what, pair))
+        })
+}
+
+/// Service level a configuration has to meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sla {
+    /// Longest the 95th percentile of the orders may wait in the queue.
+    pub p95_wait: Duration,
+    /// Most orders that may fail because the ingredients ran out.
+    pub max_failed: usize,
+}
+
+impl Default for Sla {
+    /// 95% of the orders taken within 2 minutes, none failing.
+    fn default() -> Sla {
+        Sla {
+            p95_wait: Duration::from_secs(120),
+            max_failed: 0,
+        }
+    }
+}
+
+impl FromStr for Sla {
+    type Err = String;
+
+    /// Parses a service level like `wait=120,failed=5`, with the wait in seconds.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<Sla, String> {
+        let mut sla = Sla::default();
+        for setting in settings(s, "SLA") {
+            let (key, value) = setting?;
+            match key {
+                "wait" => {
+                    sla.p95_wait = match value.parse::<f64>() {
+                        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+                            Duration::from_secs_f64(seconds)
+                        }
+                        _ => return Err(format!("Invalid wait: {}", value)),
+                    }
+                }
+                "failed" => {
+                    sla.max_failed = value
+                        .parse()
+                        .map_err(|_| format!("Invalid amount of failed orders: {}", value))?
+                }
+                _ => return Err(format!("Unknown SLA setting: {}", key)),
+            }
+        }
+        Ok(sla)
+    }
+}
+
+impl fmt::Display for Sla {
+    /// Displays the service level in the same format it is parsed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "wait={},failed={}",
+            self.p95_wait.as_secs_f64(),
+            self.max_failed
+        )
+    }
+}
+
+/// Configurations to try: every combination of the amount of dispensers and the capacity of
+/// each container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchSpace {
+    pub dispensers: Vec<usize>,
+    /// Capacities of the coffee beans container (`G`), in mg.
+    pub coffee_beans: Vec<u32>,
+    /// Capacities of the coffee container (`C`), in mg.
+    pub coffee: Vec<u32>,
+    /// Capacities of the milk container (`L`), in ml.
+    pub milk: Vec<u32>,
+    /// Capacities of the foam container (`E`), in ml.
+    pub foam: Vec<u32>,
+}
+
+impl Default for SearchSpace {
+    /// From 1 to 6 dispensers, and half, the same and twice the capacity of each container
+    /// as set in `config`.
+    fn default() -> SearchSpace {
+        let around = |capacity: u32| vec![capacity / 2, capacity, capacity * 2];
+        SearchSpace {
+            dispensers: (1..=6).collect(),
+            coffee_beans: around(config::G),
+            coffee: around(config::C),
+            milk: around(config::L),
+            foam: around(config::E),
+        }
+    }
+}
+
+impl SearchSpace {
+    /// Every configuration to try, as the amount of dispensers and the capacities.
+    pub fn candidates(&self) -> Vec<(usize, Levels)> {
+        let mut candidates = Vec::new();
+        for dispensers in &self.dispensers {
+            for coffee_beans in &self.coffee_beans {
+                for coffee in &self.coffee {
+                    for milk in &self.milk {
+                        for foam in &self.foam {
+                            let capacities = Levels {
+                                coffee: *coffee,
+                                coffee_beans: *coffee_beans,
+                                foam: *foam,
+                                milk: *milk,
+                            };
+                            candidates.push((*dispensers, capacities));
+                        }
+                    }
+                }
+            }
+        }
+        candidates
+    }
+}
+
+impl FromStr for SearchSpace {
+    type Err = String;
+
+    /// Parses a search space like `dispensers=1-4,G=5000/10000,C=1000,L=10000,E=500/1000`,
+    /// with the values to try separated by `/`, or a range of dispensers.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<SearchSpace, String> {
+        let mut space = SearchSpace::default();
+        let capacities = |value: &str| {
+            value
+                .split('/')
+                .map(|capacity| {
+                    capacity
+                        .parse()
+                        .map_err(|_| format!("Invalid capacity: {}", capacity))
+                })
+                .collect::<Result<Vec<u32>, String>>()
+        };
+        for setting in settings(s, "search space") {
+            let (key, value) = setting?;
+            match key {
+                "dispensers" => {
+                    let invalid = || format!("Invalid amount of dispensers: {}", value);
+                    space.dispensers = match value.split_once('-') {
+                        Some((from, to)) => {
+                            let from: usize = from.parse().map_err(|_| invalid())?;
+                            let to: usize = to.parse().map_err(|_| invalid())?;
+                            (from..=to).collect()
+                        }
+                        None => value
+                            .split('/')
+                            .map(|amount| amount.parse().map_err(|_| invalid()))
+                            .collect::<Result<_, String>>()?,
+                    };
+                    if space.dispensers.is_empty() || space.dispensers.contains(&0) {
+                        return Err(invalid());
+                    }
+                }
+                "G" => space.coffee_beans = capacities(value)?,
+                "C" => space.coffee = capacities(value)?,
+                "L" => space.milk = capacities(value)?,
+                "E" => space.foam = capacities(value)?,
+                _ => return Err(format!("Unknown search space setting: {}", key)),
+            }
+        }
+        Ok(space)
+    }
+}
+
+/// Cost of a dispenser and of each unit of capacity of the containers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prices {
+    pub dispenser: f64,
+    /// Per mg of coffee beans (`G`).
+    pub coffee_beans: f64,
+    /// Per mg of ground coffee (`C`).
+    pub coffee: f64,
+    /// Per ml of milk (`L`).
+    pub milk: f64,
+    /// Per ml of foam (`E`).
+    pub foam: f64,
+}
+
+impl Default for Prices {
+    /// Dispensers cost the most; the coffee and foam containers cost more per unit than those
+    /// of coffee beans and milk.
+    fn default() -> Prices {
+        Prices {
+            dispenser: 1000.0,
+            coffee_beans: 0.01,
+            coffee: 0.05,
+            milk: 0.01,
+            foam: 0.05,
+        }
+    }
+}
+
+impl Prices {
+    /// Cost of a coffee maker with the given amount of dispensers and capacities.
+    pub fn cost(&self, dispensers: usize, capacities: &Levels) -> f64 {
+        dispensers as f64 * self.dispenser
+            + capacities.coffee_beans as f64 * self.coffee_beans
+            + capacities.coffee as f64 * self.coffee
+            + capacities.milk as f64 * self.milk
+            + capacities.foam as f64 * self.foam
+    }
+}
+
+impl FromStr for Prices {
+    type Err = String;
+
+    /// Parses prices like `dispenser=500,G=0.02,C=0.1,L=0.01,E=0.1`.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<Prices, String> {
+        let mut prices = Prices::default();
+        for setting in settings(s, "price") {
+            let (key, value) = setting?;
+            let price = match value.parse::<f64>() {
+                Ok(price) if price >= 0.0 && price.is_finite() => price,
+                _ => return Err(format!("Invalid price: {}", value)),
+            };
+            match key {
+                "dispenser" => prices.dispenser = price,
+                "G" => prices.coffee_beans = price,
+                "C" => prices.coffee = price,
+                "L" => prices.milk = price,
+                "E" => prices.foam = price,
+                _ => return Err(format!("Unknown price: {}", key)),
+            }
+        }
+        Ok(prices)
+    }
+}
+
+/// Outcome of simulating the workload with a configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub dispensers: usize,
+    pub capacities: Levels,
+    pub cost: f64,
+    /// 95th percentile of the time orders waited in the queue, until a dispenser took them
+    /// or their customers left.
+    pub p95_wait: Duration,
+    pub completed: usize,
+    /// Orders that failed because the ingredients ran out.
+    pub failed: usize,
+    pub abandoned: usize,
+}
+
+impl Candidate {
+    fn new(dispensers: usize, capacities: Levels, cost: f64, report: &SimulationReport) -> Self {
+        let waits = report
+            .orders
+            .iter()
+            .filter(|order| order.outcome != Outcome::Rejected)
+            .filter_map(|order| {
+                let left = order.started_at.or(order.finished_at)?;
+                Some(left - order.queued_at)
+            })
+            .collect();
+        Candidate {
+            dispensers,
+            capacities,
+            cost,
+            p95_wait: Summary::of(waits).p95,
+            completed: report.count(Outcome::Completed),
+            failed: report.count(Outcome::Failed),
+            abandoned: report.count(Outcome::Abandoned),
+        }
+    }
+
+    /// Returns whether the configuration meets the service level.
+    pub fn meets(&self, sla: &Sla) -> bool {
+        self.p95_wait <= sla.p95_wait && self.failed <= sla.max_failed
+    }
+}
+
+/// Every configuration tried, from the cheapest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub sla: Sla,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Plan {
+    /// Cheapest configuration meeting the service level, if any.
+    pub fn cheapest(&self) -> Option<&Candidate> {
+        self.candidates
+            .iter()
+            .find(|candidate| candidate.meets(&self.sla))
+    }
+}
+
+impl fmt::Display for Plan {
+    /// Displays a table with a row per configuration, followed by the cheapest one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>7} {:>6} {:>7} {:>6} {:>9} {:>12} {:>9} {:>6} {:>9}  SLA",
+            "dispensers",
+            "G",
+            "C",
+            "L",
+            "E",
+            "cost",
+            "p95 wait (s)",
+            "completed",
+            "failed",
+            "abandoned"
+        )?;
+        for candidate in &self.candidates {
+            writeln!(
+                f,
+                "{:>10} {:>7} {:>6} {:>7} {:>6} {:>9.2} {:>12.2} {:>9} {:>6} {:>9}  {}",
+                candidate.dispensers,
+                candidate.capacities.coffee_beans,
+                candidate.capacities.coffee,
+                candidate.capacities.milk,
+                candidate.capacities.foam,
+                candidate.cost,
+                candidate.p95_wait.as_secs_f64(),
+                candidate.completed,
+                candidate.failed,
+                candidate.abandoned,
+                if candidate.meets(&self.sla) {
+                    "yes"
+                } else {
+                    "no"
+                }
+            )?;
+        }
+        match self.cheapest() {
+            Some(cheapest) => writeln!(
+                f,
+                "Cheapest meeting {}: dispensers={},G={},C={},L={},E={} (cost {:.2})",
+                self.sla,
+                cheapest.dispensers,
+                cheapest.capacities.coffee_beans,
+                cheapest.capacities.coffee,
+                cheapest.capacities.milk,
+                cheapest.capacities.foam,
+                cheapest.cost
+            ),
+            None => writeln!(f, "No configuration meets {}", self.sla),
+        }
+    }
+}
+
+/// Simulates the orders, arriving at their times, with every configuration of the search space,
+/// starting with full containers; the rest of the coffee maker as in `base`.
+/// Returns them from the cheapest, along with the service level to meet.
+pub fn plan(
+    orders: &[(Duration, Ingredients)],
+    base: &SimulationConfig,
+    space: &SearchSpace,
+    prices: &Prices,
+    sla: Sla,
+) -> Plan {
+    let mut candidates: Vec<Candidate> = space
+        .candidates()
+        .into_iter()
+        .map(|(dispensers, capacities)| {
+            let config = SimulationConfig {
+                dispensers: vec![Capabilities::ALL; dispensers],
+                capacities,
+                levels: capacities,
+                ..base.clone()
+            };
+            let report = simulate(config, orders);
+            let cost = prices.cost(dispensers, &capacities);
+            Candidate::new(dispensers, capacities, cost, &report)
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    Plan { sla, candidates }
+}
+
+#[cfg(test)]
+mod planning_tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let sla: Sla = "wait=30,failed=2".parse().unwrap();
+        assert_eq!(sla.p95_wait, Duration::from_secs(30));
+        assert_eq!(sla.max_failed, 2);
+        assert_eq!(sla.to_string().parse::<Sla>(), Ok(sla));
+
+        let space: SearchSpace = "dispensers=2-4,G=100/200,E=50".parse().unwrap();
+        assert_eq!(space.dispensers, vec![2, 3, 4]);
+        assert_eq!(space.coffee_beans, vec![100, 200]);
+        assert_eq!(space.coffee, SearchSpace::default().coffee);
+        assert_eq!(space.foam, vec![50]);
+        assert_eq!(space.candidates().len(), 3 * 2 * 3 * 3);
+
+        let prices: Prices = "dispenser=10,G=1".parse().unwrap();
+        let capacities = Levels {
+            coffee: 0,
+            coffee_beans: 5,
+            foam: 0,
+            milk: 0,
+        };
+        assert_eq!(prices.cost(2, &capacities), 25.0);
+
+        assert!("dispensers=0".parse::<SearchSpace>().is_err());
+        assert!("G=lots".parse::<SearchSpace>().is_err());
+        assert!("wait=-1".parse::<Sla>().is_err());
+        assert!("grinder=1".parse::<Prices>().is_err());
+    }
+
+    #[test]
+    fn finds_the_cheapest_configuration() {
+        let espresso = Ingredients {
+            coffee: 10,
+            water: 0,
+            foam: 0,
+        };
+        let orders = vec![(Duration::ZERO, espresso); 20];
+        let space: SearchSpace = "dispensers=1-2,G=100/1000,C=50,L=0,E=0".parse().unwrap();
+        let sla = Sla {
+            p95_wait: Duration::from_secs(3600),
+            max_failed: 0,
+        };
+        let plan = plan(
+            &orders,
+            &SimulationConfig::default(),
+            &space,
+            &Prices::default(),
+            sla,
+        );
+
+        assert_eq!(plan.candidates.len(), 4);
+        let short: Vec<usize> = plan
+            .candidates
+            .iter()
+            .filter(|candidate| candidate.capacities.coffee_beans == 100)
+            .map(|candidate| candidate.failed)
+            .collect();
+        // 150mg of coffee for 200mg of orders
+        assert_eq!(short, vec![5, 5]);
+
+        let cheapest = plan.cheapest().unwrap();
+        assert_eq!(cheapest.dispensers, 1);
+        assert_eq!(cheapest.capacities.coffee_beans, 1000);
+        assert_eq!(cheapest.completed, 20);
+        assert!(plan
+            .to_string()
+            .contains("Cheapest meeting wait=3600,failed=0"));
+
+        let impatient = Plan {
+            sla: Sla {
+                p95_wait: Duration::ZERO,
+                max_failed: 0,
+            },
+            ..plan
+        };
+        assert_eq!(impatient.cheapest(), None);
+        assert!(impatient.to_string().contains("No configuration meets"));
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{
+    capabilities::Capabilities,
+    dispenser::{spawn_dispenser, Dispenser},
+    orders::Orders,
+    Resources,
+};
+
+/// Lifecycle of a dispenser in the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispenserState {
+    /// Taking orders.
+    Running,
+    /// Finishing its current order, won't take new ones.
+    Draining,
+    /// No longer taking orders.
+    Stopped,
+}
+
+impl fmt::Display for DispenserState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DispenserState::Running => "running",
+            DispenserState::Draining => "draining",
+            DispenserState::Stopped => "stopped",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A dispenser in the pool along with its thread.
+struct Entry {
+    dispenser: Arc<Dispenser>,
+    handle: JoinHandle<()>,
+}
+
+impl Entry {
+    fn state(&self) -> DispenserState {
+        if self.handle.is_finished() {
+            DispenserState::Stopped
+        } else if self.dispenser.is_draining() {
+            DispenserState::Draining
+        } else {
+            DispenserState::Running
+        }
+    }
+}
+
+/// When to add or remove dispensers automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScalingPolicy {
+    /// Least amount of running dispensers.
+    pub min: usize,
+    /// Most amount of running dispensers.
+    pub max: usize,
+    /// Queue length above which a dispenser is added.
+    pub queue_len: usize,
+    /// Waiting time of the oldest order above which a dispenser is added.
+    pub max_wait: Duration,
+    /// How often the pool is checked.
+    pub interval: Duration,
+}
+
+impl Default for ScalingPolicy {
+    fn default() -> ScalingPolicy {
+        ScalingPolicy {
+            min: 1,
+            max: 8,
+            queue_len: 4,
+            max_wait: Duration::from_secs(2),
+            interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl FromStr for ScalingPolicy {
+    type Err = String;
+
+    /// Parses a policy in the format `min=<n>,max=<n>,queue=<n>,wait=<ms>,interval=<ms>`.
+    /// Every key is optional.
+    fn from_str(s: &str) -> Result<ScalingPolicy, String> {
+        let mut policy = ScalingPolicy::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("Invalid scaling setting: {}", pair))?;
+            let value: u64 = value
+                .parse()
+                .or(Err(format!("Invalid number: {}", value)))?;
+            match key {
+                "min" => policy.min = value as usize,
+                "max" => policy.max = value as usize,
+                "queue" => policy.queue_len = value as usize,
+                "wait" => policy.max_wait = Duration::from_millis(value),
+                "interval" => policy.interval = Duration::from_millis(value),
+                _ => return Err(format!("Unknown scaling setting: {}", key)),
+            }
+        }
+        if policy.min > policy.max {
+            return Err("Min is greater than max".to_string());
+        }
+        Ok(policy)
+    }
+}
+
+/// Dispensers of a coffee maker, which can be added and removed while running.
+/// Can be used thread-safely.
+pub struct DispenserPool {
+    orders: Arc<Orders>,
+    resources: Arc<Resources>,
+    next_id: AtomicUsize,
+    dispensers: Mutex<BTreeMap<usize, Entry>>,
+}
+
+impl DispenserPool {
+    /// Creates a new DispenserPool instance, without dispensers.
+    pub fn new(orders: Arc<Orders>, resources: Arc<Resources>) -> Arc<DispenserPool> {
+        Arc::new(DispenserPool {
+            orders,
+            resources,
+            next_id: AtomicUsize::new(0),
+            dispensers: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Starts a new dispenser with the given capabilities.
+    /// Returns its id.
+    pub fn add(&self, capabilities: Capabilities) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let dispenser = Dispenser::new(id, capabilities);
+        let handle = spawn_dispenser(
+            dispenser.clone(),
+            self.orders.clone(),
+            self.resources.clone(),
+        );
+
+        let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        dispensers.insert(id, Entry { dispenser, handle });
+        drop(dispensers);
+        self.resources
+            .observers()
+            .notify(|observer| observer.dispenser_started(id, capabilities));
+        id
+    }
+
+    /// Asks a dispenser to stop once it finishes its current order.
+    pub fn drain(&self, id: usize) -> Result<(), String> {
+        {
+            let dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+            let entry = dispensers
+                .get(&id)
+                .ok_or(format!("No dispenser with id {}", id))?;
+            entry.dispenser.drain();
+        }
+        self.orders.wake();
+        self.resources
+            .observers()
+            .notify(|observer| observer.dispenser_draining(id));
+        Ok(())
+    }
+
+    /// Drains a dispenser and waits for it to stop, removing it from the pool.
+    pub fn remove(&self, id: usize) -> Result<(), String> {
+        let entry = {
+            let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+            dispensers
+                .remove(&id)
+                .ok_or(format!("No dispenser with id {}", id))?
+        };
+        entry.dispenser.drain();
+        self.orders.wake();
+        self.resources
+            .observers()
+            .notify(|observer| observer.dispenser_draining(id));
+        self.join_entry(entry);
+        Ok(())
+    }
+
+    /// Waits for a dispenser thread to finish, notifying the observers.
+    fn join_entry(&self, entry: Entry) {
+        entry.handle.join().expect("Dispenser Panicked");
+        let id = entry.dispenser.id;
+        self.resources
+            .observers()
+            .notify(|observer| observer.dispenser_stopped(id));
+    }
+
+    /// Removes every stopped dispenser from the pool.
+    pub fn reap(&self) {
+        let stopped: Vec<Entry> = {
+            let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+            let ids: Vec<usize> = dispensers
+                .iter()
+                .filter(|(_, entry)| entry.handle.is_finished())
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter().filter_map(|id| dispensers.remove(id)).collect()
+        };
+        for entry in stopped {
+            self.join_entry(entry);
+        }
+    }
+
+    /// Waits for every dispenser in the pool to stop, removing them.
+    /// Dispensers added while waiting are waited for too.
+    pub fn join(&self) {
+        loop {
+            let entry = {
+                let mut dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+                dispensers.pop_first()
+            };
+            match entry {
+                Some((_, entry)) => self.join_entry(entry),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the id, capabilities and state of every dispenser in the pool.
+    pub fn list(&self) -> Vec<(usize, Capabilities, DispenserState)> {
+        let dispensers = self.dispensers.lock().expect("Failed to lock dispensers");
+        dispensers
+            .iter()
+            .map(|(id, entry)| (*id, entry.dispenser.capabilities, entry.state()))
+            .collect()
+    }
+
+    /// Amount of dispensers taking orders.
+    pub fn running(&self) -> usize {
+        self.list()
+            .iter()
+            .filter(|(_, _, state)| *state == DispenserState::Running)
+            .count()
+    }
+
+    /// Executes an operator command, returning what should be shown to the operator.
+    /// Commands: `add [capabilities]`, `drain <id>`, `remove <id>` and `list`.
+    pub fn execute(&self, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let parse_id = |id: Option<&str>| -> Result<usize, String> {
+            id.ok_or("Missing dispenser id")?
+                .parse()
+                .or(Err("Invalid dispenser id".to_string()))
+        };
+
+        match words.next() {
+            Some("add") => {
+                let capabilities = match words.next() {
+                    Some(capabilities) => capabilities.parse()?,
+                    None => Capabilities::ALL,
+                };
+                let id = self.add(capabilities);
+                Ok(format!("Added dispenser {}", id))
+            }
+            Some("drain") => {
+                let id = parse_id(words.next())?;
+                self.drain(id)?;
+                Ok(format!("Draining dispenser {}", id))
+            }
+            Some("remove") => {
+                let id = parse_id(words.next())?;
+                self.remove(id)?;
+                Ok(format!("Removed dispenser {}", id))
+            }
+            Some("list") => Ok(self
+                .list()
+                .iter()
+                .map(|(id, capabilities, state)| {
+                    format!("Dispenser {} ({}): {}", id, capabilities, state)
+                })
+                .collect::<Vec<String>>()
+                .join("\n")),
+            Some(command) => Err(format!("Unknown command: {}", command)),
+            None => Err("Empty command".to_string()),
+        }
+    }
+
+    /// Adds or drains a dispenser if the queue requires it, according to the policy.
+    fn scale(&self, policy: &ScalingPolicy) {
+        self.reap();
+        let running = self.running();
+        let busy =
+            self.orders.len() > policy.queue_len || self.orders.oldest_wait() > policy.max_wait;
+
+        if running < policy.min || (busy && running < policy.max) {
+            self.add(Capabilities::ALL);
+        } else if self.orders.is_empty() && running > policy.min {
+            let newest = self
+                .list()
+                .into_iter()
+                .rev()
+                .find(|(_, _, state)| *state == DispenserState::Running);
+            if let Some((id, _, _)) = newest {
+                // it may have stopped in between, which is fine
+                let _ = self.drain(id);
+            }
+        }
+    }
+
+    /// Starts scaling the pool automatically in a new thread.
+    /// Returns a handle to stop it.
+    pub fn autoscale(self: &Arc<Self>, policy: ScalingPolicy) -> AutoscalerHandle {
+        let (stop, stopped) = mpsc::channel();
+        let pool = self.clone();
+
+        let handle = thread::spawn(move || loop {
+            pool.scale(&policy);
+            match stopped.recv_timeout(policy.interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        });
+
+        AutoscalerHandle { handle, stop }
+    }
+}
+
+/// Handle to a running autoscaler, to stop it.
+pub struct AutoscalerHandle {
+    handle: JoinHandle<()>,
+    stop: Sender<()>,
+}
+
+impl AutoscalerHandle {
+    /// Stops the autoscaler right away, waiting for it to finish its current check.
+    pub fn stop(self) {
+        // it only fails if the autoscaler already stopped
+        let _ = self.stop.send(());
+        self.handle.join().expect("Autoscaler Panicked");
+    }
+}
+
+#[cfg(test)]
+mod scaling_policy_tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy() {
+        let policy: ScalingPolicy = "min=2,max=5,queue=10,wait=100,interval=50".parse().unwrap();
+        assert_eq!(policy.min, 2);
+        assert_eq!(policy.max, 5);
+        assert_eq!(policy.queue_len, 10);
+        assert_eq!(policy.max_wait, Duration::from_millis(100));
+        assert_eq!(policy.interval, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn parse_invalid_policy() {
+        for input in ["min", "min=-1", "cups=2", "min=3,max=2"] {
+            assert!(input.parse::<ScalingPolicy>().is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod dispenser_pool_tests {
+    use super::*;
+    use crate::coffee_maker::{observer::Observer, orders::Order};
+
+    fn pool() -> (Arc<Orders>, Arc<DispenserPool>) {
+        let orders = Orders::new();
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        (orders.clone(), DispenserPool::new(orders, resources))
+    }
+
+    #[test]
+    fn added_dispensers_have_different_ids() {
+        let (orders, pool) = pool();
+        let a = pool.add(Capabilities::ALL);
+        let b = pool.add(Capabilities::ALL);
+        assert_ne!(a, b);
+        assert_eq!(pool.running(), 2);
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+        assert!(pool.list().is_empty());
+    }
+
+    #[test]
+    fn observers_see_the_lifecycle() {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl Observer for Recorder {
+            fn dispenser_started(&self, dispenser: usize, capabilities: Capabilities) {
+                let event = format!("started {} {}", dispenser, capabilities);
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn dispenser_draining(&self, dispenser: usize) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("draining {}", dispenser));
+            }
+
+            fn dispenser_stopped(&self, dispenser: usize) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("stopped {}", dispenser));
+            }
+        }
+
+        let (orders, pool) = pool();
+        let recorder = Arc::new(Recorder::default());
+        pool.resources.register_observer(recorder.clone());
+        let a = pool.add("water".parse().unwrap());
+        pool.remove(a).unwrap();
+        pool.add(Capabilities::ALL);
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "started 0 water",
+                "draining 0",
+                "stopped 0",
+                "started 1 coffee+water+foam",
+                "stopped 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_dispenser_leaves_the_pool() {
+        let (orders, pool) = pool();
+        let a = pool.add(Capabilities::ALL);
+        let b = pool.add(Capabilities::ALL);
+
+        pool.remove(a).unwrap();
+        assert_eq!(pool.list().len(), 1);
+        assert_eq!(pool.list()[0].0, b);
+        assert!(pool.remove(a).is_err());
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+    }
+
+    #[test]
+    fn drained_dispenser_stops() {
+        let (orders, pool) = pool();
+        let a = pool.add(Capabilities::ALL);
+        pool.drain(a).unwrap();
+
+        while pool.list()[0].2 != DispenserState::Stopped {
+            thread::yield_now();
+        }
+        pool.reap();
+        assert!(pool.list().is_empty());
+
+        // orders are left for other dispensers
+        orders.push(Order::from(10, 10, 10).unwrap());
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn execute_commands() {
+        let (orders, pool) = pool();
+        assert_eq!(pool.execute("add").unwrap(), "Added dispenser 0");
+        assert_eq!(pool.execute("add water").unwrap(), "Added dispenser 1");
+        assert_eq!(
+            pool.execute("list").unwrap(),
+            "Dispenser 0 (coffee+water+foam): running\nDispenser 1 (water): running"
+        );
+        assert_eq!(pool.execute("remove 1").unwrap(), "Removed dispenser 1");
+        assert!(pool.execute("remove 1").is_err());
+        assert!(pool.execute("drain x").is_err());
+        assert!(pool.execute("brew").is_err());
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+    }
+
+    #[test]
+    fn scales_up_when_busy() {
+        let (orders, pool) = pool();
+        let policy = ScalingPolicy {
+            min: 0,
+            max: 1,
+            queue_len: 1,
+            ..ScalingPolicy::default()
+        };
+
+        pool.scale(&policy);
+        assert_eq!(pool.running(), 0);
+
+        orders.push(Order::from(0, 10, 0).unwrap());
+        orders.push(Order::from(0, 10, 0).unwrap());
+        pool.scale(&policy);
+        assert_eq!(pool.running(), 1);
+
+        // never above max
+        orders.push(Order::from(0, 10, 0).unwrap());
+        orders.push(Order::from(0, 10, 0).unwrap());
+        pool.scale(&policy);
+        assert!(pool.list().len() <= 1);
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+    }
+
+    #[test]
+    fn scales_between_min_and_max() {
+        let (orders, pool) = pool();
+        let policy = ScalingPolicy {
+            min: 2,
+            max: 3,
+            ..ScalingPolicy::default()
+        };
+
+        pool.scale(&policy);
+        pool.scale(&policy);
+        assert_eq!(pool.running(), 2);
+
+        pool.add(Capabilities::ALL);
+        pool.scale(&policy);
+        assert_eq!(pool.running(), 2);
+        assert_ne!(pool.list()[2].2, DispenserState::Running);
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+    }
+
+    #[test]
+    fn autoscaler_stops_right_away() {
+        let (orders, pool) = pool();
+        let policy = ScalingPolicy {
+            interval: Duration::from_secs(60),
+            ..ScalingPolicy::default()
+        };
+        let autoscaler = pool.autoscale(policy);
+        while pool.running() < policy.min {
+            thread::yield_now();
+        }
+
+        let started = std::time::Instant::now();
+        autoscaler.stop();
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        orders.push(Order::NoMoreOrders);
+        pool.join();
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    observer::{Action, ActionEvent, Context, Observer},
+    resources_monitor::Levels,
+    Resources,
+};
+
+/// An action read from an event log, without its timings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedEvent {
+    pub sequence: u64,
+    pub context: Context,
+    pub action: Action,
+    pub amount: u32,
+    pub levels: Levels,
+}
+
+impl From<&ActionEvent> for RecordedEvent {
+    fn from(event: &ActionEvent) -> RecordedEvent {
+        RecordedEvent {
+            sequence: event.sequence,
+            context: event.context,
+            action: event.action,
+            amount: event.amount,
+            levels: event.levels,
+        }
+    }
+}
+
+/// Returns the raw value of a field of a flat JSON object, as written by the `EventLog`.
+fn json_field<'a>(line: &'a str, key: &str) -> Result<&'a str, String> {
+    let pattern = format!("\"{}\":", key);
+    let start = line
+        .find(&pattern)
+        .ok_or(format!("Missing field: {}", key))?
+        + pattern.len();
+    let value = &line[start..];
+    let end = value.find([',', '}']).unwrap_or(value.len());
+    Ok(value[..end].trim_matches('"'))
+}
+
+/// Parses a number field of a JSON object.
+fn json_number<T: std::str::FromStr>(line: &str, key: &str) -> Result<T, String> {
+    json_field(line, key)?
+        .parse()
+        .map_err(|_| format!("Invalid field: {}", key))
+}
+
+/// Parses a number field of a JSON object that may be `null`.
+fn json_option<T: std::str::FromStr>(line: &str, key: &str) -> Result<Option<T>, String> {
+    match json_field(line, key)? {
+        "null" => Ok(None),
+        _ => json_number(line, key).map(Some),
+    }
+}
+
+impl RecordedEvent {
+    /// Parses a line of an event log.
+    pub fn from_json(line: &str) -> Result<RecordedEvent, String> {
+        Ok(RecordedEvent {
+            sequence: json_number(line, "seq")?,
+            context: Context {
+                dispenser: json_option(line, "dispenser")?,
+                order: json_option(line, "order")?,
+            },
+            action: json_field(line, "action")?.parse()?,
+            amount: json_number(line, "amount")?,
+            levels: Levels {
+                coffee: json_number(line, "coffee")?,
+                coffee_beans: json_number(line, "coffee_beans")?,
+                foam: json_number(line, "foam")?,
+                milk: json_number(line, "milk")?,
+            },
+        })
+    }
+
+    /// Levels of the containers right before the action.
+    fn levels_before(&self) -> Levels {
+        let mut levels = self.levels;
+        match self.action {
+            Action::Grind => {
+                levels.coffee = levels.coffee.saturating_sub(self.amount);
+                levels.coffee_beans += self.amount;
+            }
+            Action::PourCoffee => levels.coffee += self.amount,
+            Action::Whip => {
+                levels.foam = levels.foam.saturating_sub(self.amount);
+                levels.milk += self.amount;
+            }
+            Action::PourFoam => levels.foam += self.amount,
+            Action::PourWater | Action::Stall => {}
+        }
+        levels
+    }
+}
+
+impl fmt::Display for RecordedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} {} {}", self.sequence, self.action, self.amount)?;
+        if let Some(dispenser) = self.context.dispenser {
+            write!(f, " by dispenser {}", dispenser)?;
+        }
+        if let Some(order) = self.context.order {
+            write!(f, " for order {}", order)?;
+        }
+        write!(
+            f,
+            " -> coffee {}, coffee beans {}, foam {}, milk {}",
+            self.levels.coffee, self.levels.coffee_beans, self.levels.foam, self.levels.milk
+        )
+    }
+}
+
+/// Reads the events of an event log, in the order they were performed.
+pub fn read_events(path: &str) -> io::Result<Vec<RecordedEvent>> {
+    let file = File::open(path)?;
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = RecordedEvent::from_json(&line)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        events.push(event);
+    }
+    events.sort_by_key(|event| event.sequence);
+    Ok(events)
+}
+
+/// The first point where a replay didn't do what was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Sequence number of the recorded action, as in the event log.
+    pub sequence: u64,
+    pub expected: RecordedEvent,
+    /// What the replay did instead, if it did anything.
+    pub actual: Option<RecordedEvent>,
+}
+
+/// Result of replaying an event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// Amount of recorded actions that were reproduced.
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.divergence {
+            None => write!(f, "Replay matched all {} actions", self.matched),
+            Some(divergence) => {
+                writeln!(f, "Replay diverged at action #{}", divergence.sequence)?;
+                writeln!(f, "  expected: {}", divergence.expected)?;
+                match &divergence.actual {
+                    Some(actual) => write!(f, "  actual:   {}", actual),
+                    None => write!(f, "  actual:   nothing"),
+                }
+            }
+        }
+    }
+}
+
+/// Collects the actions performed while replaying.
+#[derive(Default)]
+struct Collector(Mutex<Vec<RecordedEvent>>);
+
+impl Observer for Collector {
+    fn action_performed(&self, event: &ActionEvent) {
+        let mut events = self.0.lock().expect("Failed to lock collector");
+        events.push(RecordedEvent::from(event));
+    }
+}
+
+/// Containers an action works on. Actions on different lanes may interleave
+/// differently between runs, since they take different locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lane {
+    Coffee,
+    Water,
+    Foam,
+}
+
+impl Action {
+    fn lane(&self) -> Lane {
+        match self {
+            Action::Grind | Action::PourCoffee => Lane::Coffee,
+            Action::PourWater | Action::Stall => Lane::Water,
+            Action::Whip | Action::PourFoam => Lane::Foam,
+        }
+    }
+}
+
+/// Times faster than real time the actions are replayed, so that they don't wait at all.
+const REPLAY_SPEEDUP: u32 = u32::MAX;
+
+/// Returns whether two actions did the same, comparing only the containers they work on.
+fn same_action(expected: &RecordedEvent, actual: &RecordedEvent) -> bool {
+    let same_levels = match expected.action.lane() {
+        Lane::Coffee => {
+            expected.levels.coffee == actual.levels.coffee
+                && expected.levels.coffee_beans == actual.levels.coffee_beans
+        }
+        Lane::Water => true,
+        Lane::Foam => {
+            expected.levels.foam == actual.levels.foam && expected.levels.milk == actual.levels.milk
+        }
+    };
+    expected.context == actual.context
+        && expected.action == actual.action
+        && expected.amount == actual.amount
+        && same_levels
+}
+
+/// Replays the given events, sorted by sequence, in a single thread and without waiting for
+/// the actions to take their time.
+///
+/// The resources start with the levels from before the first event, and every pour is made
+/// again on behalf of the same dispenser and order, in the same order. Grinding and whipping
+/// are expected to happen again as a consequence of the pours, while stalls are skipped.
+///
+/// Each action performed is compared with the next recorded one on the same containers,
+/// stopping at the first divergence. Actions on the coffee and on the foam are compared
+/// separately, as they may have been recorded interleaved while the pours are replayed whole.
+pub fn replay(events: &[RecordedEvent]) -> Result<Replay, String> {
+    let recorded: Vec<RecordedEvent> = events
+        .iter()
+        .filter(|event| event.action != Action::Stall)
+        .copied()
+        .collect();
+    let initial = match recorded.first() {
+        Some(first) => first.levels_before(),
+        None => {
+            return Ok(Replay {
+                matched: 0,
+                divergence: None,
+            })
+        }
+    };
+
+    let resources = Resources::new(
+        initial.coffee,
+        initial.coffee_beans,
+        initial.foam,
+        initial.milk,
+    )?;
+    resources.set_speedup(REPLAY_SPEEDUP);
+    let collector = Arc::new(Collector::default());
+    resources.register_observer(collector.clone());
+
+    // steps of each lane that are yet to be reproduced
+    let mut pending: Vec<(Lane, VecDeque<usize>)> = [Lane::Coffee, Lane::Water, Lane::Foam]
+        .into_iter()
+        .map(|lane| {
+            let steps = (0..recorded.len())
+                .filter(|step| recorded[*step].action.lane() == lane)
+                .collect();
+            (lane, steps)
+        })
+        .collect();
+
+    let mut matched = 0;
+    for (step, event) in recorded.iter().enumerate() {
+        // a failing pour shows up as a missing action
+        let _ = match event.action {
+            Action::PourCoffee => resources.use_coffee_for(event.context, event.amount),
+            Action::PourWater => resources.use_water_for(event.context, event.amount),
+            Action::PourFoam => resources.use_foam_for(event.context, event.amount),
+            _ => continue,
+        };
+
+        let performed: Vec<RecordedEvent> = collector
+            .0
+            .lock()
+            .expect("Failed to lock collector")
+            .drain(..)
+            .collect();
+        for actual in performed {
+            let lane = actual.action.lane();
+            let steps = &mut pending
+                .iter_mut()
+                .find(|(other, _)| *other == lane)
+                .expect("Every lane is pending")
+                .1;
+            match steps.pop_front() {
+                Some(expected) if same_action(&recorded[expected], &actual) => matched += 1,
+                Some(expected) => return Ok(diverged(matched, recorded[expected], Some(actual))),
+                None => return Ok(diverged(matched, *event, Some(actual))),
+            }
+        }
+
+        // the pour itself, and whatever was recorded before it on its lane, must be done
+        let lane = event.action.lane();
+        let steps = &pending
+            .iter()
+            .find(|(other, _)| *other == lane)
+            .expect("Every lane is pending")
+            .1;
+        if let Some(expected) = steps.front().filter(|expected| **expected <= step) {
+            return Ok(diverged(matched, recorded[*expected], None));
+        }
+    }
+
+    let missing = pending.iter().filter_map(|(_, steps)| steps.front()).min();
+    match missing {
+        Some(expected) => Ok(diverged(matched, recorded[*expected], None)),
+        None => Ok(Replay {
+            matched,
+            divergence: None,
+        }),
+    }
+}
+
+/// Creates the result of a replay that diverged at the given recorded action,
+/// after reproducing the given amount of them.
+fn diverged(matched: usize, expected: RecordedEvent, actual: Option<RecordedEvent>) -> Replay {
+    Replay {
+        matched,
+        divergence: Some(Divergence {
+            sequence: expected.sequence,
+            expected,
+            actual,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        capabilities::Capabilities, event_log::to_json, machine::CoffeeMaker, orders::Order,
+    };
+
+    /// Records the actions of a concurrent run.
+    fn record() -> Vec<RecordedEvent> {
+        let collector = Arc::new(Collector::default());
+        let resources = Resources::new(0, 500, 0, 500).unwrap();
+        resources.register_observer(collector.clone());
+        let machine = CoffeeMaker::new("test", resources, &[Capabilities::ALL; 3]);
+        for _ in 0..10 {
+            machine.orders().push(Order::from(30, 10, 20).unwrap());
+        }
+        machine.shutdown();
+
+        let mut events = collector.0.lock().unwrap().clone();
+        events.sort_by_key(|event| event.sequence);
+        events
+    }
+
+    #[test]
+    fn parses_logged_events() {
+        let event = ActionEvent {
+            sequence: 3,
+            started: std::time::Instant::now(),
+            at: std::time::SystemTime::now(),
+            context: Context::new(1, 2),
+            action: Action::Whip,
+            amount: 20,
+            duration: std::time::Duration::from_millis(40),
+            levels: Levels {
+                coffee: 1,
+                coffee_beans: 2,
+                foam: 20,
+                milk: 4,
+            },
+        };
+        let parsed = RecordedEvent::from_json(&to_json(&event)).unwrap();
+        assert_eq!(parsed, RecordedEvent::from(&event));
+    }
+
+    #[test]
+    fn parses_events_without_context() {
+        let line = "{\"seq\":1,\"timestamp_ms\":0,\"dispenser\":null,\"order\":null,\"action\":\"pour_water\",\"amount\":5,\"duration_ms\":1,\"levels\":{\"coffee\":0,\"coffee_beans\":0,\"foam\":0,\"milk\":0}}";
+        let parsed = RecordedEvent::from_json(line).unwrap();
+        assert_eq!(parsed.context, Context::default());
+        assert_eq!(parsed.action, Action::PourWater);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        assert!(RecordedEvent::from_json("{\"seq\":1}").is_err());
+        assert!(RecordedEvent::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn replays_a_concurrent_run() {
+        let events = record();
+        let replay = replay(&events).unwrap();
+        assert_eq!(replay.divergence, None);
+        assert_eq!(replay.matched, events.len());
+    }
+
+    #[test]
+    fn points_out_the_first_divergence() {
+        let mut events = record();
+        let step = events
+            .iter()
+            .position(|event| event.action == Action::PourFoam)
+            .unwrap();
+        events[step].levels.foam += 1;
+
+        let replay = replay(&events).unwrap();
+        let divergence = replay.divergence.unwrap();
+        assert_eq!(divergence.sequence, events[step].sequence);
+        assert_eq!(divergence.expected, events[step]);
+        assert_eq!(
+            divergence.actual.unwrap().levels.foam + 1,
+            events[step].levels.foam
+        );
+    }
+
+    #[test]
+    fn divergences_are_reported_by_their_recorded_sequence() {
+        let mut events = record();
+        // a stall before the divergence doesn't shift it
+        let stall = RecordedEvent {
+            action: Action::Stall,
+            amount: 0,
+            ..events[0]
+        };
+        events.insert(0, stall);
+        for (sequence, event) in events.iter_mut().enumerate() {
+            event.sequence = sequence as u64 + 1;
+        }
+        let step = events
+            .iter()
+            .position(|event| event.action == Action::PourCoffee)
+            .unwrap();
+        events[step].levels.coffee += 1;
+
+        let replay = replay(&events).unwrap();
+        let divergence = replay.divergence.as_ref().unwrap();
+        assert_eq!(divergence.sequence, events[step].sequence);
+        assert_eq!(replay.matched, step - 1);
+        assert!(replay
+            .to_string()
+            .contains(&format!("diverged at action #{}", events[step].sequence)));
+    }
+
+    #[test]
+    fn missing_actions_diverge() {
+        let mut events = record();
+        // the pour can't be made without the grinding before it
+        let grind = events
+            .iter()
+            .position(|event| event.action == Action::Grind)
+            .unwrap();
+        events[grind].amount = 0;
+
+        let replay = replay(&events).unwrap();
+        assert!(replay.divergence.is_some());
+    }
+
+    #[test]
+    fn empty_log_matches() {
+        let replay = replay(&[]).unwrap();
+        assert_eq!(replay.matched, 0);
+        assert_eq!(replay.divergence, None);
+    }
+}
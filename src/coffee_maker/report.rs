@@ -0,0 +1,61 @@
+use std::fmt;
+
+use super::{eta::EtaAccuracy, faults::Fault, latency::LatencyReport};
+
+/// Summary of a coffee maker run, produced when it shuts down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub name: String,
+    pub completed: u64,
+    pub failed: u64,
+    pub retried: u64,
+    pub faults: Vec<(Fault, u64)>,
+    /// Orders that no dispenser could make when they were taken.
+    pub rejected: u64,
+    /// Orders dropped because their customers left before they were taken.
+    pub abandoned: u64,
+    /// Orders left in the queue when the dispensers stopped.
+    pub unserved: usize,
+    /// Final status of the resources, as shown by the monitor.
+    pub resources: String,
+    /// How long the completed orders took.
+    pub latency: LatencyReport,
+    /// How close the estimates given to the orders were, if they were estimated.
+    pub eta: Option<EtaAccuracy>,
+}
+
+impl Report {
+    /// Share of the orders taken by the dispensers or abandoned whose customers left.
+    pub fn abandonment_rate(&self) -> f64 {
+        match self.completed + self.failed + self.abandoned {
+            0 => 0.0,
+            orders => self.abandoned as f64 / orders as f64,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    /// Displays the report, one value per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[{}]", self.name)?;
+        write!(f, "{}", self.resources)?;
+        writeln!(f, "Completed: {}", self.completed)?;
+        writeln!(f, "Failed: {}", self.failed)?;
+        writeln!(f, "Retried: {}", self.retried)?;
+        for (fault, count) in &self.faults {
+            writeln!(f, "Faults ({}): {}", fault, count)?;
+        }
+        writeln!(f, "Rejected: {}", self.rejected)?;
+        writeln!(
+            f,
+            "Abandoned: {} ({:.1}%)",
+            self.abandoned,
+            self.abandonment_rate() * 100.0
+        )?;
+        writeln!(f, "Unserved: {}", self.unserved)?;
+        if let Some(eta) = &self.eta {
+            write!(f, "{}", eta)?;
+        }
+        write!(f, "{}", self.latency)
+    }
+}
@@ -1,11 +1,25 @@
 use std::{
-    sync::{atomic::AtomicBool, Arc, Mutex, MutexGuard},
-    thread::JoinHandle,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use super::{
+    alerts::{Alert, AlertPolicy, AlertSink, AlertSinks},
     config,
-    resources_monitor::{monitor_resources, ResourcesMonitor},
+    faults::{Actuator, Fault, FaultInjector, SLOW_ACTUATOR_FACTOR},
+    observer::{Action, ActionEvent, Container, Context, Ingredient, Observer, Observers},
+    orders::Ingredients,
+    resources_monitor::{
+        monitor_resources, Levels, MonitorHandle, MonitorMode, ResourcesMonitor, Totals,
+    },
+    snapshot::MachineSnapshot,
+    stats::Stats,
 };
 
 pub mod sleep {
@@ -39,10 +53,111 @@ const GRIND_COFFEE_TIME_PER_MG: u32 = 50 / SPEED;
 const WHIP_MILK_FIXED_TIME: u32 = 4 / SPEED;
 const WHIP_MILK_TIME_PER_MG: u32 = 60 / SPEED;
 
+/// Milliseconds it takes to pour the given amount of coffee.
+pub(crate) fn coffee_time(amount: u32) -> u32 {
+    amount * COFFEE_TIME_PER_MG + COFFEE_FIXED_TIME
+}
+
+/// Milliseconds it takes to pour the given amount of water.
+pub(crate) fn water_time(amount: u32) -> u32 {
+    amount * WATER_TIME_PER_ML + WATER_FIXED_TIME
+}
+
+/// Milliseconds it takes to pour the given amount of foam.
+pub(crate) fn foam_time(amount: u32) -> u32 {
+    amount * FOAM_TIME_PER_ML + FOAM_FIXED_TIME
+}
+
+/// Milliseconds it takes to grind the given amount of coffee beans.
+pub(crate) fn grind_time(amount: u32) -> u32 {
+    amount * GRIND_COFFEE_TIME_PER_MG + GRIND_COFFEE_FIXED_TIME
+}
+
+/// Milliseconds it takes to whip the given amount of milk.
+pub(crate) fn whip_time(amount: u32) -> u32 {
+    amount * WHIP_MILK_TIME_PER_MG + WHIP_MILK_FIXED_TIME
+}
+
+/// How the containers are locked while they are used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockGranularity {
+    /// Coffee and foam can be used at the same time, each locking its own containers.
+    #[default]
+    PerContainer,
+    /// Using coffee or foam locks the whole machine.
+    Machine,
+}
+
+impl FromStr for LockGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LockGranularity, String> {
+        match s {
+            "container" => Ok(LockGranularity::PerContainer),
+            "machine" => Ok(LockGranularity::Machine),
+            _ => Err(format!("Unknown lock granularity: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LockGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LockGranularity::PerContainer => "container",
+            LockGranularity::Machine => "machine",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How much coffee beans or milk is transformed when there isn't enough coffee or foam.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RefillPolicy {
+    /// Only what is missing for the order, keeping the ingredients fresh.
+    #[default]
+    JustEnough,
+    /// As much as fits in the container, so that the next orders don't have to wait.
+    Fill,
+}
+
+impl RefillPolicy {
+    /// Amount to transform when `needed` is missing, with room for `room` in the container
+    /// and `available` left to transform.
+    pub(crate) fn amount(&self, needed: u32, room: u32, available: u32) -> u32 {
+        match self {
+            RefillPolicy::JustEnough => needed,
+            RefillPolicy::Fill => needed.max(room.min(available)),
+        }
+    }
+}
+
+impl FromStr for RefillPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RefillPolicy, String> {
+        match s {
+            "just-enough" => Ok(RefillPolicy::JustEnough),
+            "fill" => Ok(RefillPolicy::Fill),
+            _ => Err(format!("Unknown refill policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for RefillPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RefillPolicy::JustEnough => "just-enough",
+            RefillPolicy::Fill => "fill",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// Resource Errors
 #[derive(Debug, Clone)]
 pub enum Error {
     InsufficientResources,
+    Fault(Fault),
 }
 
 /// Result Wrapper for Resource
@@ -55,7 +170,17 @@ pub struct Resources {
     coffee_beans: Mutex<u32>,
     foam: Mutex<u32>,
     milk: Mutex<u32>,
+    /// Held while using coffee or foam, when locking the whole machine.
+    machine: Mutex<()>,
+    granularity: RwLock<LockGranularity>,
+    refill: RwLock<RefillPolicy>,
+    /// How many times faster than real time the actions take.
+    speedup: AtomicU32,
     monitor: Arc<Mutex<ResourcesMonitor>>,
+    faults: RwLock<Option<Arc<FaultInjector>>>,
+    stats: Arc<Stats>,
+    observers: Observers,
+    alert_sinks: AlertSinks,
 }
 
 impl Resources {
@@ -80,36 +205,117 @@ impl Resources {
                 coffee_beans: Mutex::new(coffee_beans),
                 foam: Mutex::new(foam),
                 milk: Mutex::new(milk),
+                machine: Mutex::new(()),
+                granularity: RwLock::new(LockGranularity::default()),
+                refill: RwLock::new(RefillPolicy::default()),
+                speedup: AtomicU32::new(1),
                 monitor: Arc::new(Mutex::new(ResourcesMonitor::new(
                     coffee,
                     coffee_beans,
                     foam,
                     milk,
                 ))),
+                faults: RwLock::new(None),
+                stats: Arc::new(Stats::new()),
+                observers: Observers::new(),
+                alert_sinks: AlertSinks::default(),
             }))
         }
     }
 
+    /// Locks the whole machine, if its containers aren't locked on their own.
+    fn lock_machine(&self) -> Option<MutexGuard<'_, ()>> {
+        let granularity = self.granularity.read().expect("Failed to lock granularity");
+        match *granularity {
+            LockGranularity::PerContainer => None,
+            LockGranularity::Machine => Some(self.machine.lock().expect("Failed to lock machine")),
+        }
+    }
+
+    /// Amount to transform when `needed` is missing, with room for `room` in the container
+    /// and `available` left to transform.
+    fn refill_amount(&self, needed: i64, room: u32, available: u32) -> i64 {
+        let refill = self.refill.read().expect("Failed to lock refill policy");
+        refill.amount(needed as u32, room, available) as i64
+    }
+
+    /// Sets how the containers are locked while they are used.
+    pub fn set_lock_granularity(&self, granularity: LockGranularity) {
+        *self
+            .granularity
+            .write()
+            .expect("Failed to lock granularity") = granularity;
+    }
+
+    /// Sets how much coffee beans or milk is transformed when there isn't enough coffee or foam.
+    pub fn set_refill_policy(&self, refill: RefillPolicy) {
+        *self.refill.write().expect("Failed to lock refill policy") = refill;
+    }
+
+    /// Returns how much coffee beans or milk is transformed when there isn't enough.
+    pub fn refill_policy(&self) -> RefillPolicy {
+        *self.refill.read().expect("Failed to lock refill policy")
+    }
+
+    /// Makes every action the given times shorter, to run faster than real time.
+    pub fn set_speedup(&self, speedup: u32) {
+        self.speedup.store(speedup.max(1), Ordering::Relaxed);
+    }
+
+    /// Returns how many times faster than real time the actions take.
+    pub fn speedup(&self) -> u32 {
+        self.speedup.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the given time, shortened by the speedup.
+    pub(crate) fn sleep(&self, duration: Duration) {
+        sleep::sleep(duration / self.speedup());
+    }
+
     /// Transforms the required amount of coffee_beans into coffee.
     /// Takes time according to the amount.
     fn grind_needed_coffee_beans<'cof>(
+        &self,
+        context: Context,
         mut coffee: MutexGuard<'cof, u32>,
         mut coffee_beans: MutexGuard<u32>,
         amount: u32,
-        monitor: &Mutex<ResourcesMonitor>,
     ) -> Result<MutexGuard<'cof, u32>, Error> {
         let needed = amount as i64 - *coffee as i64;
         if needed > *coffee_beans as i64 {
             Err(Error::InsufficientResources)
         } else if needed > 0 {
-            let duration = needed as u32 * GRIND_COFFEE_TIME_PER_MG + GRIND_COFFEE_FIXED_TIME;
-            sleep::sleep(std::time::Duration::from_millis(duration.into()));
-            *coffee_beans -= needed as u32;
-            *coffee += needed as u32;
-
-            let mut monitor = monitor.lock().unwrap();
-            monitor.update_coffee(*coffee);
-            monitor.update_coffee_beans(*coffee_beans);
+            let needed = self.refill_amount(needed, config::C - *coffee, *coffee_beans);
+            let mut duration = grind_time(needed as u32);
+            match self.roll_fault(Actuator::Grinder) {
+                Some(Fault::SlowActuator) => duration *= SLOW_ACTUATOR_FACTOR,
+                Some(fault) => return Err(Error::Fault(fault)),
+                None => {}
+            }
+            let needed = needed as u32;
+            self.observers
+                .notify(|observer| observer.grinder_started(context, needed));
+            let started = Instant::now();
+            self.sleep(Duration::from_millis(duration.into()));
+            *coffee_beans -= needed;
+            *coffee += needed;
+
+            let (recorded, alerts) = self.update_monitor(|monitor| {
+                monitor.update_coffee(*coffee);
+                monitor.update_coffee_beans(*coffee_beans);
+                monitor.record_action()
+            });
+
+            self.observers
+                .notify(|observer| observer.grinder_finished(context, needed));
+            self.notify_crossed(&alerts);
+            self.notify_recorded(
+                context,
+                Action::Grind,
+                needed,
+                (started, duration),
+                recorded,
+            );
             Ok(coffee)
         } else {
             Ok(coffee)
@@ -119,54 +325,107 @@ impl Resources {
     /// Reduces the required amount of coffee.
     /// Takes time according to the amount.
     pub fn use_coffee(&self, amount: u32) -> ResourceResult {
+        self.use_coffee_for(Context::default(), amount)
+    }
+
+    /// Reduces the required amount of coffee, on behalf of the given context.
+    /// Takes time according to the amount.
+    pub fn use_coffee_for(&self, context: Context, amount: u32) -> ResourceResult {
+        self.observers
+            .notify(|observer| observer.lock_waiting(context, Container::Coffee));
+        let waiting = Instant::now();
+        let _machine = self.lock_machine();
         let coffee = self.coffee.lock().expect("Failed to lock coffee");
         let coffee_beans = self
             .coffee_beans
             .lock()
             .expect("Failed to lock coffee beans");
+        let waited = waiting.elapsed();
+        self.observers
+            .notify(|observer| observer.lock_acquired(context, Container::Coffee, waited));
 
-        let mut coffee =
-            Self::grind_needed_coffee_beans(coffee, coffee_beans, amount, &self.monitor)?;
+        let mut coffee = self.grind_needed_coffee_beans(context, coffee, coffee_beans, amount)?;
 
-        let duration = amount * COFFEE_TIME_PER_MG + COFFEE_FIXED_TIME;
-        sleep::sleep(std::time::Duration::from_millis(duration.into()));
+        let duration = coffee_time(amount);
+        let started = Instant::now();
+        self.sleep(Duration::from_millis(duration.into()));
         *coffee -= amount;
 
-        let mut monitor = self.monitor.lock().expect("Failed to lock monitor");
-        monitor.update_coffee(*coffee);
-
+        let (recorded, alerts) = self.update_monitor(|monitor| {
+            monitor.update_coffee(*coffee);
+            monitor.record_action()
+        });
+
+        self.observers
+            .notify(|observer| observer.ingredient_dispensed(context, Ingredient::Coffee, amount));
+        self.notify_crossed(&alerts);
+        self.notify_recorded(
+            context,
+            Action::PourCoffee,
+            amount,
+            (started, duration),
+            recorded,
+        );
         Ok(())
     }
 
     /// Simulates using the required amount of water.
     /// Takes time according to the amount.
     pub fn use_water(&self, amount: u32) -> ResourceResult {
-        let duration = amount * WATER_TIME_PER_ML + WATER_FIXED_TIME;
-        sleep::sleep(std::time::Duration::from_millis(duration.into()));
+        self.use_water_for(Context::default(), amount)
+    }
+
+    /// Simulates using the required amount of water, on behalf of the given context.
+    /// Takes time according to the amount.
+    pub fn use_water_for(&self, context: Context, amount: u32) -> ResourceResult {
+        let duration = water_time(amount);
+        let started = Instant::now();
+        self.sleep(Duration::from_millis(duration.into()));
+
+        self.observers
+            .notify(|observer| observer.ingredient_dispensed(context, Ingredient::Water, amount));
+        self.notify_action(context, Action::PourWater, amount, (started, duration));
         Ok(())
     }
 
     /// Transforms the required amount of milk into foam.
     /// Takes time according to the amount.
     fn whip_needed_foam<'cof>(
+        &self,
+        context: Context,
         mut foam: MutexGuard<'cof, u32>,
         mut milk: MutexGuard<u32>,
         amount: u32,
-        monitor: &Mutex<ResourcesMonitor>,
     ) -> Result<MutexGuard<'cof, u32>, Error> {
         let needed = amount as i64 - *foam as i64;
         if needed > *milk as i64 {
             Err(Error::InsufficientResources)
         } else if needed > 0 {
-            let duration = needed as u32 * WHIP_MILK_TIME_PER_MG + WHIP_MILK_FIXED_TIME;
-            sleep::sleep(std::time::Duration::from_millis(duration.into()));
-            *milk -= needed as u32;
-            *foam += needed as u32;
-
-            let mut monitor = monitor.lock().unwrap();
-            monitor.update_foam(*foam);
-            monitor.update_milk(*milk);
-
+            let needed = self.refill_amount(needed, config::E - *foam, *milk);
+            let mut duration = whip_time(needed as u32);
+            match self.roll_fault(Actuator::Whipper) {
+                Some(Fault::SlowActuator) => duration *= SLOW_ACTUATOR_FACTOR,
+                Some(fault) => return Err(Error::Fault(fault)),
+                None => {}
+            }
+            let needed = needed as u32;
+            self.observers
+                .notify(|observer| observer.whipper_started(context, needed));
+            let started = Instant::now();
+            self.sleep(Duration::from_millis(duration.into()));
+            *milk -= needed;
+            *foam += needed;
+
+            let (recorded, alerts) = self.update_monitor(|monitor| {
+                monitor.update_foam(*foam);
+                monitor.update_milk(*milk);
+                monitor.record_action()
+            });
+
+            self.observers
+                .notify(|observer| observer.whipper_finished(context, needed));
+            self.notify_crossed(&alerts);
+            self.notify_recorded(context, Action::Whip, needed, (started, duration), recorded);
             Ok(foam)
         } else {
             Ok(foam)
@@ -176,34 +435,247 @@ impl Resources {
     /// Reduces the required amount of foam.
     /// Takes time according to the amount.
     pub fn use_foam(&self, amount: u32) -> ResourceResult {
+        self.use_foam_for(Context::default(), amount)
+    }
+
+    /// Reduces the required amount of foam, on behalf of the given context.
+    /// Takes time according to the amount.
+    pub fn use_foam_for(&self, context: Context, amount: u32) -> ResourceResult {
+        self.observers
+            .notify(|observer| observer.lock_waiting(context, Container::Foam));
+        let waiting = Instant::now();
+        let _machine = self.lock_machine();
         let foam = self.foam.lock().expect("Failed to lock foam");
         let milk = self.milk.lock().expect("Failed to lock milk");
+        let waited = waiting.elapsed();
+        self.observers
+            .notify(|observer| observer.lock_acquired(context, Container::Foam, waited));
 
-        let mut foam = Self::whip_needed_foam(foam, milk, amount, &self.monitor)?;
+        let mut foam = self.whip_needed_foam(context, foam, milk, amount)?;
 
-        let duration = amount * FOAM_TIME_PER_ML + FOAM_FIXED_TIME;
-        sleep::sleep(std::time::Duration::from_millis(duration.into()));
+        let duration = foam_time(amount);
+        let started = Instant::now();
+        self.sleep(Duration::from_millis(duration.into()));
         *foam -= amount;
 
+        let (recorded, alerts) = self.update_monitor(|monitor| {
+            monitor.update_foam(*foam);
+            monitor.record_action()
+        });
+
+        self.observers
+            .notify(|observer| observer.ingredient_dispensed(context, Ingredient::Foam, amount));
+        self.notify_crossed(&alerts);
+        self.notify_recorded(
+            context,
+            Action::PourFoam,
+            amount,
+            (started, duration),
+            recorded,
+        );
+        Ok(())
+    }
+
+    /// Updates the monitor, then delivers the alerts raised by the update.
+    /// Returns them, so that the observers can be told about the thresholds crossed.
+    fn update_monitor<T>(
+        &self,
+        update: impl FnOnce(&mut ResourcesMonitor) -> T,
+    ) -> (T, Vec<Alert>) {
         let mut monitor = self.monitor.lock().expect("Failed to lock monitor");
-        monitor.update_foam(*foam);
+        let result = update(&mut monitor);
+        let alerts = monitor.take_alerts();
+        drop(monitor);
 
-        Ok(())
+        self.alert_sinks.deliver(&alerts);
+        (result, alerts)
+    }
+
+    /// Sets when to alert about the containers, starting from their current levels.
+    pub fn set_alert_policy(&self, policy: AlertPolicy) {
+        let mut monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.set_alert_policy(policy);
+    }
+
+    /// Returns when the containers are alerted about.
+    pub fn alert_policy(&self) -> AlertPolicy {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.alert_policy()
+    }
+
+    /// Adds a sink where the alerts about the containers will be delivered.
+    pub fn add_alert_sink(&self, sink: Arc<dyn AlertSink>) {
+        self.alert_sinks.register(sink);
+    }
+
+    /// Notifies the observers of an action that started at the given instant and took
+    /// the given milliseconds, recording it as performed right now.
+    pub fn notify_action(
+        &self,
+        context: Context,
+        action: Action,
+        amount: u32,
+        timing: (Instant, u32),
+    ) {
+        let mut monitor = self.monitor.lock().expect("Failed to lock monitor");
+        let recorded = monitor.record_action();
+        drop(monitor);
+        self.notify_recorded(context, action, amount, timing, recorded);
+    }
+
+    /// Notifies the observers of an already recorded action that started at the given instant
+    /// and took the given milliseconds.
+    fn notify_recorded(
+        &self,
+        context: Context,
+        action: Action,
+        amount: u32,
+        (started, duration_millis): (Instant, u32),
+        (sequence, levels): (u64, Levels),
+    ) {
+        let event = ActionEvent {
+            sequence,
+            started,
+            at: SystemTime::now(),
+            context,
+            action,
+            amount,
+            duration: Duration::from_millis(duration_millis.into()),
+            levels,
+        };
+        self.observers
+            .notify(|observer| observer.action_performed(&event));
     }
 
-    /// Starts the monitor. this will print the current resources at an interval.
-    /// Returns a handle to the monitor thread and an AtomicBool to stop it.
-    /// The AtomicBool is set to true when the monitor is stopped.
-    pub fn monitor(&self, interval_millis: u64) -> (JoinHandle<()>, Arc<AtomicBool>) {
+    /// Notifies the observers of the containers that went below or back above their warning
+    /// threshold, going by the given alerts.
+    fn notify_crossed(&self, alerts: &[Alert]) {
+        let crossings = alerts
+            .iter()
+            .filter(|alert| alert.severity.is_some() != alert.previous.is_some());
+        for alert in crossings {
+            let low = alert.severity.is_some();
+            self.observers
+                .notify(|observer| observer.threshold_crossed(alert.container, alert.level, low));
+        }
+    }
+
+    /// Registers an observer of the resources, and of the dispensers using them.
+    pub fn register_observer(&self, observer: Arc<dyn Observer>) {
+        self.observers.register(observer);
+    }
+
+    /// Observers of the resources, and of the dispensers using them.
+    pub fn observers(&self) -> &Observers {
+        &self.observers
+    }
+
+    /// Starts the monitor. this will print the current resources at an interval, in the given mode,
+    /// and a final snapshot with the totals when stopped.
+    /// Returns a handle to ask the monitor for snapshots and to stop it.
+    pub fn monitor(&self, interval_millis: u64, mode: MonitorMode) -> MonitorHandle {
+        self.monitor_to(interval_millis, mode, Box::new(io::stdout()))
+    }
+
+    /// Starts the monitor like `monitor`, writing to the given output.
+    pub fn monitor_to(
+        &self,
+        interval_millis: u64,
+        mode: MonitorMode,
+        output: Box<dyn Write + Send>,
+    ) -> MonitorHandle {
         let monitor = self.monitor.clone();
-        monitor_resources(monitor, interval_millis)
+        monitor_resources(monitor, self.stats.clone(), interval_millis, mode, output)
+    }
+
+    /// Returns whether there are enough resources left for the given ingredients.
+    pub fn can_make(&self, ingredients: &Ingredients) -> bool {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.can_make(ingredients)
+    }
+
+    /// Amount of coffee and foam that could still be served, counting beans and milk.
+    pub fn remaining(&self) -> u32 {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.remaining()
+    }
+
+    /// Returns the current levels of the containers.
+    pub fn levels(&self) -> Levels {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.levels()
+    }
+
+    /// Returns a snapshot of the resources and counters, taken while the monitor is locked.
+    pub fn snapshot(&self) -> MachineSnapshot {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        MachineSnapshot::new(&monitor, &self.stats)
+    }
+
+    /// Returns the current status of the resources, as shown by the monitor.
+    pub fn status(&self) -> String {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.to_string()
+    }
+
+    /// Returns a snapshot of the resources as shown by the monitor in the given mode,
+    /// of the coffee maker with the given name if any.
+    pub fn render(&self, mode: MonitorMode, name: Option<&str>) -> String {
+        mode.render(&self.named_snapshot(name))
+    }
+
+    /// Returns the last snapshot of the resources in the given mode, along with the totals,
+    /// of the coffee maker with the given name if any.
+    pub fn render_final(&self, mode: MonitorMode, name: Option<&str>) -> String {
+        mode.render_final(&self.named_snapshot(name))
+    }
+
+    /// Takes a snapshot of the resources, of the coffee maker with the given name if any.
+    fn named_snapshot(&self, name: Option<&str>) -> MachineSnapshot {
+        let mut snapshot = self.snapshot();
+        snapshot.machine = name.map(str::to_string);
+        snapshot
+    }
+
+    /// Returns the amounts transformed and served so far.
+    pub fn totals(&self) -> Totals {
+        let monitor = self.monitor.lock().expect("Failed to lock monitor");
+        monitor.totals()
+    }
+
+    /// Starts injecting faults into the grinder, the whipper and the dispensers.
+    pub fn inject_faults(&self, injector: Arc<FaultInjector>) {
+        let mut faults = self.faults.write().expect("Failed to lock faults");
+        *faults = Some(injector);
+    }
+
+    /// Rolls for a fault on the given actuator, counting it if it happens.
+    /// Never faults if no injector was set.
+    pub fn roll_fault(&self, actuator: Actuator) -> Option<Fault> {
+        let faults = self.faults.read().expect("Failed to lock faults");
+        let fault = faults.as_ref()?.roll(actuator);
+        if let Some(fault) = fault {
+            self.stats.fault(fault);
+        }
+        fault
+    }
+
+    /// Returns the counters of the coffee maker.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
     }
 }
 
 #[cfg(test)]
 mod resources_test {
 
+    use std::sync::Mutex;
+
     use super::*;
+    use crate::coffee_maker::{
+        alerts::{Alert, Severity},
+        faults::FaultConfig,
+    };
 
     #[test]
     fn can_use_water() {
@@ -271,4 +743,150 @@ mod resources_test {
         resources.use_coffee(20).expect_err("Should have failed");
         resources.use_foam(20).expect_err("Should have failed");
     }
+
+    #[test]
+    fn jammed_grinder_does_not_consume_beans() {
+        let resources = Resources::new(0, 100, 0, 0).unwrap();
+        let mut config = FaultConfig::new(0);
+        config.jam = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+
+        let err = resources.use_coffee(100).expect_err("Should have jammed");
+        assert!(matches!(err, Error::Fault(Fault::Jam)));
+        assert_eq!(resources.stats().faults(Fault::Jam), 1);
+
+        resources.inject_faults(Arc::new(FaultInjector::new(FaultConfig::new(0))));
+        resources
+            .use_coffee(100)
+            .expect("Beans should still be there");
+    }
+
+    #[test]
+    fn misread_whipper_does_not_consume_milk() {
+        let resources = Resources::new(0, 0, 0, 100).unwrap();
+        let mut config = FaultConfig::new(0);
+        config.sensor_misread = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+
+        let err = resources.use_foam(100).expect_err("Should have misread");
+        assert!(matches!(err, Error::Fault(Fault::SensorMisread)));
+        assert_eq!(resources.stats().faults(Fault::SensorMisread), 1);
+    }
+
+    #[test]
+    fn slow_grinder_still_grinds() {
+        let resources = Resources::new(0, 100, 0, 0).unwrap();
+        let mut config = FaultConfig::new(0);
+        config.slow_actuator = 1.0;
+        resources.inject_faults(Arc::new(FaultInjector::new(config)));
+
+        resources.use_coffee(100).unwrap();
+        assert_eq!(resources.stats().faults(Fault::SlowActuator), 1);
+    }
+
+    #[test]
+    fn observers_see_thresholds_crossed() {
+        #[derive(Default)]
+        struct Thresholds(Mutex<Vec<(Container, u32, bool)>>);
+
+        impl Observer for Thresholds {
+            fn threshold_crossed(&self, container: Container, level: u32, low: bool) {
+                self.0.lock().unwrap().push((container, level, low));
+            }
+        }
+
+        let threshold = config::G * config::X / 100;
+        let resources = Resources::new(0, 2 * threshold, 0, 0).unwrap();
+        let thresholds = Arc::new(Thresholds::default());
+        resources.register_observer(thresholds.clone());
+
+        let crossed = |container: Container| {
+            let crossed = thresholds.0.lock().unwrap();
+            crossed
+                .iter()
+                .filter(|(other, _, _)| *other == container)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        resources.use_coffee(threshold / 2).unwrap();
+        assert!(crossed(Container::CoffeeBeans).is_empty());
+        resources.use_coffee(threshold / 2 + 1).unwrap();
+        assert_eq!(
+            crossed(Container::CoffeeBeans),
+            vec![(Container::CoffeeBeans, threshold - 1, true)]
+        );
+
+        // the ground coffee goes back above its threshold while grinding, then empties again
+        let coffee = crossed(Container::Coffee);
+        assert_eq!(coffee.len(), 4);
+        assert_eq!(coffee[0], (Container::Coffee, threshold / 2, false));
+        assert_eq!(coffee[1], (Container::Coffee, 0, true));
+    }
+
+    #[test]
+    fn alerts_are_delivered_when_crossing() {
+        let resources = Resources::new(0, config::G, 0, 0).unwrap();
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let received = alerts.clone();
+        resources.add_alert_sink(Arc::new(move |alert: &Alert| {
+            received
+                .lock()
+                .unwrap()
+                .push((alert.container, alert.severity));
+        }));
+        resources.set_alert_policy(AlertPolicy {
+            warning: 95,
+            critical: 50,
+            hysteresis: 0,
+        });
+
+        // the ground coffee is refilled, then emptied
+        resources.use_coffee(config::C).unwrap();
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec![
+                (Container::Coffee, None),
+                (Container::CoffeeBeans, Some(Severity::Warning)),
+                (Container::Coffee, Some(Severity::Critical))
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_policy_refills_the_container() {
+        let resources = Resources::new(0, config::G, 0, 100).unwrap();
+        resources.set_refill_policy(RefillPolicy::Fill);
+        resources.use_coffee(10).unwrap();
+        resources.use_foam(10).unwrap();
+        assert_eq!(
+            resources.levels(),
+            Levels {
+                coffee: config::C - 10,
+                coffee_beans: config::G - config::C,
+                foam: 90,
+                milk: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn machine_lock_serves_every_ingredient() {
+        let resources = Resources::new(10, 0, 10, 0).unwrap();
+        resources.set_lock_granularity(LockGranularity::Machine);
+        resources.use_coffee(10).unwrap();
+        resources.use_water(10).unwrap();
+        resources.use_foam(10).unwrap();
+        assert_eq!(resources.levels(), Levels::default());
+    }
+
+    #[test]
+    fn speedup_is_kept_per_machine() {
+        let fast = Resources::new(10, 0, 10, 0).unwrap();
+        let slow = Resources::new(10, 0, 10, 0).unwrap();
+        fast.set_speedup(10);
+        assert_eq!(fast.speedup(), 10);
+        assert_eq!(slow.speedup(), 1);
+        slow.set_speedup(0);
+        assert_eq!(slow.speedup(), 1);
+    }
 }
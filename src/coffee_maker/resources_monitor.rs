@@ -1,13 +1,59 @@
 use std::{
     fmt,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    },
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
     thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use super::config;
+use super::{
+    alerts::{Alert, AlertPolicy, AlertTracker, Severity},
+    observer::Container,
+    orders::Ingredients,
+    snapshot::MachineSnapshot,
+    stats::Stats,
+};
+
+/// Levels of every container at some point.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Levels {
+    pub coffee: u32,
+    pub coffee_beans: u32,
+    pub foam: u32,
+    pub milk: u32,
+}
+
+impl Levels {
+    /// Returns the level of a container.
+    pub fn get(&self, container: Container) -> u32 {
+        match container {
+            Container::Coffee => self.coffee,
+            Container::CoffeeBeans => self.coffee_beans,
+            Container::Foam => self.foam,
+            Container::Milk => self.milk,
+        }
+    }
+}
+
+/// Amounts transformed and served since the monitor was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub ground_coffee_beans: u32,
+    pub poured_coffee: u32,
+    pub whipped_milk: u32,
+    pub poured_foam: u32,
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Totals: {} mg of coffee beans ground, {} mg of coffee poured, {} ml of milk whipped, {} ml of foam poured",
+            self.ground_coffee_beans, self.poured_coffee, self.whipped_milk, self.poured_foam
+        )
+    }
+}
 
 /// Stores a copy of the resources to keep track of them as well as status flags.
 #[derive(Debug)]
@@ -18,6 +64,9 @@ pub struct ResourcesMonitor {
     milk: u32,
     low_on_coffee_beans: bool,
     low_on_milk: bool,
+    actions: u64,
+    alerts: AlertTracker,
+    totals: Totals,
 }
 
 impl ResourcesMonitor {
@@ -28,90 +77,357 @@ impl ResourcesMonitor {
         initial_foam: u32,
         initial_milk: u32,
     ) -> ResourcesMonitor {
+        let alerts = AlertTracker::new(
+            AlertPolicy::default(),
+            Levels {
+                coffee: initial_coffee,
+                coffee_beans: initial_coffee_beans,
+                foam: initial_foam,
+                milk: initial_milk,
+            },
+        );
         ResourcesMonitor {
             coffee: initial_coffee,
             coffee_beans: initial_coffee_beans,
             foam: initial_foam,
             milk: initial_milk,
-            low_on_coffee_beans: initial_coffee_beans < config::G * config::X / 100,
-            low_on_milk: initial_milk < config::L * config::X / 100,
+            low_on_coffee_beans: alerts.severity(Container::CoffeeBeans).is_some(),
+            low_on_milk: alerts.severity(Container::Milk).is_some(),
+            actions: 0,
+            totals: Totals::default(),
+            alerts,
         }
     }
 
+    /// Sets when to alert about the containers, starting from the current levels.
+    pub fn set_alert_policy(&mut self, policy: AlertPolicy) {
+        self.alerts = AlertTracker::new(policy, self.levels());
+        self.low_on_coffee_beans = self.is_low(Container::CoffeeBeans);
+        self.low_on_milk = self.is_low(Container::Milk);
+    }
+
+    /// Returns when the containers are alerted about.
+    pub fn alert_policy(&self) -> AlertPolicy {
+        self.alerts.policy()
+    }
+
+    /// Takes the alerts raised by the updates since the last time they were taken.
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        self.alerts.take()
+    }
+
     /// Updates the amount of coffee.
     pub fn update_coffee(&mut self, coffee: u32) {
+        self.alerts.update(Container::Coffee, coffee);
+        self.totals.poured_coffee += self.coffee.saturating_sub(coffee);
         self.coffee = coffee;
     }
     /// Updates the amount of coffee beans.
     pub fn update_coffee_beans(&mut self, coffee_beans: u32) {
-        self.low_on_coffee_beans = coffee_beans < config::G * config::X / 100;
+        self.alerts.update(Container::CoffeeBeans, coffee_beans);
+        self.low_on_coffee_beans = self.is_low(Container::CoffeeBeans);
+        self.totals.ground_coffee_beans += self.coffee_beans.saturating_sub(coffee_beans);
         self.coffee_beans = coffee_beans;
     }
 
     /// Updates the amount of foam.
     pub fn update_foam(&mut self, foam: u32) {
+        self.alerts.update(Container::Foam, foam);
+        self.totals.poured_foam += self.foam.saturating_sub(foam);
         self.foam = foam;
     }
     /// Updates the amount of milk.
     pub fn update_milk(&mut self, milk: u32) {
-        self.low_on_milk = milk < config::L * config::X / 100;
+        self.alerts.update(Container::Milk, milk);
+        self.low_on_milk = self.is_low(Container::Milk);
+        self.totals.whipped_milk += self.milk.saturating_sub(milk);
         self.milk = milk;
     }
 }
 
+impl ResourcesMonitor {
+    /// Returns whether there are enough resources for the given ingredients,
+    /// counting the coffee beans and milk that can still be transformed.
+    pub fn can_make(&self, ingredients: &Ingredients) -> bool {
+        self.coffee + self.coffee_beans >= ingredients.coffee
+            && self.foam + self.milk >= ingredients.foam
+    }
+
+    /// Returns whether a container is below the warning threshold, as set by the alert policy.
+    pub fn is_low(&self, container: Container) -> bool {
+        self.alerts.severity(container).is_some()
+    }
+
+    /// Returns the severity of the alert currently raised for a container, if any.
+    pub fn alert(&self, container: Container) -> Option<Severity> {
+        self.alerts.severity(container)
+    }
+
+    /// Returns the amount of actions recorded so far.
+    pub fn actions(&self) -> u64 {
+        self.actions
+    }
+
+    /// Returns the current levels of the containers.
+    pub fn levels(&self) -> Levels {
+        Levels {
+            coffee: self.coffee,
+            coffee_beans: self.coffee_beans,
+            foam: self.foam,
+            milk: self.milk,
+        }
+    }
+
+    /// Returns the amounts transformed and served so far.
+    pub fn totals(&self) -> Totals {
+        self.totals
+    }
+
+    /// Records that an action was performed on the containers.
+    /// Returns the sequence number of the action, starting from 1, and the levels right after it.
+    pub fn record_action(&mut self) -> (u64, Levels) {
+        self.actions += 1;
+        (self.actions, self.levels())
+    }
+
+    /// Amount of coffee and foam that could still be served, counting beans and milk.
+    pub fn remaining(&self) -> u32 {
+        self.coffee + self.coffee_beans + self.foam + self.milk
+    }
+}
+
 impl fmt::Display for ResourcesMonitor {
     /// Displays the current status of the resources.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let coffee_beans_warning = match self.low_on_coffee_beans {
-            true => "[WARNING: below threshold]",
-            false => "",
-        };
-        let milk_warning = match self.low_on_milk {
-            true => "[WARNING: below threshold]",
-            false => "",
-        };
-        let info = format!(
-            "Coffee: {} mg\nCoffee Beans: {} mg {}\nFoam: {} ml\nMilk: {} ml {}",
-            self.coffee,
-            self.coffee_beans,
-            coffee_beans_warning,
-            self.foam,
-            self.milk,
-            milk_warning
-        );
+        writeln!(
+            f,
+            "{}",
+            status(self.levels(), |container| self.alert(container))
+        )
+    }
+}
+
+/// Describes the levels of the containers, warning about those with an alert raised.
+fn status(levels: Levels, alert: impl Fn(Container) -> Option<Severity>) -> String {
+    let warning = |container| {
+        alert(container).map_or(String::new(), |severity| {
+            format!("[{}: below threshold]", severity)
+        })
+    };
+    format!(
+        "Coffee: {} mg {}\nCoffee Beans: {} mg {}\nFoam: {} ml {}\nMilk: {} ml {}",
+        levels.coffee,
+        warning(Container::Coffee),
+        levels.coffee_beans,
+        warning(Container::CoffeeBeans),
+        levels.foam,
+        warning(Container::Foam),
+        levels.milk,
+        warning(Container::Milk)
+    )
+}
+
+/// How the monitor prints the resources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MonitorMode {
+    /// Clears the screen and prints the resources.
+    #[default]
+    Dashboard,
+    /// Appends a line with a timestamp and the resources, keeping what was printed before.
+    Log,
+    /// Appends a JSON object per snapshot, to be read by other programs.
+    Json,
+}
+
+impl FromStr for MonitorMode {
+    type Err = String;
 
-        writeln!(f, "{}", info)
+    fn from_str(s: &str) -> Result<MonitorMode, String> {
+        match s {
+            "dashboard" => Ok(MonitorMode::Dashboard),
+            "log" => Ok(MonitorMode::Log),
+            "json" => Ok(MonitorMode::Json),
+            _ => Err(format!("Unknown monitor mode: {}", s)),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+fn timestamp_millis(at: SystemTime) -> u128 {
+    at.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis())
+}
+
+impl MonitorMode {
+    /// Renders a snapshot of the resources, of the coffee maker it names if any.
+    /// Dashboards clear the screen, so they only show what was rendered last.
+    pub fn render(&self, snapshot: &MachineSnapshot) -> String {
+        let name = snapshot.machine.as_deref();
+        let levels = snapshot.levels();
+        match self {
+            MonitorMode::Dashboard => {
+                let header = name.map_or(String::new(), |name| format!("[{}]\n", name));
+                let status = status(levels, |container| snapshot.container(container).alert);
+                format!("{}[2J{}{}\n", 27 as char, header, status)
+            }
+            MonitorMode::Log => {
+                let low: String = snapshot
+                    .containers
+                    .iter()
+                    .filter_map(|container| {
+                        let severity = container.alert?;
+                        Some(format!(
+                            " [{}: {} below threshold]",
+                            severity, container.container
+                        ))
+                    })
+                    .collect();
+                let name = name.map_or(String::new(), |name| format!(" [{}]", name));
+                format!(
+                    "{}{} coffee={}mg coffee_beans={}mg foam={}ml milk={}ml{}",
+                    timestamp_millis(snapshot.at),
+                    name,
+                    levels.coffee,
+                    levels.coffee_beans,
+                    levels.foam,
+                    levels.milk,
+                    low
+                )
+            }
+            MonitorMode::Json => snapshot.to_json(),
+        }
+    }
+
+    /// Renders the last snapshot of the resources, along with the totals.
+    pub fn render_final(&self, snapshot: &MachineSnapshot) -> String {
+        let totals = snapshot.totals;
+        match self {
+            MonitorMode::Dashboard => format!("{}{}", self.render(snapshot), totals),
+            MonitorMode::Log => format!(
+                "{} [final] ground={}mg poured_coffee={}mg whipped={}ml poured_foam={}ml",
+                self.render(snapshot),
+                totals.ground_coffee_beans,
+                totals.poured_coffee,
+                totals.whipped_milk,
+                totals.poured_foam
+            ),
+            MonitorMode::Json => snapshot.to_final_json(),
+        }
+    }
+}
+
+/// What the monitor thread was asked to do.
+#[derive(Debug, Default)]
+struct Requests {
+    stop: bool,
+    snapshot: bool,
+}
+
+/// Handle to a running monitor, to ask it for snapshots and to stop it.
+pub struct MonitorHandle {
+    handle: JoinHandle<()>,
+    requests: Arc<(Mutex<Requests>, Condvar)>,
+}
+
+impl MonitorHandle {
+    /// Asks the monitor to print a snapshot right away.
+    pub fn request_snapshot(&self) {
+        let (requests, changed) = &*self.requests;
+        requests
+            .lock()
+            .expect("Failed to lock monitor requests")
+            .snapshot = true;
+        changed.notify_all();
+    }
+
+    /// Stops the monitor right away, waiting for it to print the final snapshot.
+    pub fn stop(self) {
+        let (requests, changed) = &*self.requests;
+        requests
+            .lock()
+            .expect("Failed to lock monitor requests")
+            .stop = true;
+        changed.notify_all();
+        self.handle.join().expect("Monitor Panicked");
     }
 }
 
 /// Starts the monitor in a new thread.
-/// This will print the current resources at an interval.
-/// Returns a handle to the monitor thread and an AtomicBool to stop it.
-/// The AtomicBool is set to true when the monitor is stopped.
+/// This will write the current resources at an interval, in the given mode, as well as
+/// whenever a snapshot is requested; and a final snapshot with the totals when stopped.
+/// Returns a handle to ask the monitor for snapshots and to stop it.
 pub fn monitor_resources(
     monitor: Arc<Mutex<ResourcesMonitor>>,
+    stats: Arc<Stats>,
     interval_millis: u64,
-) -> (JoinHandle<()>, Arc<AtomicBool>) {
-    let stop = Arc::new(AtomicBool::new(false));
-    let stop_thread = stop.clone();
+    mode: MonitorMode,
+    output: Box<dyn Write + Send>,
+) -> MonitorHandle {
+    spawn_monitor(interval_millis, output, move |last| {
+        let snapshot = {
+            let monitor = monitor.lock().expect("Failed to lock monitor");
+            MachineSnapshot::new(&monitor, &stats)
+        };
+        match last {
+            true => mode.render_final(&snapshot),
+            false => mode.render(&snapshot),
+        }
+    })
+}
 
-    let handle = thread::spawn(move || loop {
-        thread::sleep(std::time::Duration::from_millis(interval_millis));
+/// Starts a monitor in a new thread, writing what `render` returns at an interval and whenever
+/// a snapshot is requested; and once more when stopped, telling `render` it is the last time.
+/// Returns a handle to ask the monitor for snapshots and to stop it.
+pub(crate) fn spawn_monitor(
+    interval_millis: u64,
+    mut output: Box<dyn Write + Send>,
+    mut render: impl FnMut(bool) -> String + Send + 'static,
+) -> MonitorHandle {
+    let requests = Arc::new((Mutex::new(Requests::default()), Condvar::new()));
+    let thread_requests = requests.clone();
+    let interval = Duration::from_millis(interval_millis);
 
-        print!("{}[2J", 27 as char); // clear screen
-        println!("{}", monitor.lock().unwrap());
+    let handle = thread::spawn(move || {
+        let (requests, changed) = &*thread_requests;
+        loop {
+            // released before writing, so that asking for a snapshot or to stop never waits
+            // for the output
+            let stop = {
+                let requests = requests.lock().expect("Failed to lock monitor requests");
+                let mut requests = changed
+                    .wait_timeout_while(requests, interval, |requests| {
+                        !requests.stop && !requests.snapshot
+                    })
+                    .expect("Failed to lock monitor requests")
+                    .0;
+                requests.snapshot = false;
+                requests.stop
+            };
 
-        if stop_thread.load(Ordering::Relaxed) {
-            break;
-        };
+            let snapshot = render(stop);
+            // a failing output shouldn't stop the coffee maker
+            let _ = writeln!(output, "{}", snapshot).and_then(|_| output.flush());
+
+            if stop {
+                break;
+            }
+        }
     });
 
-    (handle, stop)
+    MonitorHandle { handle, requests }
 }
 
 #[cfg(test)]
 mod resources_monitor_tests {
-    use crate::coffee_maker::config;
+    use crate::coffee_maker::{
+        alerts::AlertPolicy, config, observer::Container, orders::Ingredients,
+        snapshot::MachineSnapshot, stats::Stats, test_utils::SharedWriter,
+    };
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
 
     #[test]
     fn update_coffee() {
@@ -142,6 +458,21 @@ mod resources_monitor_tests {
     }
 
     #[test]
+    fn can_make_counts_beans_and_milk() {
+        let monitor = super::ResourcesMonitor::new(10, 10, 10, 10);
+        let ingredients = |coffee, foam| Ingredients {
+            coffee,
+            water: 1000,
+            foam,
+        };
+        assert!(monitor.can_make(&ingredients(20, 20)));
+        assert!(!monitor.can_make(&ingredients(21, 0)));
+        assert!(!monitor.can_make(&ingredients(0, 21)));
+        assert_eq!(monitor.remaining(), 40);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn update_coffee_beans_below_threshold() {
         let mut monitor = super::ResourcesMonitor::new(0, 0, 0, 0);
         assert_eq!(monitor.low_on_coffee_beans, true);
@@ -152,6 +483,101 @@ mod resources_monitor_tests {
     }
 
     #[test]
+    fn low_flags_follow_the_alert_policy() {
+        let mut monitor = super::ResourcesMonitor::new(0, config::G, config::E, config::L);
+        monitor.set_alert_policy(AlertPolicy {
+            warning: 50,
+            critical: 10,
+            hysteresis: 10,
+        });
+        monitor.update_coffee_beans(config::G * 49 / 100);
+        assert!(monitor.low_on_coffee_beans);
+        // within the hysteresis
+        monitor.update_coffee_beans(config::G * 55 / 100);
+        assert!(monitor.low_on_coffee_beans);
+        monitor.update_coffee_beans(config::G * 61 / 100);
+        assert!(!monitor.low_on_coffee_beans);
+
+        assert!(!monitor.is_low(Container::Foam));
+        monitor.update_foam(0);
+        assert!(monitor.is_low(Container::Foam));
+        // the coffee container started empty
+        assert!(monitor.is_low(Container::Coffee));
+    }
+
+    #[test]
+    fn updates_raise_alerts() {
+        let mut monitor = super::ResourcesMonitor::new(0, config::G, 0, config::L);
+        monitor.update_coffee_beans(config::G / 2);
+        assert!(monitor.take_alerts().is_empty());
+        monitor.update_coffee_beans(0);
+        monitor.update_milk(0);
+        let alerts = monitor.take_alerts();
+        assert_eq!(alerts.len(), 2);
+        assert!(monitor.take_alerts().is_empty());
+    }
+
+    #[test]
+    fn parse_monitor_mode() {
+        assert_eq!("log".parse(), Ok(super::MonitorMode::Log));
+        assert_eq!("json".parse(), Ok(super::MonitorMode::Json));
+        assert_eq!("dashboard".parse(), Ok(super::MonitorMode::Dashboard));
+        assert!("fancy".parse::<super::MonitorMode>().is_err());
+    }
+
+    /// Takes a snapshot of the monitor, of the coffee maker with the given name if any.
+    fn snapshot(monitor: &super::ResourcesMonitor, name: Option<&str>) -> MachineSnapshot {
+        let mut snapshot = MachineSnapshot::new(monitor, &Stats::new());
+        snapshot.machine = name.map(str::to_string);
+        snapshot
+    }
+
+    #[test]
+    fn render_modes() {
+        let monitor = super::ResourcesMonitor::new(1, 2, 3, config::L);
+        let dashboard = super::MonitorMode::Dashboard.render(&snapshot(&monitor, None));
+        assert!(dashboard.starts_with("\x1b[2J"));
+        assert!(dashboard.contains("Coffee: 1 mg [CRITICAL: below threshold]"));
+
+        let log = super::MonitorMode::Log.render(&snapshot(&monitor, Some("m")));
+        assert!(!log.contains('\x1b'));
+        assert!(!log.contains('\n'));
+        assert!(log.ends_with(
+            " [m] coffee=1mg coffee_beans=2mg foam=3ml milk=10000ml [CRITICAL: coffee below threshold] [CRITICAL: coffee beans below threshold] [CRITICAL: foam below threshold]"
+        ));
+
+        let beans = config::G * 8 / 100;
+        let warned = super::ResourcesMonitor::new(config::C, beans, config::E, config::L);
+        let log = super::MonitorMode::Log.render(&snapshot(&warned, None));
+        assert!(log.ends_with("ml [WARNING: coffee beans below threshold]"));
+
+        let snapshot = snapshot(&monitor, None);
+        assert_eq!(
+            super::MonitorMode::Json.render(&snapshot),
+            snapshot.to_json()
+        );
+    }
+
+    #[test]
+    fn json_escapes_the_name() {
+        let monitor = super::ResourcesMonitor::new(1, 2, 3, config::L);
+        let snapshot = snapshot(&monitor, Some("the \"best\"\none"));
+        let json = super::MonitorMode::Json.render(&snapshot);
+        assert!(
+            json.contains(",\"machine\":\"the \\\"best\\\"\\none\","),
+            "{}",
+            json
+        );
+        let json = super::MonitorMode::Json.render_final(&snapshot);
+        assert!(
+            json.contains(",\"machine\":\"the \\\"best\\\"\\none\","),
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn update_milk_below_threshold() {
         let mut monitor = super::ResourcesMonitor::new(0, 0, 0, 0);
         assert_eq!(monitor.low_on_milk, true);
@@ -160,4 +586,115 @@ mod resources_monitor_tests {
         monitor.update_milk(config::L * config::X / 100 - 1);
         assert_eq!(monitor.low_on_milk, true);
     }
+
+    #[test]
+    fn keeps_totals() {
+        let mut monitor = super::ResourcesMonitor::new(0, 100, 0, 100);
+        monitor.update_coffee_beans(70);
+        monitor.update_coffee(30);
+        monitor.update_coffee(10);
+        monitor.update_milk(80);
+        monitor.update_foam(20);
+        monitor.update_foam(0);
+        assert_eq!(
+            monitor.totals(),
+            super::Totals {
+                ground_coffee_beans: 30,
+                poured_coffee: 20,
+                whipped_milk: 20,
+                poured_foam: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn render_final_modes() {
+        let mut monitor = super::ResourcesMonitor::new(10, 0, 0, 0);
+        monitor.update_coffee(4);
+
+        let snapshot = snapshot(&monitor, None);
+        let dashboard = super::MonitorMode::Dashboard.render_final(&snapshot);
+        assert!(dashboard.ends_with("Totals: 0 mg of coffee beans ground, 6 mg of coffee poured, 0 ml of milk whipped, 0 ml of foam poured"));
+
+        let log = super::MonitorMode::Log.render_final(&snapshot);
+        assert!(log.ends_with(" [final] ground=0mg poured_coffee=6mg whipped=0ml poured_foam=0ml"));
+
+        // the object ends with the dispensers, which the final mark comes after
+        let json = super::MonitorMode::Json.render_final(&snapshot);
+        assert!(json.starts_with("{\"timestamp_ms\":"));
+        assert!(json.contains(
+            ",\"totals\":{\"ground_coffee_beans\":0,\"poured_coffee\":6,\"whipped_milk\":0,\"poured_foam\":0},"
+        ));
+        assert!(json.ends_with(",\"dispensers\":[],\"final\":true}"));
+    }
+
+    #[test]
+    fn monitor_snapshots_on_demand_and_stops_right_away() {
+        let monitor = Arc::new(Mutex::new(super::ResourcesMonitor::new(0, 0, 0, 0)));
+        let output = SharedWriter::default();
+        let started = Instant::now();
+        let handle = super::monitor_resources(
+            monitor,
+            Arc::new(Stats::new()),
+            60_000,
+            super::MonitorMode::Log,
+            Box::new(output.clone()),
+        );
+
+        handle.request_snapshot();
+        while output.lines().is_empty() {
+            std::thread::yield_now();
+        }
+        handle.stop();
+
+        let lines = output.lines();
+        assert!(started.elapsed().as_millis() < 60_000);
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].contains("[final]"));
+        assert!(lines[1].contains("[final]"));
+    }
+
+    /// A writer that tells when it starts writing, then waits for the gate to be opened.
+    struct GatedWriter {
+        writing: std::sync::mpsc::Sender<()>,
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl Write for GatedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.writing.send(());
+            let _open = self.gate.lock().unwrap();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn requests_dont_wait_for_the_output() {
+        let monitor = Arc::new(Mutex::new(super::ResourcesMonitor::new(0, 0, 0, 0)));
+        let (writing, written) = std::sync::mpsc::channel();
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let output = GatedWriter {
+            writing,
+            gate: gate.clone(),
+        };
+        let handle = super::monitor_resources(
+            monitor,
+            Arc::new(Stats::new()),
+            60_000,
+            super::MonitorMode::Log,
+            Box::new(output),
+        );
+
+        handle.request_snapshot();
+        written.recv().unwrap();
+        // the monitor is stuck writing, yet it can still be asked for another snapshot
+        handle.request_snapshot();
+        drop(closed);
+        handle.stop();
+    }
 }
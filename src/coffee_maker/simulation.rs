@@ -0,0 +1,587 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use super::{
+    bench::BenchConfig,
+    builder::DEFAULT_DISPENSERS,
+    capabilities::Capabilities,
+    config,
+    latency::{Latency, LatencyReport},
+    orders::{Ingredients, Order},
+    resources::{coffee_time, foam_time, grind_time, water_time, whip_time},
+    resources_monitor::Levels,
+    LockGranularity, RefillPolicy,
+};
+
+/// A coffee maker to simulate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationConfig {
+    /// Capabilities of each dispenser.
+    pub dispensers: Vec<Capabilities>,
+    /// Capacity of each container.
+    pub capacities: Levels,
+    /// Initial levels of the containers.
+    pub levels: Levels,
+    pub granularity: LockGranularity,
+    pub refill: RefillPolicy,
+    /// How long customers wait for their orders to be taken before leaving, if they do.
+    pub patience: Option<Duration>,
+}
+
+impl Default for SimulationConfig {
+    /// Full containers and `DEFAULT_DISPENSERS` dispensers able to pour every ingredient,
+    /// as built by `CoffeeMakerBuilder`.
+    fn default() -> SimulationConfig {
+        let capacities = Levels {
+            coffee: config::C,
+            coffee_beans: config::G,
+            foam: config::E,
+            milk: config::L,
+        };
+        SimulationConfig {
+            dispensers: vec![Capabilities::ALL; DEFAULT_DISPENSERS],
+            capacities,
+            levels: capacities,
+            granularity: LockGranularity::default(),
+            refill: RefillPolicy::default(),
+            patience: None,
+        }
+    }
+}
+
+impl From<BenchConfig> for SimulationConfig {
+    /// Full containers and the configured amount of dispensers able to pour every ingredient,
+    /// as benchmarked.
+    fn from(config: BenchConfig) -> SimulationConfig {
+        SimulationConfig {
+            dispensers: vec![Capabilities::ALL; config.dispensers],
+            granularity: config.granularity,
+            refill: config.refill,
+            ..SimulationConfig::default()
+        }
+    }
+}
+
+/// How an order ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    /// There weren't enough resources left for it.
+    Failed,
+    /// No dispenser could make it.
+    Rejected,
+    /// Its customer left before a dispenser took it.
+    Abandoned,
+}
+
+/// What happened to an order, with times since the simulation started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderOutcome {
+    /// Id of the order, counting from 1 in the order they were given.
+    pub id: u64,
+    pub ingredients: Ingredients,
+    pub outcome: Outcome,
+    /// Dispenser that made the order, or tried to.
+    pub dispenser: Option<usize>,
+    pub queued_at: Duration,
+    pub started_at: Option<Duration>,
+    pub finished_at: Option<Duration>,
+}
+
+/// Results of a simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    /// Every order, in the order they were given.
+    pub orders: Vec<OrderOutcome>,
+    /// Levels of the containers at the end.
+    pub levels: Levels,
+    /// From the start until the last order was finished.
+    pub makespan: Duration,
+    pub grinder_runs: u64,
+    pub whipper_runs: u64,
+    /// Time every dispenser spent waiting for the containers to be unlocked, added up.
+    pub lock_wait: Duration,
+    pub latency: LatencyReport,
+}
+
+impl SimulationReport {
+    /// Amount of orders that ended the given way.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.orders
+            .iter()
+            .filter(|order| order.outcome == outcome)
+            .count()
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    /// Displays the report, one value per line, followed by the latencies.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Makespan: {:.3}s", self.makespan.as_secs_f64())?;
+        writeln!(f, "Completed: {}", self.count(Outcome::Completed))?;
+        writeln!(f, "Failed: {}", self.count(Outcome::Failed))?;
+        writeln!(f, "Rejected: {}", self.count(Outcome::Rejected))?;
+        writeln!(f, "Abandoned: {}", self.count(Outcome::Abandoned))?;
+        writeln!(f, "Grinder runs: {}", self.grinder_runs)?;
+        writeln!(f, "Whipper runs: {}", self.whipper_runs)?;
+        writeln!(f, "Lock wait: {:.3}s", self.lock_wait.as_secs_f64())?;
+        writeln!(
+            f,
+            "Levels: coffee={}mg coffee_beans={}mg foam={}ml milk={}ml",
+            self.levels.coffee, self.levels.coffee_beans, self.levels.foam, self.levels.milk
+        )?;
+        write!(f, "{}", self.latency)
+    }
+}
+
+/// Steps of an order, in the order a dispenser makes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Step {
+    Coffee,
+    Water,
+    Foam,
+}
+
+/// What happens at some point of the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    /// The order with the given index is put in the queue.
+    Arrival(usize),
+    /// The dispenser finished a step of its order.
+    Finished(usize, Step),
+    /// The dispenser couldn't make its order, while holding the lock of the given step.
+    Failed(usize, Step),
+}
+
+/// State of a simulation, advancing from event to event in simulated milliseconds.
+///
+/// Follows the same rules as the threads: each dispenser takes the first queued order it can
+/// make; pours coffee holding the coffee lock, grinding first if needed; pours water; then pours
+/// foam holding the foam lock, whipping first if needed. Locks are handed to whoever waited first.
+/// Orders past their deadline are dropped when dispensers look for their next order.
+struct Simulation {
+    config: SimulationConfig,
+    now: u64,
+    /// Pending events by time, then by when they were scheduled.
+    events: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    scheduled: u64,
+    orders: Vec<OrderOutcome>,
+    queue: VecDeque<usize>,
+    /// Order each dispenser is making.
+    making: Vec<Option<usize>>,
+    /// Dispenser holding each lock, and those waiting for it with their step and since when.
+    holders: [Option<usize>; 2],
+    waiting: [VecDeque<(usize, Step, u64)>; 2],
+    levels: Levels,
+    grinder_runs: u64,
+    whipper_runs: u64,
+    lock_wait: u64,
+}
+
+impl Simulation {
+    fn new(config: SimulationConfig, arrivals: &[(u64, Ingredients)]) -> Simulation {
+        let mut simulation = Simulation {
+            making: vec![None; config.dispensers.len()],
+            levels: config.levels,
+            config,
+            now: 0,
+            events: BinaryHeap::new(),
+            scheduled: 0,
+            orders: Vec::new(),
+            queue: VecDeque::new(),
+            holders: [None; 2],
+            waiting: [VecDeque::new(), VecDeque::new()],
+            grinder_runs: 0,
+            whipper_runs: 0,
+            lock_wait: 0,
+        };
+        for (i, (at, ingredients)) in arrivals.iter().enumerate() {
+            simulation.orders.push(OrderOutcome {
+                id: i as u64 + 1,
+                ingredients: *ingredients,
+                outcome: Outcome::Rejected,
+                dispenser: None,
+                queued_at: Duration::from_millis(*at),
+                started_at: None,
+                finished_at: None,
+            });
+            simulation.schedule(*at, Event::Arrival(i));
+        }
+        simulation
+    }
+
+    /// Schedules an event at the given time.
+    fn schedule(&mut self, at: u64, event: Event) {
+        self.events.push(Reverse((at, self.scheduled, event)));
+        self.scheduled += 1;
+    }
+
+    /// Runs every event, until every order is finished.
+    fn run(mut self) -> SimulationReport {
+        while let Some(Reverse((at, _, event))) = self.events.pop() {
+            self.now = at;
+            match event {
+                Event::Arrival(order) => self.arrive(order),
+                Event::Finished(dispenser, Step::Coffee) => {
+                    self.release(Step::Coffee);
+                    let water = self.order_of(dispenser).ingredients.water;
+                    self.schedule(
+                        self.now + water_time(water) as u64,
+                        Event::Finished(dispenser, Step::Water),
+                    );
+                }
+                Event::Finished(dispenser, Step::Water) => self.request(dispenser, Step::Foam),
+                Event::Finished(dispenser, Step::Foam) => {
+                    self.release(Step::Foam);
+                    self.finish(dispenser, Outcome::Completed);
+                }
+                Event::Failed(dispenser, step) => {
+                    self.release(step);
+                    self.finish(dispenser, Outcome::Failed);
+                }
+            }
+        }
+        self.report()
+    }
+
+    /// Order the dispenser is making.
+    fn order_of(&self, dispenser: usize) -> &OrderOutcome {
+        let order = self.making[dispenser].expect("The dispenser is making an order");
+        &self.orders[order]
+    }
+
+    /// Queues an order, unless no dispenser can make it.
+    fn arrive(&mut self, order: usize) {
+        let ingredients = self.orders[order].ingredients;
+        if self
+            .config
+            .dispensers
+            .iter()
+            .any(|capabilities| capabilities.can_make(&ingredients))
+        {
+            self.queue.push_back(order);
+            self.dispatch();
+        }
+    }
+
+    /// Hands each idle dispenser the first queued order it can make, after dropping those
+    /// past their deadline.
+    fn dispatch(&mut self) {
+        if let Some(patience) = self.config.patience {
+            let now = Duration::from_millis(self.now);
+            let orders = &mut self.orders;
+            self.queue.retain(|order| {
+                let order = &mut orders[*order];
+                let expired = now > order.queued_at + patience;
+                if expired {
+                    order.outcome = Outcome::Abandoned;
+                    order.finished_at = Some(now);
+                }
+                !expired
+            });
+        }
+        for dispenser in 0..self.making.len() {
+            if self.making[dispenser].is_some() {
+                continue;
+            }
+            let capabilities = self.config.dispensers[dispenser];
+            let chosen = self
+                .queue
+                .iter()
+                .position(|order| capabilities.can_make(&self.orders[*order].ingredients));
+            if let Some(order) = chosen.and_then(|i| self.queue.remove(i)) {
+                self.making[dispenser] = Some(order);
+                self.orders[order].dispenser = Some(dispenser);
+                self.orders[order].started_at = Some(Duration::from_millis(self.now));
+                self.request(dispenser, Step::Coffee);
+            }
+        }
+    }
+
+    /// Lock needed for a step.
+    fn lock(&self, step: Step) -> usize {
+        match (self.config.granularity, step) {
+            (LockGranularity::PerContainer, Step::Foam) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Takes the lock of a step for the dispenser, or waits for it.
+    fn request(&mut self, dispenser: usize, step: Step) {
+        let lock = self.lock(step);
+        match self.holders[lock] {
+            None => self.acquire(dispenser, step, self.now),
+            Some(_) => self.waiting[lock].push_back((dispenser, step, self.now)),
+        }
+    }
+
+    /// Gives the lock of a step to the dispenser, which had been waiting since the given time,
+    /// and starts the step.
+    fn acquire(&mut self, dispenser: usize, step: Step, since: u64) {
+        self.holders[self.lock(step)] = Some(dispenser);
+        self.lock_wait += self.now - since;
+
+        let ingredients = self.order_of(dispenser).ingredients;
+        let duration = match step {
+            Step::Coffee => {
+                let (amount, capacity) = (ingredients.coffee, self.config.capacities.coffee);
+                let (stored, source) = (self.levels.coffee, self.levels.coffee_beans);
+                self.transform(amount, stored, source, capacity)
+                    .map(|transformed| {
+                        self.levels.coffee += transformed;
+                        self.levels.coffee_beans -= transformed;
+                        self.levels.coffee -= amount;
+                        if transformed > 0 {
+                            self.grinder_runs += 1;
+                            grind_time(transformed) + coffee_time(amount)
+                        } else {
+                            coffee_time(amount)
+                        }
+                    })
+            }
+            _ => {
+                let (amount, capacity) = (ingredients.foam, self.config.capacities.foam);
+                let (stored, source) = (self.levels.foam, self.levels.milk);
+                self.transform(amount, stored, source, capacity)
+                    .map(|transformed| {
+                        self.levels.foam += transformed;
+                        self.levels.milk -= transformed;
+                        self.levels.foam -= amount;
+                        if transformed > 0 {
+                            self.whipper_runs += 1;
+                            whip_time(transformed) + foam_time(amount)
+                        } else {
+                            foam_time(amount)
+                        }
+                    })
+            }
+        };
+        match duration {
+            Some(duration) => {
+                self.schedule(self.now + duration as u64, Event::Finished(dispenser, step))
+            }
+            None => self.schedule(self.now, Event::Failed(dispenser, step)),
+        }
+    }
+
+    /// Amount to transform to serve `amount`, with `stored` in a container holding up to
+    /// `capacity` and `source` left to transform. `None` if there isn't enough, or it doesn't fit.
+    fn transform(&self, amount: u32, stored: u32, source: u32, capacity: u32) -> Option<u32> {
+        let needed = amount.saturating_sub(stored);
+        if needed > source || amount > capacity {
+            None
+        } else if needed > 0 {
+            let room = capacity.saturating_sub(stored);
+            Some(self.config.refill.amount(needed, room, source))
+        } else {
+            Some(0)
+        }
+    }
+
+    /// Releases the lock of a step, handing it to the first one waiting for it.
+    fn release(&mut self, step: Step) {
+        let lock = self.lock(step);
+        self.holders[lock] = None;
+        if let Some((dispenser, step, since)) = self.waiting[lock].pop_front() {
+            self.acquire(dispenser, step, since);
+        }
+    }
+
+    /// Finishes the order of the dispenser, which takes the next one.
+    fn finish(&mut self, dispenser: usize, outcome: Outcome) {
+        if let Some(order) = self.making[dispenser].take() {
+            self.orders[order].outcome = outcome;
+            self.orders[order].finished_at = Some(Duration::from_millis(self.now));
+        }
+        self.dispatch();
+    }
+
+    fn report(self) -> SimulationReport {
+        let latency = Latency::new();
+        for order in &self.orders {
+            if let (Outcome::Completed, Some(dispenser), Some(started), Some(finished)) = (
+                order.outcome,
+                order.dispenser,
+                order.started_at,
+                order.finished_at,
+            ) {
+                let timings = (
+                    started - order.queued_at,
+                    finished - started,
+                    finished - order.queued_at,
+                );
+                latency.record(&order.ingredients, dispenser, timings);
+            }
+        }
+        SimulationReport {
+            makespan: Duration::from_millis(self.now),
+            orders: self.orders,
+            levels: self.levels,
+            grinder_runs: self.grinder_runs,
+            whipper_runs: self.whipper_runs,
+            lock_wait: Duration::from_millis(self.lock_wait),
+            latency: latency.report(),
+        }
+    }
+}
+
+/// Simulates the orders arriving at the given times since the start, in a single thread and
+/// without sleeping, so the results are the same on every run. Faults are not simulated.
+pub fn simulate(
+    config: SimulationConfig,
+    arrivals: &[(Duration, Ingredients)],
+) -> SimulationReport {
+    let arrivals: Vec<(u64, Ingredients)> = arrivals
+        .iter()
+        .map(|(at, ingredients)| (at.as_millis() as u64, *ingredients))
+        .collect();
+    Simulation::new(config, &arrivals).run()
+}
+
+/// Simulates the orders all arriving at the start, until there are no more orders.
+pub fn simulate_orders(config: SimulationConfig, orders: &[Order]) -> SimulationReport {
+    let arrivals: Vec<(Duration, Ingredients)> = orders
+        .iter()
+        .map_while(|order| match order {
+            Order::Order(ingredients) => Some((Duration::ZERO, *ingredients)),
+            Order::NoMoreOrders => None,
+        })
+        .collect();
+    simulate(config, &arrivals)
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+    use crate::coffee_maker::{machine::CoffeeMaker, Resources};
+
+    #[test]
+    fn dispensers_wait_for_the_locks() {
+        let config = SimulationConfig {
+            dispensers: vec![Capabilities::ALL; 2],
+            ..SimulationConfig::default()
+        };
+        let orders = vec![Order::from(10, 0, 0).unwrap(); 2];
+        let report = simulate_orders(config, &orders);
+
+        let pour = Duration::from_millis(coffee_time(10).into());
+        let rest = Duration::from_millis((water_time(0) + foam_time(0)).into());
+        assert_eq!(report.count(Outcome::Completed), 2);
+        assert_eq!(report.lock_wait, pour);
+        assert_eq!(report.makespan, pour * 2 + rest);
+        assert_eq!(report.orders[1].started_at, Some(Duration::ZERO));
+        assert_eq!(report.orders[1].finished_at, Some(pour * 2 + rest));
+        assert_eq!(report.levels.coffee, config::C - 20);
+        assert_eq!(report.grinder_runs, 0);
+    }
+
+    #[test]
+    fn fails_rejects_and_refills() {
+        let config = SimulationConfig {
+            dispensers: vec!["coffee+water".parse().unwrap()],
+            levels: Levels {
+                coffee: 0,
+                coffee_beans: 100,
+                foam: 0,
+                milk: 0,
+            },
+            ..SimulationConfig::default()
+        };
+        let arrivals = vec![
+            (Duration::ZERO, Ingredients::new(60, 10, 0)),
+            (Duration::ZERO, Ingredients::new(60, 10, 0)),
+            (Duration::from_secs(60), Ingredients::new(10, 0, 10)),
+        ];
+        let report = simulate(config, &arrivals);
+
+        let outcomes: Vec<Outcome> = report.orders.iter().map(|order| order.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Completed, Outcome::Failed, Outcome::Rejected]
+        );
+        assert_eq!(report.levels.coffee_beans, 40);
+        assert_eq!(report.grinder_runs, 1);
+        assert_eq!(report.latency.overall.end_to_end.count, 1);
+        assert_eq!(report.makespan, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn impatient_customers_leave() {
+        let config = SimulationConfig {
+            dispensers: vec![Capabilities::ALL],
+            patience: Some(Duration::from_millis(
+                (coffee_time(10) + water_time(0) + foam_time(0)).into(),
+            )),
+            ..SimulationConfig::default()
+        };
+        let orders = vec![Order::from(10, 0, 0).unwrap(); 3];
+        let report = simulate_orders(config, &orders);
+
+        // the second order is taken right at its deadline, the third one after it
+        let outcomes: Vec<Outcome> = report.orders.iter().map(|order| order.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Completed, Outcome::Completed, Outcome::Abandoned]
+        );
+        assert_eq!(report.latency.overall.end_to_end.count, 2);
+        assert!(report.to_string().contains("Abandoned: 1"));
+    }
+
+    #[test]
+    fn matches_the_threads() {
+        let orders = vec![
+            Order::from(30, 10, 20).unwrap(),
+            Order::from(50, 0, 10).unwrap(),
+            Order::from(10, 10, 0).unwrap(),
+            Order::from(500, 0, 0).unwrap(),
+        ];
+        let resources = Resources::new(20, 100, 10, 50).unwrap();
+        let machine = CoffeeMaker::new("threads", resources, &[Capabilities::ALL; 2]);
+        for order in &orders {
+            machine.orders().push(*order);
+        }
+        let threads = machine.shutdown();
+
+        let config = SimulationConfig {
+            dispensers: vec![Capabilities::ALL; 2],
+            levels: Levels {
+                coffee: 20,
+                coffee_beans: 100,
+                foam: 10,
+                milk: 50,
+            },
+            ..SimulationConfig::default()
+        };
+        let simulated = simulate_orders(config, &orders);
+        assert_eq!(
+            simulated.count(Outcome::Completed) as u64,
+            threads.completed
+        );
+        assert_eq!(simulated.count(Outcome::Failed) as u64, threads.failed);
+        assert_eq!(simulated.levels, machine.snapshot().levels());
+    }
+
+    #[test]
+    fn a_day_of_orders_is_reproducible() {
+        let orders: Vec<Order> = (0..10_000)
+            .map(|i| Order::from(i % 40, i % 25, i % 30).unwrap())
+            .collect();
+        let config = SimulationConfig {
+            granularity: LockGranularity::Machine,
+            refill: RefillPolicy::Fill,
+            ..SimulationConfig::default()
+        };
+        let first = simulate_orders(config.clone(), &orders);
+        let second = simulate_orders(config, &orders);
+
+        assert_eq!(first, second);
+        assert_eq!(first.orders.len(), 10_000);
+        assert!(first.count(Outcome::Completed) > 0);
+        assert!(first.makespan > Duration::from_secs(60));
+        assert!(first.to_string().starts_with("Makespan: "));
+    }
+}
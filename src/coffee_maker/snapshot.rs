@@ -0,0 +1,292 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{
+    alerts::Severity,
+    capabilities::Capabilities,
+    faults::Fault,
+    observer::Container,
+    pool::DispenserState,
+    resources_monitor::{Levels, ResourcesMonitor, Totals},
+    stats::Stats,
+};
+
+/// State of a container when the snapshot was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerSnapshot {
+    pub container: Container,
+    pub level: u32,
+    pub capacity: u32,
+    /// Whether it is below the warning threshold of the monitor.
+    pub low: bool,
+    /// Severity of the alert raised for it, if any.
+    pub alert: Option<Severity>,
+}
+
+/// State of a dispenser when the snapshot was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispenserSnapshot {
+    pub id: usize,
+    pub capabilities: Capabilities,
+    pub state: DispenserState,
+}
+
+/// Counters of the coffee maker when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counters {
+    pub completed: u64,
+    pub failed: u64,
+    pub retried: u64,
+    pub faults: Vec<(Fault, u64)>,
+    /// Actions performed by the actuators.
+    pub actions: u64,
+}
+
+/// The state of a coffee maker at some point, to be read by dashboards, tests and reports.
+///
+/// The containers, totals and counters are taken while the monitor is locked, so they are
+/// consistent with each other. The queue and the dispensers are only known by a `CoffeeMaker`,
+/// so they are empty in snapshots taken from the `Resources` alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub at: SystemTime,
+    /// Name of the coffee maker, if known.
+    pub machine: Option<String>,
+    /// Every container, in the order of `Container::ALL`.
+    pub containers: Vec<ContainerSnapshot>,
+    pub totals: Totals,
+    pub counters: Counters,
+    /// Orders waiting in the queue.
+    pub queue_length: usize,
+    pub dispensers: Vec<DispenserSnapshot>,
+}
+
+impl MachineSnapshot {
+    /// Creates a new MachineSnapshot instance from a locked monitor and the counters.
+    pub fn new(monitor: &ResourcesMonitor, stats: &Stats) -> MachineSnapshot {
+        let levels = monitor.levels();
+        MachineSnapshot {
+            at: SystemTime::now(),
+            machine: None,
+            containers: Container::ALL
+                .into_iter()
+                .map(|container| ContainerSnapshot {
+                    container,
+                    level: levels.get(container),
+                    capacity: container.capacity(),
+                    low: monitor.is_low(container),
+                    alert: monitor.alert(container),
+                })
+                .collect(),
+            totals: monitor.totals(),
+            counters: Counters {
+                completed: stats.completed(),
+                failed: stats.failed(),
+                retried: stats.retried(),
+                faults: Fault::ALL
+                    .into_iter()
+                    .map(|fault| (fault, stats.faults(fault)))
+                    .collect(),
+                actions: monitor.actions(),
+            },
+            queue_length: 0,
+            dispensers: Vec::new(),
+        }
+    }
+
+    /// Returns the state of a container.
+    pub fn container(&self, container: Container) -> &ContainerSnapshot {
+        self.containers
+            .iter()
+            .find(|snapshot| snapshot.container == container)
+            .expect("Every container is in the snapshot")
+    }
+
+    /// Returns the levels of the containers.
+    pub fn levels(&self) -> Levels {
+        Levels {
+            coffee: self.container(Container::Coffee).level,
+            coffee_beans: self.container(Container::CoffeeBeans).level,
+            foam: self.container(Container::Foam).level,
+            milk: self.container(Container::Milk).level,
+        }
+    }
+
+    /// Serializes the snapshot as a JSON object, in a single line.
+    pub fn to_json(&self) -> String {
+        format!("{{{}}}", self.json_fields())
+    }
+
+    /// Serializes the snapshot like `to_json`, marked as the last one with `"final":true`.
+    pub fn to_final_json(&self) -> String {
+        format!("{{{},\"final\":true}}", self.json_fields())
+    }
+
+    /// The fields of the JSON object of the snapshot, without the braces around them.
+    fn json_fields(&self) -> String {
+        let timestamp = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+        let machine = self
+            .machine
+            .as_ref()
+            .map_or("null".to_string(), |name| format!("\"{}\"", escape(name)));
+        let containers: Vec<String> = self
+            .containers
+            .iter()
+            .map(|snapshot| {
+                let alert = snapshot.alert.map_or("null".to_string(), |severity| {
+                    format!("\"{}\"", severity.to_string().to_lowercase())
+                });
+                format!(
+                    "\"{}\":{{\"level\":{},\"capacity\":{},\"low\":{},\"alert\":{}}}",
+                    key(&snapshot.container.to_string()),
+                    snapshot.level,
+                    snapshot.capacity,
+                    snapshot.low,
+                    alert
+                )
+            })
+            .collect();
+        let faults: Vec<String> = self
+            .counters
+            .faults
+            .iter()
+            .map(|(fault, count)| format!("\"{}\":{}", key(&fault.to_string()), count))
+            .collect();
+        let dispensers: Vec<String> = self
+            .dispensers
+            .iter()
+            .map(|dispenser| {
+                format!(
+                    "{{\"id\":{},\"capabilities\":\"{}\",\"state\":\"{}\"}}",
+                    dispenser.id, dispenser.capabilities, dispenser.state
+                )
+            })
+            .collect();
+
+        format!(
+            "\"timestamp_ms\":{},\"machine\":{},\"containers\":{{{}}},\"totals\":{{\"ground_coffee_beans\":{},\"poured_coffee\":{},\"whipped_milk\":{},\"poured_foam\":{}}},\"counters\":{{\"completed\":{},\"failed\":{},\"retried\":{},\"faults\":{{{}}},\"actions\":{}}},\"queue_length\":{},\"dispensers\":[{}]",
+            timestamp,
+            machine,
+            containers.join(","),
+            self.totals.ground_coffee_beans,
+            self.totals.poured_coffee,
+            self.totals.whipped_milk,
+            self.totals.poured_foam,
+            self.counters.completed,
+            self.counters.failed,
+            self.counters.retried,
+            faults.join(","),
+            self.counters.actions,
+            self.queue_length,
+            dispensers.join(",")
+        )
+    }
+}
+
+/// Turns a name like `coffee beans` into a JSON key like `coffee_beans`.
+fn key(name: &str) -> String {
+    name.replace(' ', "_")
+}
+
+/// Escapes a string to be written inside JSON quotes.
+pub(crate) fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        config, machine::CoffeeMaker, orders::Order, pool::DispenserState, Resources,
+    };
+
+    #[test]
+    fn snapshots_the_resources() {
+        let resources = Resources::new(0, 100, 0, config::L).unwrap();
+        resources.use_coffee(30).unwrap();
+
+        let snapshot = resources.snapshot();
+        assert_eq!(snapshot.machine, None);
+        assert_eq!(
+            snapshot.levels(),
+            Levels {
+                coffee: 0,
+                coffee_beans: 70,
+                foam: 0,
+                milk: config::L,
+            }
+        );
+        let beans = snapshot.container(Container::CoffeeBeans);
+        assert_eq!(beans.capacity, config::G);
+        assert!(beans.low);
+        assert_eq!(beans.alert, Some(Severity::Critical));
+        assert!(!snapshot.container(Container::Milk).low);
+        assert_eq!(snapshot.totals.ground_coffee_beans, 30);
+        assert_eq!(snapshot.totals.poured_coffee, 30);
+        assert_eq!(snapshot.counters.actions, 2);
+        assert!(snapshot.dispensers.is_empty());
+    }
+
+    #[test]
+    fn snapshots_the_machine() {
+        let resources = Resources::new(100, 0, 100, 0).unwrap();
+        let machine = CoffeeMaker::new("front", resources, &[Capabilities::ALL; 2]);
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        machine.shutdown();
+
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.machine.as_deref(), Some("front"));
+        assert_eq!(snapshot.counters.completed, 1);
+        assert_eq!(snapshot.queue_length, 0);
+        assert!(snapshot
+            .dispensers
+            .iter()
+            .all(|dispenser| dispenser.state == DispenserState::Stopped));
+    }
+
+    #[test]
+    fn escapes_quotes_and_control_characters() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a \"b\" \\ c"), "a \\\"b\\\" \\\\ c");
+        assert_eq!(escape("one\ntwo\tthree\r"), "one\\ntwo\\tthree\\r");
+        assert_eq!(escape("\u{1b}[2J"), "\\u001b[2J");
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let resources = Resources::new(10, 0, 0, 0).unwrap();
+        let mut snapshot = resources.snapshot();
+        snapshot.machine = Some("the \"best\" one".to_string());
+        snapshot.queue_length = 3;
+        snapshot.dispensers.push(DispenserSnapshot {
+            id: 0,
+            capabilities: Capabilities::ALL,
+            state: DispenserState::Running,
+        });
+
+        let json = snapshot.to_json();
+        assert!(json.starts_with("{\"timestamp_ms\":"));
+        assert!(json.contains(",\"machine\":\"the \\\"best\\\" one\","));
+        assert!(json.contains(
+            "\"coffee\":{\"level\":10,\"capacity\":1000,\"low\":true,\"alert\":\"critical\"}"
+        ));
+        assert!(json.contains("\"faults\":{\"jam\":0,\"slow_actuator\":0,"));
+        assert!(json.ends_with(
+            ",\"queue_length\":3,\"dispensers\":[{\"id\":0,\"capabilities\":\"coffee+water+foam\",\"state\":\"running\"}]}"
+        ));
+    }
+}
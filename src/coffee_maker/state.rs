@@ -0,0 +1,333 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use super::{
+    faults::Fault,
+    journal::{sync_dir, temp_path},
+    machine::CoffeeMaker,
+    observer::Container,
+    orders::{Ingredients, Order},
+    resources_monitor::Levels,
+};
+
+/// Version of the state files written, increased whenever their format changes.
+pub const STATE_VERSION: u32 = 1;
+
+/// Key of a container or fault in the state file, its name without spaces.
+fn key(name: impl ToString) -> String {
+    name.to_string().replace(' ', "_")
+}
+
+/// What a coffee maker keeps from one run to the next: the levels of its containers,
+/// its counters and the orders it didn't get to make.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub levels: Levels,
+    pub completed: u64,
+    pub failed: u64,
+    pub retried: u64,
+    pub rejected: u64,
+    pub abandoned: u64,
+    pub faults: Vec<(Fault, u64)>,
+    /// Orders left in the queue, in the order they were to be taken,
+    /// with how much longer their customers wait if they leave at all.
+    pub orders: Vec<(Ingredients, Option<Duration>)>,
+}
+
+impl MachineState {
+    /// Takes the state of a coffee maker.
+    pub fn of(machine: &CoffeeMaker) -> MachineState {
+        let stats = machine.resources().stats();
+        MachineState {
+            levels: machine.resources().levels(),
+            completed: stats.completed(),
+            failed: stats.failed(),
+            retried: stats.retried(),
+            rejected: machine.orders().rejected(),
+            abandoned: machine.orders().abandoned(),
+            faults: Fault::ALL
+                .into_iter()
+                .map(|fault| (fault, stats.faults(fault)))
+                .collect(),
+            orders: machine
+                .orders()
+                .tickets()
+                .into_iter()
+                .map(|ticket| {
+                    // orders already taken by a dispenser are never abandoned
+                    let deadline = ticket.deadline.filter(|_| ticket.dequeued_at.is_none());
+                    let patience =
+                        deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    (ticket.ingredients, patience)
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads the state saved in a file, if there is one.
+    /// Fails if it can't be read, or it isn't a valid state of this version.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<MachineState>, String> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => contents
+                .parse()
+                .map(Some)
+                .map_err(|error| format!("Invalid state in {}: {}", path.display(), error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(format!("Failed to read {}: {}", path.display(), error)),
+        }
+    }
+
+    /// Saves the state to a file, replacing it at once so that it is never left half written,
+    /// and waiting for it to reach the disk.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let temporary = temp_path(path);
+        let mut file = File::create(&temporary)?;
+        file.write_all(self.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        sync_dir(path)
+    }
+}
+
+impl fmt::Display for MachineState {
+    /// Displays the state in the format of the state file: a `key=value` per line,
+    /// starting with the version and followed by an `order=coffee,water,foam` per order,
+    /// preceded by `~<ms>,` if its customer leaves after that long.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version={}", STATE_VERSION)?;
+        for container in Container::ALL {
+            writeln!(f, "{}={}", key(container), self.levels.get(container))?;
+        }
+        writeln!(f, "completed={}", self.completed)?;
+        writeln!(f, "failed={}", self.failed)?;
+        writeln!(f, "retried={}", self.retried)?;
+        writeln!(f, "rejected={}", self.rejected)?;
+        writeln!(f, "abandoned={}", self.abandoned)?;
+        for (fault, count) in &self.faults {
+            writeln!(f, "fault.{}={}", key(fault), count)?;
+        }
+        for (order, patience) in &self.orders {
+            let patience = patience.map_or(String::new(), |p| format!("~{},", p.as_millis()));
+            let (coffee, water, foam) = (order.coffee, order.water, order.foam);
+            writeln!(f, "order={}{},{},{}", patience, coffee, water, foam)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MachineState {
+    type Err = String;
+
+    /// Parses a state in the format of the state file.
+    /// Every level must be there and within the capacity of its container;
+    /// missing counters are zero.
+    fn from_str(s: &str) -> Result<MachineState, String> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        match lines.next().and_then(|line| line.strip_prefix("version=")) {
+            Some(version) if version == STATE_VERSION.to_string() => {}
+            Some(version) => return Err(format!("Unsupported state version: {}", version)),
+            None => return Err("The state must start with its version".to_string()),
+        }
+
+        let mut levels = [None; Container::ALL.len()];
+        let mut state = MachineState {
+            levels: Levels::default(),
+            completed: 0,
+            failed: 0,
+            retried: 0,
+            rejected: 0,
+            abandoned: 0,
+            faults: Fault::ALL.into_iter().map(|fault| (fault, 0)).collect(),
+            orders: Vec::new(),
+        };
+        for line in lines {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(format!("Invalid state line: {}", line))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid {}: {}", name, value))
+            };
+            if let Some(i) = Container::ALL.iter().position(|c| key(c) == name) {
+                let level = number()?;
+                let container = Container::ALL[i];
+                if level > container.capacity().into() {
+                    return Err(format!(
+                        "The level of {} is over the capacity of its container: {}",
+                        container, level
+                    ));
+                }
+                levels[i] = Some(level as u32);
+            } else if let Some(fault) = name.strip_prefix("fault.") {
+                let count = number()?;
+                let (_, total) = state
+                    .faults
+                    .iter_mut()
+                    .find(|(f, _)| key(f) == fault)
+                    .ok_or(format!("Unknown fault: {}", fault))?;
+                *total = count;
+            } else {
+                match name {
+                    "completed" => state.completed = number()?,
+                    "failed" => state.failed = number()?,
+                    "retried" => state.retried = number()?,
+                    "rejected" => state.rejected = number()?,
+                    "abandoned" => state.abandoned = number()?,
+                    "order" => {
+                        let invalid = || format!("Invalid order: {}", value);
+                        let (patience, amounts) = match value.strip_prefix('~') {
+                            Some(rest) => {
+                                let (millis, amounts) = rest.split_once(',').ok_or_else(invalid)?;
+                                let millis = millis.parse().map_err(|_| invalid())?;
+                                (Some(Duration::from_millis(millis)), amounts)
+                            }
+                            None => (None, value),
+                        };
+                        let amounts = amounts
+                            .split(',')
+                            .map(|amount| amount.parse().map_err(|_| invalid()))
+                            .collect::<Result<Vec<u32>, String>>()?;
+                        let [coffee, water, foam] = amounts[..] else {
+                            return Err(invalid());
+                        };
+                        match Order::from(coffee, water, foam)? {
+                            Order::Order(ingredients) => state.orders.push((ingredients, patience)),
+                            Order::NoMoreOrders => return Err(invalid()),
+                        }
+                    }
+                    _ => return Err(format!("Unknown state key: {}", name)),
+                }
+            }
+        }
+
+        let level = |container: Container| {
+            let i = Container::ALL.iter().position(|c| *c == container);
+            i.and_then(|i| levels[i])
+                .ok_or(format!("Missing level of {}", container))
+        };
+        state.levels = Levels {
+            coffee: level(Container::Coffee)?,
+            coffee_beans: level(Container::CoffeeBeans)?,
+            foam: level(Container::Foam)?,
+            milk: level(Container::Milk)?,
+        };
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        builder::CoffeeMakerBuilder, capabilities::Capabilities, test_utils::temp_file,
+    };
+
+    fn state() -> MachineState {
+        MachineState {
+            levels: Levels {
+                coffee: 100,
+                coffee_beans: 200,
+                foam: 0,
+                milk: 300,
+            },
+            completed: 5,
+            failed: 1,
+            retried: 2,
+            rejected: 3,
+            abandoned: 4,
+            faults: Fault::ALL.into_iter().map(|fault| (fault, 1)).collect(),
+            orders: vec![
+                (Ingredients::new(10, 20, 30), None),
+                (Ingredients::new(0, 10, 0), Some(Duration::from_millis(250))),
+            ],
+        }
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let state = state();
+        let written = state.to_string();
+        assert!(written.starts_with("version=1\ncoffee=100\ncoffee_beans=200\n"));
+        assert!(written.contains("rejected=3\nabandoned=4\n"));
+        assert!(written.contains("fault.slow_actuator=1\n"));
+        assert!(written.ends_with("order=10,20,30\norder=~250,0,10,0\n"));
+        assert_eq!(written.parse(), Ok(state));
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let written = state().to_string();
+        let invalid = [
+            written.replace("version=1", "version=2"),
+            written.replace("version=1\n", ""),
+            written.replace("milk=300", "milk=100000"),
+            written.replace("coffee_beans=200\n", ""),
+            written.replace("order=10,20,30", "order=10,20"),
+            written.replace("order=10,20,30", "order=5000,0,0"),
+            written.replace("order=~250,", "order=~,"),
+            written.replace("order=~250,0,10,0", "order=~250"),
+            written.replace("fault.jam", "fault.fire"),
+            written.replace("completed", "served"),
+        ];
+        for state in invalid {
+            assert!(state.parse::<MachineState>().is_err(), "{}", state);
+        }
+    }
+
+    #[test]
+    fn restores_what_was_left() {
+        let path = temp_file("state");
+        let _ = fs::remove_file(&path);
+        assert_eq!(MachineState::load(&path), Ok(None));
+
+        // no dispenser can make foam, so the order is left in the queue
+        let machine = CoffeeMakerBuilder::new()
+            .resources(100, 100, 100, 100)
+            .dispensers(vec!["coffee+water".parse().unwrap()])
+            .state_file(&path)
+            .build()
+            .unwrap();
+        machine.orders().push(Order::from(30, 10, 0).unwrap());
+        let patience = Some(Duration::from_secs(60));
+        machine
+            .orders()
+            .push_with_patience(Order::from(0, 0, 10).unwrap(), patience);
+        assert!(machine
+            .orders()
+            .try_push(Order::from(0, 0, 20).unwrap())
+            .is_err());
+        machine.shutdown();
+
+        let saved = MachineState::load(&path).unwrap().unwrap();
+        assert_eq!(saved.levels.coffee, 70);
+        assert_eq!(saved.completed, 1);
+        assert_eq!(saved.rejected, 1);
+        assert_eq!(saved.orders.len(), 1);
+        let left = saved.orders[0].1.unwrap();
+        assert!(left <= Duration::from_secs(60) && left > Duration::from_secs(50));
+
+        let machine = CoffeeMakerBuilder::new()
+            .dispensers(vec![Capabilities::ALL])
+            .state_file(&path)
+            .build()
+            .unwrap();
+        let report = machine.shutdown();
+        assert_eq!(report.completed, 2);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(machine.resources().levels().coffee, 70);
+        assert_eq!(machine.resources().levels().foam, 90);
+
+        let saved = MachineState::load(&path).unwrap().unwrap();
+        assert!(saved.orders.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
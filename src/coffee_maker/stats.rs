@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::faults::Fault;
+
+/// Counters of what happened while running the coffee maker.
+/// Can be used thread-safely.
+#[derive(Debug, Default)]
+pub struct Stats {
+    completed: AtomicU64,
+    failed: AtomicU64,
+    retried: AtomicU64,
+    faults: [AtomicU64; Fault::ALL.len()],
+}
+
+impl Stats {
+    /// Creates a new Stats instance with every counter at zero.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Counts an order that was fulfilled.
+    pub fn order_completed(&self) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an order that could not be fulfilled.
+    pub fn order_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an order that was sent back to the queue to be retried.
+    pub fn order_retried(&self) {
+        self.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a fault.
+    pub fn fault(&self, fault: Fault) {
+        self.faults[fault as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets every counter, to carry on from a previous run.
+    pub fn restore(&self, completed: u64, failed: u64, retried: u64, faults: &[(Fault, u64)]) {
+        self.completed.store(completed, Ordering::Relaxed);
+        self.failed.store(failed, Ordering::Relaxed);
+        self.retried.store(retried, Ordering::Relaxed);
+        for (fault, count) in faults {
+            self.faults[*fault as usize].store(*count, Ordering::Relaxed);
+        }
+    }
+
+    /// Amount of orders fulfilled.
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    /// Amount of orders that could not be fulfilled.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Amount of retries.
+    pub fn retried(&self) -> u64 {
+        self.retried.load(Ordering::Relaxed)
+    }
+
+    /// Amount of faults of the given kind.
+    pub fn faults(&self, fault: Fault) -> u64 {
+        self.faults[fault as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for Stats {
+    /// Displays the counters, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Completed: {}", self.completed())?;
+        writeln!(f, "Failed: {}", self.failed())?;
+        writeln!(f, "Retried: {}", self.retried())?;
+        for fault in Fault::ALL {
+            writeln!(f, "Faults ({}): {}", fault, self.faults(fault))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn counters_start_at_zero() {
+        let stats = Stats::new();
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.failed(), 0);
+        assert_eq!(stats.retried(), 0);
+        for fault in Fault::ALL {
+            assert_eq!(stats.faults(fault), 0);
+        }
+    }
+
+    #[test]
+    fn faults_are_counted_separately() {
+        let stats = Stats::new();
+        stats.fault(Fault::Jam);
+        stats.fault(Fault::Jam);
+        stats.fault(Fault::SensorMisread);
+        assert_eq!(stats.faults(Fault::Jam), 2);
+        assert_eq!(stats.faults(Fault::SensorMisread), 1);
+        assert_eq!(stats.faults(Fault::PartialPour), 0);
+    }
+}
@@ -1,9 +1,13 @@
-use super::orders::{Order, Orders};
+use super::{
+    orders::{Ingredients, Order, Orders, Origin},
+    resources::sleep,
+};
 use std::{
     fs::File,
     io::{self, BufRead},
     sync::Arc,
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 /// Parses a line into an Order.
@@ -27,17 +31,164 @@ fn parse_line(line: io::Result<String>) -> io::Result<Order> {
     Ok(order)
 }
 
-/// Takes orders from a file and puts them into the queue.
-/// Pushes a `NoMoreOrders` order when finished.
-fn take_orders_loop(orders_file: File, orders: Arc<Orders>) {
+/// When an order arrives, from the optional first column of the orders file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    /// Since the orders started being taken, written `@<ms>`.
+    At(Duration),
+    /// Since the previous order arrived, written `+<ms>`.
+    After(Duration),
+}
+
+impl Arrival {
+    /// Time it arrives, given when the previous order did.
+    /// Orders never arrive before the previous one.
+    pub fn time(&self, previous: Duration) -> Duration {
+        match *self {
+            Arrival::At(at) => at.max(previous),
+            Arrival::After(delay) => previous + delay,
+        }
+    }
+}
+
+/// An order read from a line of the orders file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    arrival: Option<Arrival>,
+    /// How long the customer waits for the order to be taken.
+    patience: Option<Duration>,
+    order: Order,
+}
+
+/// Parses a line into an Order, along with when it arrives and how long its customer waits,
+/// if the line says so.
+/// The format is the one of `parse_line`, optionally preceded by `@<ms:u64>,` or `+<ms:u64>,`
+/// and by `~<ms:u64>,`, in any order.
+fn parse_timed_line(line: io::Result<String>) -> io::Result<Entry> {
+    let line = line?;
+    let error = io::ErrorKind::InvalidData;
+    let (mut arrival, mut patience, mut rest) = (None, None, line.as_str());
+    while let Some((first, others)) = rest.split_once(',') {
+        let millis = || first[1..].parse().map(Duration::from_millis).or(Err(error));
+        match first.chars().next() {
+            Some('@') if arrival.is_none() => arrival = Some(Arrival::At(millis()?)),
+            Some('+') if arrival.is_none() => arrival = Some(Arrival::After(millis()?)),
+            Some('~') if patience.is_none() => patience = Some(millis()?),
+            Some('@' | '+' | '~') => return Err(io::Error::from(error)),
+            _ => break,
+        }
+        rest = others;
+    }
+    Ok(Entry {
+        arrival,
+        patience,
+        order: parse_line(Ok(rest.to_string()))?,
+    })
+}
+
+/// Where orders are taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderSource {
+    /// A file with an order per line.
+    File(String),
+    /// A file with an order per line, skipping the given amount of orders, taken in a previous run.
+    Resumed(String, usize),
+    /// A list of orders.
+    List(Vec<Order>),
+}
+
+/// Reads every valid order of the file at the given path, skipping invalid lines.
+/// Arrival times and patience are ignored.
+pub fn read_orders_file(orders_filename: &str) -> io::Result<Vec<Order>> {
+    let orders_file = File::open(orders_filename)?;
     let lines = io::BufReader::new(&orders_file).lines();
+    Ok(lines
+        .filter_map(|line| parse_timed_line(line).ok())
+        .map(|entry| entry.order)
+        .collect())
+}
 
-    for line in lines {
-        if let Ok(order) = parse_line(line) {
-            orders.push(order);
+/// Reads every valid order of the file at the given path, skipping invalid lines,
+/// along with the time it arrives since the first one. Untimed orders arrive along with
+/// the previous one. Patience is ignored.
+pub fn read_timed_orders_file(orders_filename: &str) -> io::Result<Vec<(Duration, Ingredients)>> {
+    let orders_file = File::open(orders_filename)?;
+    let lines = io::BufReader::new(&orders_file).lines();
+    let mut now = Duration::ZERO;
+    let mut timed = Vec::new();
+    for entry in lines.filter_map(|line| parse_timed_line(line).ok()) {
+        now = entry.arrival.map_or(now, |arrival| arrival.time(now));
+        if let Order::Order(ingredients) = entry.order {
+            timed.push((now, ingredients));
         }
     }
+    Ok(timed)
+}
 
+/// When an order arriving at the given time since `start` is due, that time being `speedup`
+/// times shorter.
+fn arrival_deadline(start: Instant, at: Duration, speedup: u32) -> Instant {
+    start + at / speedup.max(1)
+}
+
+/// Waits until an order arriving at the given time since `start` is due, that time being
+/// `speedup` times shorter. Waiting for a deadline instead of the time between orders keeps
+/// the waits from drifting.
+pub(crate) fn wait_for_arrival(start: Instant, at: Duration, speedup: u32) {
+    let deadline = arrival_deadline(start, at, speedup);
+    sleep::sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+/// Takes orders from a file and puts them into the queue, waiting for the arrival time
+/// of each order, if it has one, following the clock made `speedup` times faster.
+/// Customers wait for as long as their line says, or the patience of the queue.
+/// Orders that no dispenser can make are rejected.
+/// The first `skip` valid orders are left out, as they were taken in a previous run.
+/// Orders taken are told to come from the given source, if any.
+fn read_orders(
+    orders_file: File,
+    orders: &Orders,
+    skip: usize,
+    source: Option<usize>,
+    speedup: u32,
+) {
+    let lines = io::BufReader::new(&orders_file).lines();
+    let entries = lines.filter_map(|line| parse_timed_line(line).ok());
+    let start = Instant::now();
+    let mut now = Duration::ZERO;
+    // the skipped orders arrived in the previous run
+    let mut resumed_at = Duration::ZERO;
+
+    for (i, entry) in entries.enumerate() {
+        let skipped = i < skip;
+        if let Some(arrival) = entry.arrival {
+            let at = arrival.time(now);
+            match skipped {
+                true => resumed_at = at,
+                false => wait_for_arrival(start, at.saturating_sub(resumed_at), speedup),
+            }
+            now = at;
+        }
+        if !skipped {
+            let origin = source.map(|source| Origin {
+                source,
+                position: i,
+            });
+            match entry.order {
+                // rejected orders are counted by the queue
+                Order::Order(ingredients) => {
+                    let _ = orders.take_from(ingredients, entry.patience, origin);
+                }
+                Order::NoMoreOrders => orders.push(entry.order),
+            }
+        }
+    }
+}
+
+/// Takes orders from a file and puts them into the queue.
+/// Pushes a `NoMoreOrders` order when finished.
+fn take_orders_loop(orders_file: File, orders: Arc<Orders>) {
+    read_orders(orders_file, &orders, 0, None, 1);
     orders.push(Order::NoMoreOrders);
 }
 
@@ -49,6 +200,37 @@ pub fn take_orders(orders_filename: String, orders: Arc<Orders>) -> io::Result<J
     Ok(handler)
 }
 
+/// Takes orders from the given source and puts them into the queue.
+/// Unlike `take_orders`, doesn't push `NoMoreOrders` when finished; so that several
+/// sources can share a queue.
+/// Orders read from files are told to come from the given index, that of the source
+/// among those of the coffee maker; and arrive `speedup` times faster than their times say.
+/// Returns a handle to the thread that is taking the orders.
+pub fn take_orders_from(
+    source: OrderSource,
+    index: usize,
+    orders: Arc<Orders>,
+    speedup: u32,
+) -> io::Result<JoinHandle<()>> {
+    let handler = match source {
+        OrderSource::File(orders_filename) => {
+            let orders_file = File::open(orders_filename)?;
+            thread::spawn(move || read_orders(orders_file, &orders, 0, Some(index), speedup))
+        }
+        OrderSource::Resumed(orders_filename, skip) => {
+            let orders_file = File::open(orders_filename)?;
+            thread::spawn(move || read_orders(orders_file, &orders, skip, Some(index), speedup))
+        }
+        OrderSource::List(list) => thread::spawn(move || {
+            for order in list {
+                // rejected orders are counted by the queue
+                let _ = orders.try_push(order);
+            }
+        }),
+    };
+    Ok(handler)
+}
+
 #[cfg(test)]
 mod parse_line_tests {
     use crate::coffee_maker::orders::Order;
@@ -95,6 +277,74 @@ mod parse_line_tests {
     }
 }
 
+#[cfg(test)]
+mod parse_timed_line_tests {
+    use super::*;
+
+    fn parse(line: &str) -> io::Result<Entry> {
+        parse_timed_line(Ok(line.to_string()))
+    }
+
+    #[test]
+    fn untimed_lines_are_unchanged() {
+        let entry = parse("1,2,3").unwrap();
+        assert_eq!(entry.arrival, None);
+        assert_eq!(entry.patience, None);
+        assert_eq!(entry.order, Order::from(1, 2, 3).unwrap());
+        assert!(parse("1,2,3,4").is_err());
+    }
+
+    #[test]
+    fn timed_lines() {
+        let entry = parse("@1500,1,2,3").unwrap();
+        assert_eq!(
+            entry.arrival,
+            Some(Arrival::At(Duration::from_millis(1500)))
+        );
+        assert_eq!(entry.order, Order::from(1, 2, 3).unwrap());
+        let entry = parse("+250,1,2,3").unwrap();
+        assert_eq!(
+            entry.arrival,
+            Some(Arrival::After(Duration::from_millis(250)))
+        );
+
+        for invalid in [
+            "@,1,2,3",
+            "@-5,1,2,3",
+            "+1.5,1,2,3",
+            "@10,1,2",
+            "@10,1,2,3,4",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn lines_with_patience() {
+        let entry = parse("~3000,1,2,3").unwrap();
+        assert_eq!(entry.patience, Some(Duration::from_secs(3)));
+        assert_eq!(entry.arrival, None);
+        let entry = parse("~3000,+10,1,2,3").unwrap();
+        assert_eq!(
+            entry.arrival,
+            Some(Arrival::After(Duration::from_millis(10)))
+        );
+        assert_eq!(entry.patience, Some(Duration::from_secs(3)));
+
+        for invalid in ["~1,~2,1,2,3", "@1,+2,1,2,3", "~,1,2,3"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn arrivals_never_go_back() {
+        let second = Duration::from_secs(1);
+        assert_eq!(Arrival::At(second).time(Duration::ZERO), second);
+        assert_eq!(Arrival::At(Duration::ZERO).time(second), second);
+        assert_eq!(Arrival::After(second).time(second), second * 2);
+    }
+}
+
 #[cfg(test)]
 mod take_orders_tests {
 
@@ -156,4 +406,57 @@ mod take_orders_tests {
 
         fs::remove_file(filename).unwrap();
     }
+
+    #[test]
+    fn timed_orders() {
+        let filename = "assets/_temp__take_orders__timed_orders.csv";
+        create_file(filename, "@1000,1,2,3\n4,5,6\n+500,7,8,9\n@200,1,1,1\n");
+
+        let timed = read_timed_orders_file(filename).unwrap();
+        let times: Vec<u64> = timed.iter().map(|(at, _)| at.as_millis() as u64).collect();
+        assert_eq!(times, vec![1000, 1000, 1500, 1500]);
+        assert_eq!(read_orders_file(filename).unwrap().len(), 4);
+
+        let orders = Orders::new();
+        take_orders(filename.to_string(), orders.clone())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(orders.len(), 4);
+        assert_eq!(orders.pop(), Order::from(1, 2, 3).unwrap());
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn take_orders_from_list_without_no_more_orders() {
+        let list = vec![Order::from(1, 2, 3).unwrap(), Order::from(4, 5, 6).unwrap()];
+        let orders = Orders::new();
+        let handle =
+            take_orders_from(OrderSource::List(list.clone()), 0, orders.clone(), 1).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(orders.len(), 2);
+        for order in list {
+            assert_eq!(orders.pop(), order);
+        }
+    }
+
+    #[test]
+    fn take_orders_from_missing_file() {
+        let source = OrderSource::File("assets/_temp__missing.csv".to_string());
+        assert!(take_orders_from(source, 0, Orders::new(), 1).is_err());
+    }
+
+    #[test]
+    fn arrivals_are_due_from_the_start_and_sped_up() {
+        let start = Instant::now();
+        let at = Duration::from_millis(1000);
+        assert_eq!(arrival_deadline(start, at, 1), start + at);
+        assert_eq!(
+            arrival_deadline(start, at, 10),
+            start + Duration::from_millis(100)
+        );
+        assert_eq!(arrival_deadline(start, at, 0), start + at);
+    }
 }
@@ -0,0 +1,36 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/// A writer that can be read after being moved into whatever writes to it.
+#[derive(Clone, Default)]
+pub struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl SharedWriter {
+    /// Returns everything written so far.
+    pub fn written(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+
+    /// Returns the lines written so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.written().lines().map(str::to_string).collect()
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Path of a file in the temporary directory, unique to the given name and this process.
+pub fn temp_file(name: &str) -> String {
+    let file = format!("coffee_{}_{}", name, std::process::id());
+    std::env::temp_dir().join(file).display().to_string()
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::observer::{Action, ActionEvent, Container, Context, Observer};
+
+/// Track of the grinder.
+const GRINDER_TRACK: u64 = 1;
+/// Track of the whipper.
+const WHIPPER_TRACK: u64 = 2;
+/// Track of what wasn't done by a dispenser.
+const OTHER_TRACK: u64 = 3;
+/// Track of the first dispenser, the others follow it.
+const FIRST_DISPENSER_TRACK: u64 = 10;
+
+/// A span of time on a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Span {
+    name: String,
+    track: u64,
+    start: Duration,
+    duration: Duration,
+    order: Option<u64>,
+    amount: Option<u32>,
+}
+
+/// Records a timeline of what the coffee maker did, to be exported in the Chrome Trace Event
+/// format, which can be opened in `chrome://tracing` or Perfetto.
+///
+/// There is a track for each dispenser, with its lock waits and pours,
+/// and a track for the grinder and for the whipper.
+pub struct Trace {
+    origin: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Trace {
+    /// Creates a new Trace instance, with its timeline starting now.
+    pub fn new() -> Arc<Trace> {
+        Arc::new(Trace {
+            origin: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Track of what was done for the given context.
+    fn dispenser_track(context: Context) -> u64 {
+        context
+            .dispenser
+            .map_or(OTHER_TRACK, |id| FIRST_DISPENSER_TRACK + id as u64)
+    }
+
+    /// Adds a span that started at the given instant.
+    fn add(
+        &self,
+        name: String,
+        track: u64,
+        started: Instant,
+        duration: Duration,
+        context: Context,
+        amount: Option<u32>,
+    ) {
+        let span = Span {
+            name,
+            track,
+            start: started.saturating_duration_since(self.origin),
+            duration,
+            order: context.order,
+            amount,
+        };
+        self.spans.lock().expect("Failed to lock trace").push(span);
+    }
+
+    /// Writes the trace as a Chrome Trace Event JSON object.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let spans = self.spans.lock().expect("Failed to lock trace");
+        let tracks: BTreeSet<u64> = spans.iter().map(|span| span.track).collect();
+
+        let mut events: Vec<String> = tracks
+            .into_iter()
+            .map(|track| {
+                format!(
+                    "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                    track,
+                    track_name(track)
+                )
+            })
+            .collect();
+        events.extend(spans.iter().map(|span| {
+            let mut args = Vec::new();
+            if let Some(order) = span.order {
+                args.push(format!("\"order\":{}", order));
+            }
+            if let Some(amount) = span.amount {
+                args.push(format!("\"amount\":{}", amount));
+            }
+            format!(
+                "{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{{}}}}}",
+                span.name,
+                span.track,
+                span.start.as_micros(),
+                span.duration.as_micros(),
+                args.join(",")
+            )
+        }));
+
+        writeln!(
+            writer,
+            "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n{}\n]}}",
+            events.join(",\n")
+        )?;
+        writer.flush()
+    }
+
+    /// Writes the trace to the file at the given path, which is truncated if it exists.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)
+    }
+}
+
+/// Name shown for a track.
+fn track_name(track: u64) -> String {
+    match track {
+        GRINDER_TRACK => "grinder".to_string(),
+        WHIPPER_TRACK => "whipper".to_string(),
+        OTHER_TRACK => "other".to_string(),
+        dispenser => format!("dispenser {}", dispenser - FIRST_DISPENSER_TRACK),
+    }
+}
+
+impl Observer for Trace {
+    fn lock_acquired(&self, context: Context, container: Container, waited: Duration) {
+        let track = Trace::dispenser_track(context);
+        let started = Instant::now() - waited;
+        let name = format!("wait {} lock", container);
+        self.add(name, track, started, waited, context, None);
+    }
+
+    fn action_performed(&self, event: &ActionEvent) {
+        let track = match event.action {
+            Action::Grind => GRINDER_TRACK,
+            Action::Whip => WHIPPER_TRACK,
+            _ => Trace::dispenser_track(event.context),
+        };
+        let duration = event.started.elapsed();
+        let name = event.action.to_string();
+        self.add(
+            name,
+            track,
+            event.started,
+            duration,
+            event.context,
+            Some(event.amount),
+        );
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    use crate::coffee_maker::{
+        capabilities::Capabilities, machine::CoffeeMaker, orders::Order, Resources,
+    };
+
+    fn written(trace: &Trace) -> String {
+        let mut buffer = Vec::new();
+        trace.write(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn empty_trace() {
+        let trace = Trace::new();
+        assert_eq!(
+            written(&trace),
+            "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n\n]}\n"
+        );
+    }
+
+    #[test]
+    fn tracks_every_actuator() {
+        let trace = Trace::new();
+        let resources = Resources::new(0, 100, 0, 100).unwrap();
+        resources.register_observer(trace.clone());
+        let machine = CoffeeMaker::new("test", resources, &[Capabilities::ALL; 2]);
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        machine.orders().push(Order::from(10, 10, 10).unwrap());
+        machine.shutdown();
+
+        let written = written(&trace);
+        for track in ["grinder", "whipper", "dispenser 0", "dispenser 1"] {
+            assert!(written.contains(&format!("\"args\":{{\"name\":\"{}\"}}", track)));
+        }
+        for span in [
+            "wait coffee lock",
+            "wait foam lock",
+            "grind",
+            "pour_coffee",
+            "pour_water",
+            "whip",
+            "pour_foam",
+        ] {
+            assert!(written.contains(&format!("\"name\":\"{}\",\"ph\":\"X\"", span)));
+        }
+        assert!(!written.contains("\"other\""));
+    }
+
+    #[test]
+    fn spans_without_dispenser_go_to_other_track() {
+        let trace = Trace::new();
+        let resources = Resources::new(10, 0, 0, 0).unwrap();
+        resources.register_observer(trace.clone());
+        resources.use_coffee(10).unwrap();
+
+        let written = written(&trace);
+        assert!(written.contains("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":3,\"args\":{\"name\":\"other\"}}"));
+        assert!(written.contains("\"name\":\"pour_coffee\",\"ph\":\"X\",\"pid\":1,\"tid\":3,"));
+        assert!(written.contains("\"args\":{\"amount\":10}"));
+    }
+}
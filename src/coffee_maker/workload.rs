@@ -0,0 +1,451 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::{
+    config,
+    orders::{Ingredients, Order, Orders},
+    rng::Rng,
+    take_orders::wait_for_arrival,
+};
+
+/// A kind of drink, with the range of each ingredient it is made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Drink {
+    pub name: &'static str,
+    /// Least and most coffee, in mg.
+    pub coffee: (u32, u32),
+    /// Least and most water, in ml.
+    pub water: (u32, u32),
+    /// Least and most foam, in ml.
+    pub foam: (u32, u32),
+}
+
+impl Drink {
+    pub const ESPRESSO: Drink = Drink {
+        name: "espresso",
+        coffee: (30, 60),
+        water: (20, 40),
+        foam: (0, 0),
+    };
+    pub const AMERICANO: Drink = Drink {
+        name: "americano",
+        coffee: (30, 60),
+        water: (150, 250),
+        foam: (0, 0),
+    };
+    pub const CAPPUCCINO: Drink = Drink {
+        name: "cappuccino",
+        coffee: (30, 60),
+        water: (50, 100),
+        foam: (60, 120),
+    };
+    pub const LATTE: Drink = Drink {
+        name: "latte",
+        coffee: (20, 40),
+        water: (100, 200),
+        foam: (100, 200),
+    };
+
+    pub const ALL: [Drink; 4] = [
+        Drink::ESPRESSO,
+        Drink::AMERICANO,
+        Drink::CAPPUCCINO,
+        Drink::LATTE,
+    ];
+
+    /// Returns the drink with the given name, if any.
+    pub fn by_name(name: &str) -> Option<Drink> {
+        Drink::ALL.into_iter().find(|drink| drink.name == name)
+    }
+
+    /// Picks the ingredients of a drink, bounded by the capacity of the containers.
+    fn pour(&self, rng: &mut Rng) -> Ingredients {
+        Ingredients {
+            coffee: between(rng, self.coffee).min(config::C),
+            water: between(rng, self.water),
+            foam: between(rng, self.foam).min(config::E),
+        }
+    }
+}
+
+/// Picks a number between the bounds, both included.
+fn between(rng: &mut Rng, (least, most): (u32, u32)) -> u32 {
+    let span = most.saturating_sub(least) as u64 + 1;
+    least + (rng.next_u64() % span) as u32
+}
+
+/// How customers arrive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arrivals {
+    /// At random, with the given mean amount of orders per minute.
+    Poisson { per_minute: f64 },
+    /// At random like `Poisson`, but at `rush_per_minute` for a while, like a morning rush.
+    Rush {
+        per_minute: f64,
+        rush_per_minute: f64,
+        start: Duration,
+        length: Duration,
+    },
+}
+
+impl Arrivals {
+    /// Mean orders per minute, outside of any rush.
+    pub fn per_minute(&self) -> f64 {
+        match *self {
+            Arrivals::Poisson { per_minute } | Arrivals::Rush { per_minute, .. } => per_minute,
+        }
+    }
+
+    /// Mean orders per millisecond at the given time, in milliseconds since the start.
+    fn rate_at(&self, at: f64) -> f64 {
+        let per_minute = match *self {
+            Arrivals::Poisson { per_minute } => per_minute,
+            Arrivals::Rush {
+                per_minute,
+                rush_per_minute,
+                start,
+                length,
+            } => {
+                let start = start.as_secs_f64() * 1000.0;
+                let end = start + length.as_secs_f64() * 1000.0;
+                if (start..end).contains(&at) {
+                    rush_per_minute
+                } else {
+                    per_minute
+                }
+            }
+        };
+        per_minute / 60_000.0
+    }
+
+    /// When the rate changes next after the given time, if it does.
+    fn change_after(&self, at: f64) -> Option<f64> {
+        match *self {
+            Arrivals::Poisson { .. } => None,
+            Arrivals::Rush { start, length, .. } => {
+                let start = start.as_secs_f64() * 1000.0;
+                let end = start + length.as_secs_f64() * 1000.0;
+                [start, end].into_iter().find(|change| *change > at)
+            }
+        }
+    }
+
+    /// Time of the arrival following one at the given time, in milliseconds.
+    /// As arrivals are memoryless, reaching a change of rate just draws again from there.
+    fn next(&self, mut at: f64, rng: &mut Rng) -> f64 {
+        loop {
+            let rate = self.rate_at(at);
+            let gap = match rate {
+                rate if rate > 0.0 => -(1.0 - rng.next_f64()).ln() / rate,
+                _ => f64::INFINITY,
+            };
+            match self.change_after(at) {
+                Some(change) if at + gap > change => at = change,
+                _ => return at + gap,
+            }
+        }
+    }
+}
+
+/// Settings to generate a stream of orders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// Amount of orders to generate.
+    pub orders: usize,
+    pub arrivals: Arrivals,
+    /// Drinks ordered, each with its weight.
+    pub mix: Vec<(Drink, u32)>,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Workload {
+        Workload {
+            orders: 100,
+            arrivals: Arrivals::Poisson { per_minute: 4.0 },
+            mix: vec![
+                (Drink::ESPRESSO, 3),
+                (Drink::AMERICANO, 2),
+                (Drink::CAPPUCCINO, 3),
+                (Drink::LATTE, 2),
+            ],
+            seed: 0,
+        }
+    }
+}
+
+impl Workload {
+    /// Generates the orders, each with the time it arrives since the start.
+    /// The same settings always generate the same orders.
+    pub fn generate(&self) -> Vec<(Duration, Ingredients)> {
+        let mut rng = Rng::new(self.seed);
+        let total: u64 = self.mix.iter().map(|(_, weight)| *weight as u64).sum();
+        let mut at = 0.0;
+        let mut generated = Vec::with_capacity(self.orders);
+        for _ in 0..self.orders {
+            at = self.arrivals.next(at, &mut rng);
+            let mut pick = rng.next_u64() % total.max(1);
+            let drink = self
+                .mix
+                .iter()
+                .find(|(_, weight)| match pick.checked_sub(*weight as u64) {
+                    Some(rest) => {
+                        pick = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .map_or(Drink::ESPRESSO, |(drink, _)| *drink);
+            let arrival = Duration::from_millis(at.round() as u64);
+            generated.push((arrival, drink.pour(&mut rng)));
+        }
+        generated
+    }
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    /// Parses settings like `orders=500,rate=2,rush=30+60@10,mix=espresso:3/latte:1,seed=7`.
+    /// Rates are in orders per minute; a rush starts and lasts the given minutes.
+    /// Missing keys keep their default values.
+    fn from_str(s: &str) -> Result<Workload, String> {
+        let mut workload = Workload::default();
+        let (mut per_minute, mut rush) = (workload.arrivals.per_minute(), None);
+        let rate = |value: &str| match value.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+            _ => Err(format!("Invalid rate: {}", value)),
+        };
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("Invalid workload setting: {}", pair))?;
+            match key {
+                "orders" => {
+                    workload.orders = value
+                        .parse()
+                        .map_err(|_| format!("Invalid amount of orders: {}", value))?
+                }
+                "rate" => per_minute = rate(value)?,
+                "rush" => {
+                    let invalid = || format!("Invalid rush: {}", value);
+                    let (window, rush_rate) = value.split_once('@').ok_or_else(invalid)?;
+                    let (start, length) = window.split_once('+').ok_or_else(invalid)?;
+                    let minutes = |minutes: &str| match minutes.parse::<f64>() {
+                        Ok(minutes) if minutes >= 0.0 && minutes.is_finite() => {
+                            Ok(Duration::from_secs_f64(minutes * 60.0))
+                        }
+                        _ => Err(invalid()),
+                    };
+                    rush = Some((minutes(start)?, minutes(length)?, rate(rush_rate)?));
+                }
+                "mix" => {
+                    workload.mix = value
+                        .split('/')
+                        .map(|entry| {
+                            let (name, weight) = entry.split_once(':').unwrap_or((entry, "1"));
+                            let drink =
+                                Drink::by_name(name).ok_or(format!("Unknown drink: {}", name))?;
+                            let weight = weight
+                                .parse()
+                                .map_err(|_| format!("Invalid weight: {}", weight))?;
+                            Ok((drink, weight))
+                        })
+                        .collect::<Result<_, String>>()?;
+                    if workload.mix.iter().all(|(_, weight)| *weight == 0) {
+                        return Err("The mix must have some drink".to_string());
+                    }
+                }
+                "seed" => {
+                    workload.seed = value
+                        .parse()
+                        .map_err(|_| format!("Invalid seed: {}", value))?
+                }
+                _ => return Err(format!("Unknown workload setting: {}", key)),
+            }
+        }
+        workload.arrivals = match rush {
+            None => Arrivals::Poisson { per_minute },
+            Some((start, length, rush_per_minute)) => Arrivals::Rush {
+                per_minute,
+                rush_per_minute,
+                start,
+                length,
+            },
+        };
+        Ok(workload)
+    }
+}
+
+impl fmt::Display for Workload {
+    /// Displays the settings in the same format they are parsed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "orders={},", self.orders)?;
+        match self.arrivals {
+            Arrivals::Poisson { per_minute } => write!(f, "rate={},", per_minute)?,
+            Arrivals::Rush {
+                per_minute,
+                rush_per_minute,
+                start,
+                length,
+            } => write!(
+                f,
+                "rate={},rush={}+{}@{},",
+                per_minute,
+                start.as_secs_f64() / 60.0,
+                length.as_secs_f64() / 60.0,
+                rush_per_minute
+            )?,
+        }
+        let mix: Vec<String> = self
+            .mix
+            .iter()
+            .map(|(drink, weight)| format!("{}:{}", drink.name, weight))
+            .collect();
+        write!(f, "mix={},seed={}", mix.join("/"), self.seed)
+    }
+}
+
+/// Writes the orders in the format of the orders file, with a header line and the time each
+/// order arrives.
+pub fn to_csv(orders: &[(Duration, Ingredients)]) -> String {
+    let mut csv = "arrival (ms),coffee (mg),water (ml),foam (ml)\n".to_string();
+    for (at, ingredients) in orders {
+        csv += &format!(
+            "@{},{},{},{}\n",
+            at.as_millis(),
+            ingredients.coffee,
+            ingredients.water,
+            ingredients.foam
+        );
+    }
+    csv
+}
+
+/// Puts the orders into the queue as they arrive, following the clock made `speedup` times faster.
+/// Like `take_orders_from`, doesn't push `NoMoreOrders` when finished.
+/// Returns a handle to the thread that is feeding the orders.
+pub fn feed(
+    orders: Vec<(Duration, Ingredients)>,
+    queue: Arc<Orders>,
+    speedup: u32,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let start = Instant::now();
+        for (at, ingredients) in orders {
+            wait_for_arrival(start, at, speedup);
+            // rejected orders are counted by the queue
+            let _ = queue.try_push(Order::Order(ingredients));
+        }
+    })
+}
+
+#[cfg(test)]
+mod workload_tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_orders() {
+        let workload = Workload {
+            orders: 200,
+            ..Workload::default()
+        };
+        let orders = workload.generate();
+        assert_eq!(orders, workload.generate());
+        assert_eq!(orders.len(), 200);
+        assert!(orders.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        let other = Workload {
+            seed: 1,
+            ..workload.clone()
+        };
+        assert_ne!(orders, other.generate());
+    }
+
+    #[test]
+    fn drinks_follow_the_mix() {
+        let workload = Workload {
+            orders: 500,
+            mix: vec![(Drink::LATTE, 1), (Drink::ESPRESSO, 0)],
+            ..Workload::default()
+        };
+        for (_, ingredients) in workload.generate() {
+            assert!((20..=40).contains(&ingredients.coffee));
+            assert!((100..=200).contains(&ingredients.water));
+            assert!((100..=200).contains(&ingredients.foam));
+        }
+    }
+
+    #[test]
+    fn rush_brings_more_orders() {
+        let workload: Workload = "orders=2000,rate=1,rush=60+60@20,seed=3".parse().unwrap();
+        let orders = workload.generate();
+        let within = |from: u64, to: u64| {
+            orders
+                .iter()
+                .filter(|(at, _)| (from * 60..to * 60).contains(&at.as_secs()))
+                .count()
+        };
+        // about 60 orders an hour before the rush and 1200 during it
+        assert!((30..=90).contains(&within(0, 60)));
+        assert!((1000..=1400).contains(&within(60, 120)));
+    }
+
+    #[test]
+    fn parse_workload() {
+        let text = "orders=50,rate=2.5,rush=30+15@12,mix=espresso:3/latte:1,seed=7";
+        let workload: Workload = text.parse().unwrap();
+        assert_eq!(workload.orders, 50);
+        assert_eq!(
+            workload.arrivals,
+            Arrivals::Rush {
+                per_minute: 2.5,
+                rush_per_minute: 12.0,
+                start: Duration::from_secs(30 * 60),
+                length: Duration::from_secs(15 * 60),
+            }
+        );
+        assert_eq!(workload.mix, vec![(Drink::ESPRESSO, 3), (Drink::LATTE, 1)]);
+        assert_eq!(workload.to_string(), text);
+        assert_eq!("".parse::<Workload>().unwrap(), Workload::default());
+        let seeded: Workload = "seed=1".parse().unwrap();
+        assert_eq!(seeded.arrivals, Workload::default().arrivals);
+
+        for invalid in ["rate=0", "rush=30@2", "mix=mocha", "mix=latte:0", "size=2"] {
+            assert!(invalid.parse::<Workload>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn writes_the_orders_file_format() {
+        let orders = vec![
+            (Duration::ZERO, Drink::ESPRESSO.pour(&mut Rng::new(0))),
+            (Duration::from_secs(1), Drink::LATTE.pour(&mut Rng::new(0))),
+        ];
+        let csv = to_csv(&orders);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let (_, latte) = orders[1];
+        assert_eq!(
+            lines[2],
+            format!("@1000,{},{},{}", latte.coffee, latte.water, latte.foam)
+        );
+    }
+
+    #[test]
+    fn feeds_the_queue() {
+        let orders = Workload {
+            orders: 10,
+            ..Workload::default()
+        }
+        .generate();
+        let queue = Orders::new();
+        feed(orders, queue.clone(), 1).join().unwrap();
+        assert_eq!(queue.len(), 10);
+    }
+}
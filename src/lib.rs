@@ -0,0 +1,9 @@
+//! Simulation of a multi-nozzle coffee maker.
+//!
+//! Build one with `CoffeeMakerBuilder`, then `run` it or `start` it and `shutdown` it later.
+
+pub mod coffee_maker;
+
+pub use coffee_maker::{
+    builder::CoffeeMakerBuilder, machine::CoffeeMaker, report::Report, OrderSource,
+};
@@ -1,41 +1,349 @@
-mod coffee_maker;
-use std::{sync::atomic::Ordering, thread::JoinHandle};
+use std::{
+    io::{self, BufRead},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use coffee_maker::{config, orders::Orders, spawn_dispenser, take_orders, Resources};
+use tp1::{
+    coffee_maker::{
+        alerts::{Alert, FileSink},
+        bench::{self, BenchConfig},
+        capabilities::Capabilities,
+        event_log::EventLog,
+        fleet::Fleet,
+        machine::CoffeeMaker,
+        metrics::{Metrics, MetricsServer},
+        observer::Observer,
+        orders::Orders,
+        planning::{self, Prices, SearchSpace, Sla},
+        pool::DispenserPool,
+        read_orders_file, read_timed_orders_file, replay,
+        resources_monitor::MonitorMode,
+        simulation::{self, SimulationConfig},
+        take_orders,
+        trace::Trace,
+        workload::{self, Workload},
+    },
+    CoffeeMakerBuilder,
+};
 
-pub const DISPENSERS: u16 = 3;
+const ORDERS_FILE: &str = "./assets/orders.csv";
 
-const INITIAL_COFFEE: u32 = config::C;
-const INITIAL_COFFEE_BEANS: u32 = config::G;
-const INITIAL_FOAM: u32 = config::E;
-const INITIAL_MILK: u32 = config::L;
+const MONITOR_INTERVAL: u64 = 300;
 
-fn main() {
-    let orders = Orders::new();
-    let order_taker_handle = take_orders(String::from("./assets/orders.csv"), orders.clone())
-        .expect("Failed open orders");
+const BENCH_SPEEDUP: u32 = 10;
+
+/// Returns the value of the `<name> <value>` argument, if any.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name).skip(1);
+    args.next()
+}
+
+/// Returns whether the `<name>` flag was given.
+fn has_flag(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+/// Returns how long customers wait for their orders, from the `--patience <ms>` argument, if any.
+fn patience() -> Option<Duration> {
+    arg_value("--patience")
+        .map(|millis| Duration::from_millis(millis.parse().expect("Invalid patience")))
+}
+
+/// Returns the path of the orders file, `--orders <file>` or the default one.
+fn orders_file() -> String {
+    arg_value("--orders").unwrap_or(ORDERS_FILE.to_string())
+}
+
+/// Returns the path of the `<name> <file>` argument for the given coffee maker, if any.
+/// Each coffee maker of a fleet gets its own file.
+fn output_path(name: &str, machine: &str) -> Option<String> {
+    let path = arg_value(name)?;
+    match arg_value("--machines") {
+        Some(_) => Some(format!("{}.{}", path, machine.replace(' ', "_"))),
+        None => Some(path),
+    }
+}
+
+/// Returns the mode of the monitor, from the `--monitor-mode <mode>` argument.
+fn monitor_mode() -> MonitorMode {
+    arg_value("--monitor-mode").map_or(MonitorMode::default(), |mode| {
+        mode.parse().expect("Invalid monitor mode")
+    })
+}
+
+/// Prints when dispensers start, drain and stop, and when the state can't be saved.
+struct ConsoleLog;
+
+impl Observer for ConsoleLog {
+    fn dispenser_started(&self, dispenser: usize, capabilities: Capabilities) {
+        println!("Dispenser {} ({}): started", dispenser, capabilities);
+    }
+
+    fn dispenser_draining(&self, dispenser: usize) {
+        println!("Dispenser {}: draining", dispenser);
+    }
 
-    let resources = Resources::new(
-        INITIAL_COFFEE,
-        INITIAL_COFFEE_BEANS,
-        INITIAL_FOAM,
-        INITIAL_MILK,
-    )
-    .expect("Failed to create resources");
-    let (monitor_handle, stop_monitor) = resources.monitor(300);
+    fn dispenser_stopped(&self, dispenser: usize) {
+        println!("Dispenser {}: stopped", dispenser);
+    }
 
-    let mut dispenser_handles: Vec<JoinHandle<()>> = Vec::new();
-    for _ in 0..DISPENSERS {
-        let handle = spawn_dispenser(orders.clone(), resources.clone());
-        dispenser_handles.push(handle);
+    fn state_not_saved(&self, path: &str, error: &io::Error) {
+        eprintln!("Failed to save the state to {}: {}", path, error);
     }
+}
+
+/// Creates a builder with the settings from the arguments:
+/// `--dispensers <list>`, `--faults <config>`, `--autoscale <policy>`, `--alerts <policy>`,
+/// `--patience <ms>`, `--alert-file <file>`, `--event-log <file>`, `--state <file>`,
+/// `--journal <file>` and `--trace <file>`.
+/// Traces are added to the given list, to be saved once run.
+fn builder(name: &str, traces: &mut Vec<(String, Arc<Trace>)>) -> CoffeeMakerBuilder {
+    let mut builder = CoffeeMakerBuilder::new()
+        .name(name)
+        .observer(Arc::new(ConsoleLog));
+    if let Some(list) = arg_value("--dispensers") {
+        let dispensers: Vec<Capabilities> = list
+            .split(',')
+            .map(|capabilities| {
+                capabilities
+                    .parse()
+                    .expect("Invalid dispenser capabilities")
+            })
+            .collect();
+        builder = builder.dispensers(dispensers);
+    }
+    if let Some(config) = arg_value("--faults") {
+        builder = builder.faults(config.parse().expect("Invalid fault config"));
+    }
+    if let Some(policy) = arg_value("--autoscale") {
+        builder = builder.autoscale(policy.parse().expect("Invalid scaling policy"));
+    }
+    if let Some(patience) = patience() {
+        builder = builder.patience(patience);
+    }
+    if let Some(policy) = arg_value("--alerts") {
+        builder = builder
+            .alerts(policy.parse().expect("Invalid alert policy"))
+            // on the standard error, so they aren't cleared by the monitor
+            .alert_sink(Arc::new(|alert: &Alert| eprintln!("{}", alert)));
+    }
+    if let Some(path) = output_path("--alert-file", name) {
+        let sink = FileSink::open(&path).expect("Failed to open alert file");
+        builder = builder.alert_sink(Arc::new(sink));
+    }
+    if let Some(path) = output_path("--event-log", name) {
+        builder = builder.observer(EventLog::create(&path).expect("Failed to create event log"));
+    }
+    if let Some(path) = output_path("--state", name) {
+        builder = builder.state_file(&path);
+    }
+    if let Some(path) = output_path("--journal", name) {
+        builder = builder.journal(&path);
+    }
+    if let Some(path) = output_path("--trace", name) {
+        let trace = Trace::new();
+        builder = builder.observer(trace.clone());
+        traces.push((path, trace));
+    }
+    builder
+}
+
+/// Starts serving the metrics of the coffee makers on the `--metrics <port>` argument, if any.
+fn serve_metrics(machines: &[CoffeeMaker]) -> Option<MetricsServer> {
+    let port = arg_value("--metrics")?
+        .parse()
+        .expect("Invalid metrics port");
+    let metrics = machines
+        .iter()
+        .map(|machine| {
+            let metrics = Metrics::new(machine.name(), machine.resources().clone());
+            machine.register_observer(metrics.clone());
+            metrics
+        })
+        .collect();
+    let server = MetricsServer::start(port, metrics).expect("Failed to serve metrics");
+    println!("Serving metrics at http://{}/metrics", server.address());
+    Some(server)
+}
+
+/// Saves the traces recorded while running.
+fn save_traces(traces: Vec<(String, Arc<Trace>)>) {
+    for (path, trace) in traces {
+        trace.save(&path).expect("Failed to save trace");
+    }
+}
+
+/// Executes the operator commands read from stdin on the pool, in a new thread.
+fn take_operator_commands(pool: Arc<DispenserPool>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            match pool.execute(&line) {
+                Ok(output) => println!("{}", output),
+                Err(err) => println!("Error: {}", err),
+            }
+        }
+    });
+}
+
+/// Runs a single coffee maker, showing the full screen dashboard with `--dashboard`
+/// instead of the resources monitor.
+fn run_coffee_maker() {
+    let mut traces = Vec::new();
+    let builder = builder("coffee maker", &mut traces).orders_file(&orders_file());
+    let builder = match has_flag("--dashboard") {
+        true => builder.dashboard(MONITOR_INTERVAL),
+        false => builder
+            .monitor(MONITOR_INTERVAL)
+            .monitor_mode(monitor_mode()),
+    };
+    let machine = builder
+        .sink(Box::new(io::stdout()))
+        .build()
+        .expect("Failed to create coffee maker");
+
+    let metrics_server = serve_metrics(std::slice::from_ref(&machine));
+
+    if has_flag("--operator") {
+        take_operator_commands(machine.pool().clone());
+    }
+    machine.run().expect("Failed open orders");
+    save_traces(traces);
+    if let Some(server) = metrics_server {
+        server.stop();
+    }
+}
+
+/// Runs several coffee makers sharing the orders.
+fn run_fleet(machines: usize) {
+    let mut traces = Vec::new();
+    let machines = (0..machines)
+        .map(|i| {
+            builder(&format!("coffee maker {}", i), &mut traces)
+                .build()
+                .expect("Failed to create coffee maker")
+        })
+        .collect();
+    let fleet = Fleet::new(machines);
+    let metrics_server = serve_metrics(fleet.machines());
+    let monitor = fleet.monitor(MONITOR_INTERVAL, monitor_mode());
+
+    let orders = Orders::new();
+    orders.set_patience(patience());
+    let order_taker_handle =
+        take_orders(orders_file(), orders.clone()).expect("Failed open orders");
+    let dispatcher_handle = fleet.dispatch(orders);
 
     order_taker_handle.join().expect("Order Taker Panicked");
+    dispatcher_handle.join().expect("Dispatcher Panicked");
+
+    monitor.stop();
 
-    for handle in dispenser_handles {
-        handle.join().expect("Dispenser Panicked");
+    println!("{}", fleet.stats());
+    for machine in fleet.machines() {
+        println!("[{}]\n{}", machine.name(), machine.report().latency);
     }
+    save_traces(traces);
+    if let Some(server) = metrics_server {
+        server.stop();
+    }
+}
 
-    stop_monitor.store(true, Ordering::Relaxed);
-    monitor_handle.join().expect("Monitor Panicked");
+/// Replays an event log, exiting with an error if it diverges.
+fn run_replay(path: &str) {
+    let events = replay::read_events(path).expect("Failed to read event log");
+    let replay = replay::replay(&events).expect("Failed to replay event log");
+    println!("{}", replay);
+    if replay.divergence.is_some() {
+        std::process::exit(1);
+    }
+}
+
+/// Benchmarks the orders with the configurations of `--bench-configs <list>`, separated by `;`,
+/// or the standard ones; with the clock `--speedup <n>` times faster.
+fn run_bench() {
+    let orders = read_orders_file(&orders_file()).expect("Failed open orders");
+    let configs = arg_value("--bench-configs").map_or(BenchConfig::standard(), |list| {
+        list.split(';')
+            .map(|config| config.parse().expect("Invalid bench config"))
+            .collect()
+    });
+    let speedup = arg_value("--speedup").map_or(BENCH_SPEEDUP, |speedup| {
+        speedup.parse().expect("Invalid speedup")
+    });
+    print!("{}", bench::compare(&configs, &orders, speedup));
+}
+
+/// Simulation configuration of `--sim-config <config>`, in the format of the bench
+/// configurations, or the default one; with the `--patience <ms>` of the customers.
+fn simulation_config() -> SimulationConfig {
+    let config: BenchConfig = arg_value("--sim-config").map_or(BenchConfig::default(), |config| {
+        config.parse().expect("Invalid simulation config")
+    });
+    SimulationConfig {
+        patience: patience(),
+        ..SimulationConfig::from(config)
+    }
+}
+
+/// Simulates the orders with the simulation configuration.
+fn run_simulation() {
+    let orders = read_timed_orders_file(&orders_file()).expect("Failed open orders");
+    print!("{}", simulation::simulate(simulation_config(), &orders));
+}
+
+/// Searches the configurations of `--plan-space <space>` for the cheapest one meeting the
+/// `--sla <sla>` with the orders, at the `--prices <prices>`, or the default ones.
+/// The locks, refill policy and patience are those of the simulation configuration.
+fn run_plan() {
+    let orders = read_timed_orders_file(&orders_file()).expect("Failed open orders");
+    let space: SearchSpace = arg_value("--plan-space").map_or(SearchSpace::default(), |space| {
+        space.parse().expect("Invalid search space")
+    });
+    let prices: Prices = arg_value("--prices").map_or(Prices::default(), |prices| {
+        prices.parse().expect("Invalid prices")
+    });
+    let sla: Sla =
+        arg_value("--sla").map_or(Sla::default(), |sla| sla.parse().expect("Invalid SLA"));
+    let plan = planning::plan(&orders, &simulation_config(), &space, &prices, sla);
+    print!("{}", plan);
+}
+
+/// Generates orders with the settings of `--generate <workload>` and writes them in the format
+/// of the orders file, to `--output <file>` or the standard output.
+fn run_generate(settings: &str) {
+    let workload: Workload = settings.parse().expect("Invalid workload");
+    let csv = workload::to_csv(&workload.generate());
+    match arg_value("--output") {
+        Some(path) => std::fs::write(path, csv).expect("Failed to write orders"),
+        None => print!("{}", csv),
+    }
+}
+
+fn main() {
+    if let Some(path) = arg_value("--replay") {
+        run_replay(&path);
+        return;
+    }
+    if has_flag("--bench") {
+        run_bench();
+        return;
+    }
+    if let Some(settings) = arg_value("--generate") {
+        run_generate(&settings);
+        return;
+    }
+    if has_flag("--simulate") {
+        run_simulation();
+        return;
+    }
+    if has_flag("--plan") {
+        run_plan();
+        return;
+    }
+    match arg_value("--machines") {
+        Some(machines) => run_fleet(machines.parse().expect("Invalid amount of machines")),
+        None => run_coffee_maker(),
+    }
 }